@@ -0,0 +1,21 @@
+-- This file should undo anything in `up.sql`
+
+DROP PROCEDURE IF EXISTS rublic_restore_initial_tables;
+
+CREATE PROCEDURE rublic_restore_initial_tables()
+BEGIN
+    IF DATABASE() <> 'rublic'
+        AND NOT EXISTS (SELECT 1 FROM information_schema.tables WHERE table_schema = 'rublic' AND table_name = 'domains') THEN
+        RENAME TABLE
+            domains TO rublic.domains,
+            users TO rublic.users,
+            `groups` TO rublic.`groups`,
+            domain_group_mappings TO rublic.domain_group_mappings,
+            user_group_mappings TO rublic.user_group_mappings,
+            certificates TO rublic.certificates;
+    END IF;
+END;
+
+CALL rublic_restore_initial_tables();
+
+DROP PROCEDURE rublic_restore_initial_tables;
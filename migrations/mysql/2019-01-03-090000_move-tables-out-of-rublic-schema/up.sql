@@ -0,0 +1,25 @@
+-- Your SQL goes here
+
+-- The first two migrations created their tables in a database literally named rublic, rather
+-- than in whichever one DATABASE_URL points at. Unless that happens to be rublic, the tables
+-- are moved over, as every later migration and query expects them there
+DROP PROCEDURE IF EXISTS rublic_move_initial_tables;
+
+CREATE PROCEDURE rublic_move_initial_tables()
+BEGIN
+    IF DATABASE() <> 'rublic'
+        AND EXISTS (SELECT 1 FROM information_schema.tables WHERE table_schema = 'rublic' AND table_name = 'domains')
+        AND NOT EXISTS (SELECT 1 FROM information_schema.tables WHERE table_schema = DATABASE() AND table_name = 'domains') THEN
+        RENAME TABLE
+            rublic.domains TO domains,
+            rublic.users TO users,
+            rublic.groups TO `groups`,
+            rublic.domain_group_mappings TO domain_group_mappings,
+            rublic.user_group_mappings TO user_group_mappings,
+            rublic.certificates TO certificates;
+    END IF;
+END;
+
+CALL rublic_move_initial_tables();
+
+DROP PROCEDURE rublic_move_initial_tables;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS domain_aliases (
+    fqdn VARCHAR(256) NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_fqdn CHAR(64) NOT NULL,
+    domain_id CHAR(36) NOT NULL,
+    CONSTRAINT domain_aliases_PK PRIMARY KEY (hashed_fqdn),
+    CONSTRAINT domain_aliases_domain_FK FOREIGN KEY (domain_id) REFERENCES domains(id) ON DELETE CASCADE
+)
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Domains whose archive directory has disappeared are kept around as history
+ALTER TABLE domains ADD COLUMN archived BOOLEAN NOT NULL DEFAULT FALSE
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE certificates
+    DROP COLUMN modified_at,
+    DROP COLUMN hash;
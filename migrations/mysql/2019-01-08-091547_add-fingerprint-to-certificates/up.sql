@@ -0,0 +1,6 @@
+-- Your SQL goes here
+
+-- Used by the reconciler to tell whether a file has changed since it was ingested
+ALTER TABLE certificates
+    ADD COLUMN modified_at DATETIME NULL,
+    ADD COLUMN hash CHAR(64) NULL
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- The version each of certbot's live/ symlinks points at. Keyed by the name of the
+-- live directory rather than the domain, as it may be seen before the archive is
+CREATE TABLE IF NOT EXISTS live_versions (
+    fqdn VARCHAR(256) NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_fqdn CHAR(64) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    version INT NOT NULL,
+    CONSTRAINT live_versions_PK PRIMARY KEY (hashed_fqdn, friendly_name)
+)
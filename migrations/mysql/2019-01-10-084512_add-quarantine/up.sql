@@ -0,0 +1,15 @@
+-- Your SQL goes here
+
+-- Files which failed to be ingested, until they are ingested successfully or disappear
+CREATE TABLE IF NOT EXISTS quarantine (
+    path MEDIUMTEXT NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_path CHAR(64) NOT NULL,
+    fqdn VARCHAR(256) NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    message TEXT NOT NULL,
+    -- Hash of the contents which failed, so unchanged files aren't retried over and over
+    hash CHAR(64) NULL,
+    occurred_at DATETIME NOT NULL,
+    CONSTRAINT quarantine_PK PRIMARY KEY (hashed_path)
+)
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS group_roles;
+DROP TABLE IF EXISTS user_roles;
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Administrative roles, held by users directly or through any of their groups
+CREATE TABLE IF NOT EXISTS user_roles (
+    user_id CHAR(36) NOT NULL,
+    role VARCHAR(64) NOT NULL,
+    CONSTRAINT user_roles_PK PRIMARY KEY (user_id, role),
+    CONSTRAINT user_roles_user_FK FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS group_roles (
+    group_id CHAR(36) NOT NULL,
+    role VARCHAR(64) NOT NULL,
+    CONSTRAINT group_roles_PK PRIMARY KEY (group_id, role),
+    CONSTRAINT group_roles_group_FK FOREIGN KEY (group_id) REFERENCES `groups`(id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    ADD COLUMN hashed_key VARCHAR(256) NOT NULL DEFAULT '',
+    ADD COLUMN previous_hashed_key VARCHAR(256) NULL,
+    ADD COLUMN previous_key_expires_at DATETIME NULL;
+
+-- Only the default keys can be kept, every other key is lost
+UPDATE users SET
+    hashed_key = (SELECT hashed_key FROM api_keys WHERE api_keys.user_id = users.id AND label = 'default'),
+    previous_hashed_key = (SELECT previous_hashed_key FROM api_keys WHERE api_keys.user_id = users.id AND label = 'default'),
+    previous_key_expires_at = (SELECT previous_key_expires_at FROM api_keys WHERE api_keys.user_id = users.id AND label = 'default')
+WHERE EXISTS (SELECT 1 FROM api_keys WHERE api_keys.user_id = users.id AND label = 'default');
+
+DROP TABLE IF EXISTS api_keys;
@@ -0,0 +1,32 @@
+-- Your SQL goes here
+
+-- Users can hold any number of keys, each of which may expire and be
+-- restricted to some of the user's domains or permissions
+CREATE TABLE IF NOT EXISTS api_keys (
+    id CHAR(36) NOT NULL,
+    user_id CHAR(36) NOT NULL,
+    label VARCHAR(64) NOT NULL,
+    hashed_key VARCHAR(256) NOT NULL,
+    previous_hashed_key VARCHAR(256) NULL,
+    previous_key_expires_at DATETIME NULL,
+    created_at DATETIME NOT NULL,
+    expires_at DATETIME NULL,
+    last_used_at DATETIME NULL,
+    -- JSON arrays of the domains and permissions the key is restricted to, if any
+    scope_domains TEXT NULL,
+    scope_permissions TEXT NULL,
+    CONSTRAINT api_keys_PK PRIMARY KEY (id),
+    CONSTRAINT api_keys_label_UN UNIQUE (user_id, label),
+    CONSTRAINT api_keys_user_FK FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- The key every user had so far becomes their default one, which borrows
+-- the user's id since there's no portable way of generating a new one here
+INSERT INTO api_keys (id, user_id, label, hashed_key, previous_hashed_key, previous_key_expires_at, created_at)
+    SELECT id, id, 'default', hashed_key, previous_hashed_key, previous_key_expires_at, CURRENT_TIMESTAMP
+    FROM users;
+
+ALTER TABLE users
+    DROP COLUMN hashed_key,
+    DROP COLUMN previous_hashed_key,
+    DROP COLUMN previous_key_expires_at;
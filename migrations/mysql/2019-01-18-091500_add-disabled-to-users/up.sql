@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Disabled users keep their account, but can no longer log in or refresh their tokens
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
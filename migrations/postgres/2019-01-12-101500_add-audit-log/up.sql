@@ -0,0 +1,22 @@
+-- Your SQL goes here
+
+-- Certificate downloads and administrative changes, for answering who did what and when
+CREATE TABLE IF NOT EXISTS audit_log (
+    id BIGSERIAL NOT NULL,
+    occurred_at TIMESTAMP NOT NULL,
+    -- The user or token subject, if the request was authenticated at all
+    subject VARCHAR(256) NULL,
+    client_ip VARCHAR(64) NULL,
+    action VARCHAR(64) NOT NULL,
+    fqdn VARCHAR(256) NULL,
+    version INT NULL,
+    friendly_name VARCHAR(64) NULL,
+    is_private BOOLEAN NULL,
+    -- The user or group an administrative change was made to
+    target VARCHAR(256) NULL,
+    details TEXT NULL,
+    result VARCHAR(32) NOT NULL,
+    CONSTRAINT audit_log_PK PRIMARY KEY (id)
+);
+
+CREATE INDEX audit_log_occurred_at_IX ON audit_log (occurred_at);
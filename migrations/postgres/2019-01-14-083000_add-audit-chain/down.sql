@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS audit_checkpoints;
+
+ALTER TABLE audit_log
+    DROP COLUMN previous_hash,
+    DROP COLUMN hash;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+
+ALTER TABLE users
+    DROP COLUMN previous_hashed_key,
+    DROP COLUMN previous_key_expires_at;
@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS quarantine;
+DROP TABLE IF EXISTS live_versions;
+DROP TABLE IF EXISTS domain_aliases;
+DROP TABLE IF EXISTS certificates;
+DROP TABLE IF EXISTS domain_group_mappings;
+DROP TABLE IF EXISTS user_group_mappings;
+DROP TABLE IF EXISTS groups;
+DROP TABLE IF EXISTS domains;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,93 @@
+-- Your SQL goes here
+
+CREATE TABLE IF NOT EXISTS domains (
+    id CHAR(36) NOT NULL,
+    fqdn VARCHAR(256) NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_fqdn CHAR(64) NOT NULL,
+    -- Domains whose archive directory has disappeared are kept around as history
+    archived BOOLEAN NOT NULL DEFAULT FALSE,
+    CONSTRAINT domains_PK PRIMARY KEY (id),
+    CONSTRAINT domains_fqdn_UN UNIQUE (hashed_fqdn)
+);
+
+CREATE TABLE IF NOT EXISTS users (
+    id CHAR(36) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    hashed_key VARCHAR(256) NOT NULL,
+    CONSTRAINT users_PK PRIMARY KEY (id),
+    CONSTRAINT users_friendly_name_UN UNIQUE (friendly_name)
+);
+
+CREATE TABLE IF NOT EXISTS groups (
+    id CHAR(36) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    permission VARCHAR(256) NOT NULL,
+    CONSTRAINT groups_PK PRIMARY KEY (id),
+    CONSTRAINT groups_friendly_name_UN UNIQUE (friendly_name)
+);
+
+CREATE TABLE IF NOT EXISTS domain_group_mappings (
+    domain_id CHAR(36) NOT NULL,
+    group_id CHAR(36) NOT NULL,
+    CONSTRAINT domain_group_mappings_PK PRIMARY KEY (domain_id, group_id),
+    CONSTRAINT mapping_domain_FK FOREIGN KEY (domain_id) REFERENCES domains(id) ON DELETE CASCADE,
+    CONSTRAINT mapping_domain_group_FK FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS user_group_mappings (
+    user_id CHAR(36) NOT NULL,
+    group_id CHAR(36) NOT NULL,
+    CONSTRAINT user_group_mappings_PK PRIMARY KEY (user_id, group_id),
+    CONSTRAINT mapping_users_FK FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+    CONSTRAINT mapping_users_group_FK FOREIGN KEY (group_id) REFERENCES groups(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS certificates (
+    id INT NOT NULL,
+    domain_id CHAR(36) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    path TEXT NOT NULL,
+    is_private BOOLEAN NOT NULL,
+    not_before TIMESTAMP NULL,
+    not_after TIMESTAMP NULL,
+    -- Used by the reconciler to tell whether a file has changed since it was ingested
+    modified_at TIMESTAMP NULL,
+    hash CHAR(64) NULL,
+    CONSTRAINT certificates_PK PRIMARY KEY (domain_id, id, friendly_name),
+    CONSTRAINT certificates_domain_FK FOREIGN KEY (domain_id) REFERENCES domains(id) ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS domain_aliases (
+    fqdn VARCHAR(256) NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_fqdn CHAR(64) NOT NULL,
+    domain_id CHAR(36) NOT NULL,
+    CONSTRAINT domain_aliases_PK PRIMARY KEY (hashed_fqdn),
+    CONSTRAINT domain_aliases_domain_FK FOREIGN KEY (domain_id) REFERENCES domains(id) ON DELETE CASCADE
+);
+
+-- The version each of certbot's live/ symlinks points at. Keyed by the name of the
+-- live directory rather than the domain, as it may be seen before the archive is
+CREATE TABLE IF NOT EXISTS live_versions (
+    fqdn VARCHAR(256) NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_fqdn CHAR(64) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    version INT NOT NULL,
+    CONSTRAINT live_versions_PK PRIMARY KEY (hashed_fqdn, friendly_name)
+);
+
+-- Files which failed to be ingested, until they are ingested successfully or disappear
+CREATE TABLE IF NOT EXISTS quarantine (
+    path TEXT NOT NULL,
+    -- This hash is used for checking uniqueness
+    hashed_path CHAR(64) NOT NULL,
+    fqdn VARCHAR(256) NOT NULL,
+    kind VARCHAR(32) NOT NULL,
+    message TEXT NOT NULL,
+    -- Hash of the contents which failed, so unchanged files aren't retried over and over
+    hash CHAR(64) NULL,
+    occurred_at TIMESTAMP NOT NULL,
+    CONSTRAINT quarantine_PK PRIMARY KEY (hashed_path)
+);
@@ -0,0 +1,27 @@
+-- This file should undo anything in `up.sql`
+
+DROP TABLE IF EXISTS audit_checkpoints;
+
+-- SQLite can't drop columns, so the table is rebuilt without them
+CREATE TABLE audit_log_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    occurred_at TIMESTAMP NOT NULL,
+    subject VARCHAR(256) NULL,
+    client_ip VARCHAR(64) NULL,
+    action VARCHAR(64) NOT NULL,
+    fqdn VARCHAR(256) NULL,
+    version INT NULL,
+    friendly_name VARCHAR(64) NULL,
+    is_private BOOLEAN NULL,
+    target VARCHAR(256) NULL,
+    details TEXT NULL,
+    result VARCHAR(32) NOT NULL
+);
+
+INSERT INTO audit_log_new (id, occurred_at, subject, client_ip, action, fqdn, version, friendly_name, is_private, target, details, result)
+    SELECT id, occurred_at, subject, client_ip, action, fqdn, version, friendly_name, is_private, target, details, result FROM audit_log;
+
+DROP TABLE audit_log;
+ALTER TABLE audit_log_new RENAME TO audit_log;
+
+CREATE INDEX audit_log_occurred_at_IX ON audit_log (occurred_at);
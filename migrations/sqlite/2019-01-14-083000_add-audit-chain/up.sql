@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- Every entry includes the hash of the one before it, so changing, removing
+-- or reordering entries breaks the chain. Entries recorded before are left unchained
+ALTER TABLE audit_log ADD COLUMN previous_hash CHAR(64) NULL;
+ALTER TABLE audit_log ADD COLUMN hash CHAR(64) NULL;
+
+-- The chain head at some point in time, signed so that truncating or
+-- rewriting the whole log since can be told from a copy kept elsewhere
+CREATE TABLE IF NOT EXISTS audit_checkpoints (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    entry_id BIGINT NOT NULL,
+    entry_hash CHAR(64) NOT NULL,
+    public_key CHAR(64) NOT NULL,
+    signature CHAR(128) NOT NULL
+);
@@ -0,0 +1,16 @@
+-- This file should undo anything in `up.sql`
+
+-- SQLite can't drop columns, so the table is rebuilt without them
+CREATE TABLE users_new (
+    id CHAR(36) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    hashed_key VARCHAR(256) NOT NULL,
+    CONSTRAINT users_PK PRIMARY KEY (id),
+    CONSTRAINT users_friendly_name_UN UNIQUE (friendly_name)
+);
+
+INSERT INTO users_new (id, friendly_name, hashed_key)
+    SELECT id, friendly_name, hashed_key FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
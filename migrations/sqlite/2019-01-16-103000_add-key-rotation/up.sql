@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- The key a user had before it was rotated, which keeps working until it expires
+ALTER TABLE users ADD COLUMN previous_hashed_key VARCHAR(256) NULL;
+ALTER TABLE users ADD COLUMN previous_key_expires_at TIMESTAMP NULL;
@@ -0,0 +1,15 @@
+-- This file should undo anything in `up.sql`
+
+-- SQLite can't drop columns, so the table is rebuilt without it
+CREATE TABLE users_new (
+    id CHAR(36) NOT NULL,
+    friendly_name VARCHAR(64) NOT NULL,
+    CONSTRAINT users_PK PRIMARY KEY (id),
+    CONSTRAINT users_friendly_name_UN UNIQUE (friendly_name)
+);
+
+INSERT INTO users_new (id, friendly_name)
+    SELECT id, friendly_name FROM users;
+
+DROP TABLE users;
+ALTER TABLE users_new RENAME TO users;
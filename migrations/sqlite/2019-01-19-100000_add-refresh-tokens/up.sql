@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Every refresh token handed out, by the jti it carries, so it can be revoked
+-- before it expires. Subjects are user ids, or admin for the bootstrap administrator.
+-- The key a token was issued for decides what it may still be used for, and
+-- revoking the key revokes it. The bootstrap administrator has no key
+CREATE TABLE IF NOT EXISTS refresh_tokens (
+    jti CHAR(36) NOT NULL,
+    subject VARCHAR(64) NOT NULL,
+    issued_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    revoked_at TIMESTAMP NULL,
+    key_id CHAR(36) NULL,
+    CONSTRAINT refresh_tokens_PK PRIMARY KEY (jti)
+);
+
+CREATE INDEX refresh_tokens_subject_IX ON refresh_tokens (subject);
+CREATE INDEX refresh_tokens_key_IX ON refresh_tokens (key_id);
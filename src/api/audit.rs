@@ -0,0 +1,274 @@
+use std::net::IpAddr;
+use actix::Addr;
+use actix_web::{State, http::Method, Scope, HttpRequest, HttpResponse, FutureResponse, Query, AsyncResponder};
+use futures::future::{result, Future};
+use chrono::Utc;
+use crate::app::AppState;
+use crate::errors::ServiceError;
+use crate::database::DbExecutor;
+use crate::database::messages::*;
+use crate::database::models::{self, NewAuditEntry, AuditFilter};
+use crate::auditor::messages::Checkpoint;
+use crate::config::{TRUSTED_PROXIES, audit_trusted_keys};
+use crate::authorization::ResourceAuthorization;
+use crate::authorization::permissions::AUDIT_READ;
+use crate::authorization::models::Identity;
+use super::{make_result, ResultType};
+use super::pagination::{log_query, log_page};
+use super::models::*;
+
+pub fn register(router: Scope<AppState>) -> Scope<AppState> {
+    router
+        .authorize_resource("*", AUDIT_READ)
+        .resource("/verify", |r| {
+            r.method(Method::GET).with_async(api_verify_audit_log);
+        })
+        .resource("/checkpoints", |r| {
+            r.method(Method::GET).with_async(api_get_audit_checkpoints);
+            r.method(Method::POST).with_async(api_create_audit_checkpoint);
+        })
+        .resource("", |r| {
+            r.method(Method::GET).with_async(api_get_audit_log);
+        })
+}
+
+impl From<models::AuditCheckpoint> for AuditCheckpoint {
+    fn from(checkpoint: models::AuditCheckpoint) -> Self {
+        AuditCheckpoint {
+            message: checkpoint.message(),
+            id: checkpoint.id,
+            created_at: checkpoint.created_at,
+            entry_id: checkpoint.entry_id,
+            entry_hash: checkpoint.entry_hash,
+            public_key: checkpoint.public_key,
+            signature: checkpoint.signature
+        }
+    }
+}
+
+fn api_get_audit_log((state, query): (State<AppState>, Query<AuditQuery>))
+    -> FutureResponse<HttpResponse> {
+
+    let query = query.into_inner();
+
+    result(log_query(query.cursor.clone(), query.limit))
+        .and_then(move |(before, limit)| {
+            state.db
+                .send(GetAuditLog {
+                    filter: AuditFilter {
+                        subject: query.subject,
+                        fqdn: query.fqdn,
+                        action: query.action,
+                        result: query.result,
+                        since: query.since,
+                        until: query.until
+                    },
+                    before,
+                    limit
+                }).flatten().from_err()
+                .and_then(move |entries| Ok(log_page(entries, limit, |entry| entry.id, |entry| AuditEntry {
+                    id: entry.id,
+                    occurred_at: entry.occurred_at,
+                    subject: entry.subject,
+                    client_ip: entry.client_ip,
+                    action: entry.action,
+                    fqdn: entry.fqdn,
+                    version: entry.version,
+                    friendly_name: entry.friendly_name,
+                    is_private: entry.is_private,
+                    target: entry.target,
+                    details: entry.details,
+                    result: entry.result,
+                    previous_hash: entry.previous_hash,
+                    hash: entry.hash
+                })))
+        })
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_verify_audit_log(state: State<AppState>)
+    -> FutureResponse<HttpResponse> {
+
+    state.db
+        .send(VerifyAuditChain { trusted_keys: audit_trusted_keys() }).flatten().from_err()
+        .and_then(|verification| Ok(AuditVerification {
+            intact: verification.first_break.is_none(),
+            entries: verification.entries,
+            unchained: verification.unchained,
+            checkpoints: verification.checkpoints,
+            head: verification.head,
+            first_break: verification.first_break.map(|broken| AuditChainBreak {
+                id: broken.id,
+                reason: broken.reason
+            })
+        }))
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_get_audit_checkpoints((state, query): (State<AppState>, Query<PageQuery>))
+    -> FutureResponse<HttpResponse> {
+
+    let query = query.into_inner();
+
+    result(log_query(query.cursor, query.limit))
+        .and_then(move |(before, limit)| {
+            state.db
+                .send(GetAuditCheckpoints { before, limit }).flatten().from_err()
+                .and_then(move |checkpoints| Ok(log_page(checkpoints, limit, |checkpoint| checkpoint.id, AuditCheckpoint::from)))
+        })
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_create_audit_checkpoint(state: State<AppState>)
+    -> FutureResponse<HttpResponse> {
+
+    state.auditor
+        .send(Checkpoint {}).flatten().from_err()
+        .and_then(|checkpoint| Ok(checkpoint.map(AuditCheckpoint::from)))
+        .then(make_result(ResultType::Created)).responder()
+}
+
+// The address a request came from. Behind a trusted proxy, that's the last one in
+// X-Forwarded-For which wasn't added by one of the trusted proxies themselves
+fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpAddr]) -> IpAddr {
+    if !trusted.contains(&peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+
+    for hop in forwarded_for.unwrap_or("").rsplit(',') {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            },
+            // Whatever comes before a malformed entry can't be relied on
+            Err(_) => break
+        }
+    }
+
+    client
+}
+
+fn outcome<T>(result: &Result<T, ServiceError>) -> String {
+    match result {
+        Ok(_) => "success",
+        Err(ServiceError::Unauthorized) => "denied",
+        Err(ServiceError::NotFound(_)) => "not_found",
+        Err(_) => "failed"
+    }.into()
+}
+
+fn record(db: Addr<DbExecutor>, entry: NewAuditEntry) -> impl Future<Item = (), Error = ServiceError> {
+    db.send(RecordAudit { entry }).flatten()
+        .map_err(|e| {
+            error!("unable to record audit entry: {}", e);
+            ServiceError::InternalServerError
+        })
+}
+
+// An audit entry in the making, recorded along with the outcome of whatever it's about
+pub struct Audit {
+    db: Addr<DbExecutor>,
+    entry: NewAuditEntry
+}
+
+// Starts an audit entry for the given action, attributed to whoever made the request
+pub fn audit(req: &HttpRequest<AppState>, action: &str) -> Audit {
+    let forwarded_for = req.headers().get("X-Forwarded-For").and_then(|value| value.to_str().ok());
+
+    Audit {
+        db: req.state().db.clone(),
+        entry: NewAuditEntry {
+            occurred_at: Utc::now().naive_utc(),
+            subject: req.extensions().get::<Identity>().map(|identity| identity.0.clone()),
+            client_ip: req.peer_addr().map(|addr| client_ip(addr.ip(), forwarded_for, &TRUSTED_PROXIES).to_string()),
+            action: action.into(),
+            fqdn: None,
+            version: None,
+            friendly_name: None,
+            is_private: None,
+            target: None,
+            details: None,
+            result: String::new(),
+            previous_hash: None,
+            hash: None
+        }
+    }
+}
+
+impl Audit {
+    pub fn fqdn<S: Into<Option<String>>>(mut self, fqdn: S) -> Self {
+        self.entry.fqdn = fqdn.into();
+        self
+    }
+
+    pub fn version(mut self, version: Option<i32>) -> Self {
+        self.entry.version = version;
+        self
+    }
+
+    pub fn friendly_name<S: Into<Option<String>>>(mut self, friendly_name: S) -> Self {
+        self.entry.friendly_name = friendly_name.into();
+        self
+    }
+
+    pub fn private(mut self, is_private: bool) -> Self {
+        self.entry.is_private = Some(is_private);
+        self
+    }
+
+    pub fn target<S: Into<Option<String>>>(mut self, target: S) -> Self {
+        self.entry.target = target.into();
+        self
+    }
+
+    pub fn details<S: Into<Option<String>>>(mut self, details: S) -> Self {
+        self.entry.details = details.into();
+        self
+    }
+
+    // Records the outcome of an administrative change once it has been made. The
+    // change has already happened by then, so failing to record it is only logged
+    pub fn change<T, F>(self, change: F) -> impl Future<Item = T, Error = ServiceError>
+        where F: Future<Item = T, Error = ServiceError> {
+
+        change.then(move |result| {
+            record(self.db, NewAuditEntry { result: outcome(&result), ..self.entry })
+                .then(move |_| result)
+        })
+    }
+
+    // Records an attempt to get at something, which is only handed out once it's on record
+    pub fn access<T>(self, result: Result<T, ServiceError>) -> impl Future<Item = T, Error = ServiceError> {
+        record(self.db, NewAuditEntry { result: outcome(&result), ..self.entry })
+            .and_then(move |_| result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use super::client_ip;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_addresses_are_only_believed_from_trusted_proxies() {
+        let proxy = ip("10.0.0.1");
+        let other = ip("192.0.2.7");
+        let trusted = vec![proxy, ip("10.0.0.2")];
+
+        assert_eq!(client_ip(other, Some("203.0.113.5"), &trusted), other);
+        assert_eq!(client_ip(proxy, None, &trusted), proxy);
+        assert_eq!(client_ip(proxy, Some("203.0.113.5"), &trusted), ip("203.0.113.5"));
+        // A client can put anything in front, only what the proxies added counts
+        assert_eq!(client_ip(proxy, Some("198.51.100.1, 203.0.113.5, 10.0.0.2"), &trusted), ip("203.0.113.5"));
+        assert_eq!(client_ip(proxy, Some("bogus, 10.0.0.2"), &trusted), ip("10.0.0.2"));
+    }
+}
@@ -1,4 +1,4 @@
-use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, Query, AsyncResponder};
+use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, Query, Form, AsyncResponder};
 use futures::future::Future;
 use crate::errors::ServiceError;
 use crate::app::AppState;
@@ -12,6 +12,9 @@ pub fn register(router: Scope<AppState>) -> Scope<AppState> {
         .resource("/token", |r| {
             r.method(Method::POST).with_async(api_password_grant_query);
         })
+        .resource("/revoke", |r| {
+            r.method(Method::POST).with_async(api_revoke_token);
+        })
 }
 
 fn api_password_grant_query((state, grant): (State<AppState>, Query<Grant>))
@@ -44,14 +47,20 @@ fn password_grant(state: State<AppState>, username: String, password: String)
 
     state.authman.clone()
         .send(AuthorizeUser{ friendly_name: username, password }).flatten()
-        .and_then(move |claims| {
+        .and_then(move |authorization| {
             state.authman.clone().send(BuildTokenFromClaims {
+                subject: authorization.subject.clone(),
+                key_id: authorization.key_id.clone(),
                 lifetime: *JWT_ACCESS_LIFETIME,
-                claims: claims.clone()
+                claims: authorization.claims.clone(),
+                refresh: false
             }).flatten()
             .join(state.authman.clone().send(BuildTokenFromClaims {
+                subject: authorization.subject,
+                key_id: authorization.key_id,
                 lifetime: *JWT_REFRESH_LIFETIME,
-                claims
+                claims: authorization.claims,
+                refresh: true
             }).flatten())
             .and_then(move |(access, refresh)| Ok(TokenResponse {
                 token_type: "bearer".into(),
@@ -60,4 +69,85 @@ fn password_grant(state: State<AppState>, username: String, password: String)
                 refresh_token: refresh
             }))
         })
-}
\ No newline at end of file
+}
+
+// Along the lines of RFC 7009, which is also how to log out: whoever holds the
+// token may revoke it, and unknown or invalid tokens are acknowledged all the same.
+// The token is posted as a form, so it doesn't end up in the logs along with the URL
+fn api_revoke_token((state, revocation): (State<AppState>, Form<TokenRevocation>))
+    -> FutureResponse<HttpResponse> {
+
+    state.authman
+        .send(RevokeToken { token: revocation.into_inner().token }).flatten()
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use crate::cryptoutil::CryptoUtil;
+    use crate::database::messages::{CreateUser, SetUserRoles};
+    use serde_json::json;
+    use crate::authorization::permissions::{USERS_MANAGE, AUDIT_READ};
+    use super::super::testing::TestApp;
+
+    #[test]
+    fn refresh_tokens_are_only_good_for_refreshing() {
+        let mut app = TestApp::new();
+        let (user, key) = app.send(CreateUser { friendly_name: "alice".into(), hashed_key: CryptoUtil::hash_key("secret") }).unwrap();
+        app.send(SetUserRoles { id: user.id, roles: vec![USERS_MANAGE.into()] }).unwrap();
+
+        let grant = format!("/api/auth/token?grant_type=password&username=alice&password={}", key.credential("secret"));
+        let (status, tokens) = app.call(app.request(Method::POST, &grant).finish());
+        assert_eq!(status, StatusCode::CREATED);
+
+        let access = tokens["access_token"].as_str().unwrap().to_string();
+        let refresh = tokens["refresh_token"].as_str().unwrap().to_string();
+        let bearer = |token: &str| format!("Bearer {}", token);
+
+        let (status, _) = app.call(app.request(Method::GET, "/api/users").header("Authorization", bearer(&access)).finish());
+        assert_eq!(status, StatusCode::OK);
+
+        // Carrying the same claims doesn't make a refresh token an access token
+        let (status, _) = app.call(app.request(Method::GET, "/api/users").header("Authorization", bearer(&refresh)).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Nor is an access token any good for refreshing
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/auth/token?grant_type=refresh_token&refresh_token={}", access)).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let refreshing = format!("/api/auth/token?grant_type=refresh_token&refresh_token={}", refresh);
+        let (status, _) = app.call(app.request(Method::POST, &refreshing).finish());
+        assert_eq!(status, StatusCode::CREATED);
+
+        // Revoked the way RFC 7009 has it, with the token in the body
+        let (status, _) = app.call(app.request(Method::POST, "/api/auth/revoke").form(&[("token", refresh.as_str())]));
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = app.call(app.request(Method::POST, &refreshing).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn tokens_follow_their_user_through_a_rename() {
+        let mut app = TestApp::new();
+        let (id, caller) = app.login("alice", &[USERS_MANAGE, AUDIT_READ]);
+
+        let (status, _) = app.call(app.request(Method::PATCH, &format!("/api/users/{}", id))
+            .header("Authorization", caller.as_str()).json(json!({ "friendly_name": "alicia" })));
+        assert_eq!(status, StatusCode::OK);
+
+        // Someone else taking the old name doesn't get the changes made with the token
+        app.login("alice", &[]);
+
+        let (status, _) = app.call(app.request(Method::PATCH, &format!("/api/users/{}", id))
+            .header("Authorization", caller.as_str()).json(json!({ "friendly_name": "ally" })));
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, log) = app.call(app.request(Method::GET, "/api/audit?action=update_user")
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(log["items"].as_array().unwrap().len(), 2);
+        assert!(log["items"].as_array().unwrap().iter().all(|entry| entry["subject"] == json!(id)));
+    }
+}
@@ -1,24 +1,56 @@
 use actix::Addr;
-use actix_web::{State, http::Method, Scope, HttpRequest, HttpResponse, FutureResponse, Path, AsyncResponder};
-use futures::future::Future;
+use actix_web::{State, http::Method, Scope, HttpRequest, HttpResponse, FutureResponse, Path, Query, AsyncResponder};
+use futures::future::{result, Future};
 use crate::app::AppState;
 use crate::errors::ServiceError;
 use crate::database::DbExecutor;
 use crate::database::messages::*;
 use crate::certificates::messages::*;
+use crate::watcher::messages::DomainRegistered;
 use crate::certificates::CertificateManager;
 use crate::authorization::{ValidateClaim, ResourceAuthorization};
 use crate::authorization::models::*;
+use crate::authorization::permissions::{self, DOMAINS_MANAGE};
 use super::{make_result, ResultType};
+use super::pagination::{list_query, cursor_page};
+use super::audit::audit;
+use super::roles::authorize_group_change;
 use super::models::*;
 
 pub fn register(router: Scope<AppState>) -> Scope<AppState> {
     router
         .nested("/{fqdn}", |entry| {
-            entry.authorize_resource("fqdn", "public")
+            // Seeing a domain only takes its metadata. Everything which changes it
+            // asks for more, either below or in the handlers themselves
+            entry.resolve_domain("fqdn")
+            .authorize_resource("fqdn", "metadata")
             .resource("", |r| {
                 r.method(Method::GET).with_async(api_get_domain);
                 r.method(Method::POST).with_async(api_create_domain);
+                r.method(Method::DELETE).with_async(api_delete_domain);
+            })
+            .nested("/aliases/{alias}", |alias| {
+                alias.authorize_resource("*", DOMAINS_MANAGE)
+                .resource("", |r| {
+                    r.method(Method::PUT).with_async(api_create_domain_alias);
+                    r.method(Method::DELETE).with_async(api_delete_domain_alias);
+                })
+            })
+            .resource("/aliases", |r| {
+                r.method(Method::GET).with_async(api_get_domain_aliases);
+            })
+            .nested("/groups/{group_id}", |group| {
+                group.authorize_resource("fqdn", "manage")
+                .resource("", |r| {
+                    r.method(Method::POST).with_async(api_add_domain_group);
+                    r.method(Method::DELETE).with_async(api_remove_domain_group);
+                })
+            })
+            .nested("/promote", |promote| {
+                promote.authorize_resource("*", DOMAINS_MANAGE)
+                .resource("", |r| {
+                    r.method(Method::POST).with_async(api_promote_domain_lineage);
+                })
             })
             .nested("/certs", |certs| {
                 certs.nested("/latest", |latest| {
@@ -42,23 +74,178 @@ pub fn register(router: Scope<AppState>) -> Scope<AppState> {
                 })
             })
         })
+        .resource("", |r| {
+            r.method(Method::GET).with_async(api_get_domains);
+        })
+}
+
+fn api_get_domains((state, req, params): (State<AppState>, HttpRequest<AppState>, Query<ListParams>))
+    -> FutureResponse<HttpResponse> {
+
+    // Any authenticated user may list domains, but will only see those they have access to
+    result(req.validate_claims(&[])).from_err()
+        .and_then(move |_| list_query(params.into_inner()))
+        .and_then(move |query| {
+            let fqdns = req.claimed_subjects("metadata");
+
+            state.db.send(ListDomains { query: query.clone(), fqdns }).flatten().from_err()
+                .and_then(move |domains| Ok(cursor_page(domains, &query,
+                    |domain| (&domain.fqdn, &domain.id),
+                    |domain| PluggableDomain {
+                        id: domain.id,
+                        fqdn: domain.fqdn,
+                        archived: domain.archived,
+                        aliases: None,
+                        groups: None,
+                        latest_certs: None
+                    })))
+        })
+        .then(make_result(ResultType::Data)).responder()
 }
 
-fn api_create_domain((fqdn, state): (Path<String>, State<AppState>))
+fn api_create_domain((fqdn, state, req): (Path<String>, State<AppState>, HttpRequest<AppState>))
     -> FutureResponse<HttpResponse> {
 
-    state.db
-        .send(CreateDomain { fqdn: fqdn.into_inner() })
-        .flatten().from_err()
+    let fqdn = fqdn.into_inner();
+    let audit = audit(&req, "create_domain").fqdn(fqdn.clone());
+
+    // Like deleting them, creating domains is up to domain managers
+    let db = state.db.clone();
+    let created = result(req.validate_claims(&[permissions::role(DOMAINS_MANAGE)])).map_err(|_| ServiceError::Unauthorized)
+        .and_then(move |_| db.send(CreateDomain { fqdn }).flatten().from_err());
+
+    audit.change(created)
+        .and_then(move |domain| {
+            // In manual mode the watcher may have been waiting for this domain to be registered
+            state.watcher.do_send(DomainRegistered { fqdn: domain.fqdn.clone() });
+            Ok(domain)
+        })
         .and_then(|domain| Ok(PluggableDomain {
             fqdn: domain.fqdn,
             id: domain.id,
+            archived: domain.archived,
+            aliases: None,
             groups: None,
             latest_certs: None
         }))
         .then(make_result(ResultType::Created)).responder()
 }
 
+fn api_delete_domain((fqdn, state, req): (Path<String>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let fqdn = fqdn.into_inner();
+    let audit = audit(&req, "delete_domain").fqdn(fqdn.clone());
+
+    // Everyone with access to the domain may look at it, but only domain managers get to delete it.
+    // The name isn't resolved through aliases, so an alias is refused rather than taking its domain along
+    let deleted = result(req.validate_claims(&[permissions::role(DOMAINS_MANAGE)])).map_err(|_| ServiceError::Unauthorized)
+        .and_then(move |_| state.db.send(DeleteDomain { fqdn }).flatten().from_err());
+
+    audit.change(deleted)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_add_domain_group((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (fqdn, group_id) = path.into_inner();
+    let audit = audit(&req, "add_group_domain").fqdn(fqdn.clone()).target(group_id.clone());
+
+    let db = state.db.clone();
+    let changed = authorize_group_change(&req, group_id.clone())
+        .and_then(move |_| db.send(AddGroupDomain { domain: fqdn, group_id, create_missing: false }).flatten().from_err());
+
+    audit.change(changed)
+        // Nothing is created for a domain which is already there
+        .map(|_| ())
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_remove_domain_group((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (fqdn, group_id) = path.into_inner();
+    let audit = audit(&req, "remove_group_domain").fqdn(fqdn.clone()).target(group_id.clone());
+
+    let db = state.db.clone();
+    let changed = authorize_group_change(&req, group_id.clone())
+        .and_then(move |_| db.send(RemoveGroupDomain { domain: fqdn, group_id }).flatten().from_err());
+
+    audit.change(changed)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_promote_domain_lineage((fqdn, state, req): (Path<String>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let fqdn = fqdn.into_inner();
+    let audit = audit(&req, "promote_domain_lineage").fqdn(fqdn.clone());
+
+    audit.change(state.db
+        .send(PromoteDomainLineage { fqdn })
+        .flatten().from_err())
+        .and_then(move |domain| get_domain_by_fqdn(state.db.clone(), domain.fqdn))
+        .then(make_result(ResultType::Created)).responder()
+}
+
+fn api_get_domain_aliases((fqdn, state, params): (Path<String>, State<AppState>, Query<ListParams>))
+    -> FutureResponse<HttpResponse> {
+
+    let db = state.db.clone();
+
+    result(list_query(params.into_inner()))
+        .and_then(move |query| {
+            state.db.send(ResolveDomain { fqdn: fqdn.into_inner() }).flatten().from_err()
+                .and_then(move |domain| {
+                    db.send(ListDomainAliases { id: domain.id, query: query.clone() }).flatten().from_err()
+                        .and_then(move |aliases| Ok(cursor_page(aliases, &query,
+                            |alias| (&alias.fqdn, &alias.hashed_fqdn),
+                            |alias| alias.fqdn)))
+                })
+        })
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_create_domain_alias((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (fqdn, alias) = path.into_inner();
+    let audit = audit(&req, "create_domain_alias").fqdn(fqdn.clone()).target(alias.clone());
+
+    let db = state.db.clone();
+    let created = state.db.send(ResolveDomain { fqdn }).flatten().from_err()
+        .and_then(move |domain| {
+            db.send(CreateDomainAlias { 
+                fqdn: alias, 
+                domain_id: domain.id 
+            }).flatten().from_err()
+        });
+
+    audit.change(created)
+        .and_then(move |alias| get_domain_by_fqdn(state.db.clone(), alias.fqdn))
+        .then(make_result(ResultType::Created)).responder()
+}
+
+fn api_delete_domain_alias((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (fqdn, alias) = path.into_inner();
+    let audit = audit(&req, "delete_domain_alias").fqdn(fqdn.clone()).target(alias.clone());
+
+    let db = state.db.clone();
+    let deleted = state.db.send(ResolveDomain { fqdn }).flatten().from_err()
+        .and_then(move |domain| {
+            db.send(DeleteDomainAlias { 
+                fqdn: alias, 
+                domain_id: domain.id 
+            }).flatten().from_err()
+        });
+
+    audit.change(deleted)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
 fn api_get_domain((fqdn, state): (Path<String>, State<AppState>))
     -> FutureResponse<HttpResponse> {
   
@@ -69,7 +256,7 @@ fn api_get_domain((fqdn, state): (Path<String>, State<AppState>))
 fn api_get_domain_certificates((fqdn, state): (Path<String>, State<AppState>)) 
     -> FutureResponse<HttpResponse> {
 
-    state.db.send(GetDomainByFqdn { fqdn: fqdn.into_inner() }).flatten().from_err()
+    state.db.send(ResolveDomain { fqdn: fqdn.into_inner() }).flatten().from_err()
         .and_then(move |domain| {
             get_domains_certificates(state.db.clone(), domain.id)
         })
@@ -80,40 +267,44 @@ fn api_get_domain_certificate((path, state, req): (Path<(String, i32, String)>,
     -> FutureResponse<HttpResponse> {
 
     let (fqdn, version, friendly_name) = path.into_inner();
-    
-    get_domain_certificate(state.db.clone(), state.certman.clone(), (fqdn, Some(version), friendly_name))
-        // If the returned certificate is a private key, make sure the user 
-        // is allowed to see them, before transmitting them
-        .and_then(move |result| {
-            if result.is_private && req.validate_claims(&[Claim { subject: "fqdn".into(), permission: "private".into()}]).is_err() {
-                return Err(ServiceError::Unauthorized);
-            }
 
-            Ok(result)
-        })
-        .and_then(|result| {
-            Ok(HttpResponse::Ok()
-                .content_type("application/x-pem-file")
-                .body(result.raw_data))
-        })
-        .from_err()
-        .responder()
+    download_certificate(state, req, (fqdn, Some(version), friendly_name))
 }
 
 fn api_get_domain_latest_certificate((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
     -> FutureResponse<HttpResponse> {
 
     let (fqdn, friendly_name) = path.into_inner();
-    
-    get_domain_certificate(state.db.clone(), state.certman.clone(), (fqdn, None, friendly_name))
-        // If the returned certificate is a private key, make sure the user 
-        // is allowed to see them, before transmitting them
-        .and_then(move |result| {
-            if result.is_private && req.validate_claims(&[Claim { subject: "fqdn".into(), permission: "private".into()}]).is_err() {
-                return Err(ServiceError::Unauthorized);
-            }
 
-            Ok(result)
+    download_certificate(state, req, (fqdn, None, friendly_name))
+}
+
+fn download_certificate(state: State<AppState>, req: HttpRequest<AppState>, (fqdn, version, friendly_name): (String, Option<i32>, String))
+    -> FutureResponse<HttpResponse> {
+
+    let audit = audit(&req, "download")
+        .fqdn(fqdn.clone())
+        .version(version)
+        .friendly_name(friendly_name.clone());
+
+    get_domain_certificate(state.db.clone(), state.certman.clone(), (fqdn, version, friendly_name))
+        .then(move |found| {
+            // Looking at the domain only takes its metadata, so make sure the user is allowed
+            // to see the file, private keys in particular, before transmitting it. Denied
+            // attempts are recorded with what they were after, just like successful ones.
+            // Every attempt is recorded, and nothing is handed out unless it was
+            match found {
+                Ok(cert) => {
+                    let permission = if cert.is_private { "private" } else { "public" };
+                    let audit = audit.version(Some(cert.version)).private(cert.is_private);
+
+                    match req.validate_claims(&[Claim { subject: "fqdn".into(), permission: permission.into()}]) {
+                        Ok(_) => audit.access(Ok(cert)),
+                        Err(_) => audit.access(Err(ServiceError::Unauthorized))
+                    }
+                },
+                Err(e) => audit.access(Err(e))
+            }
         })
         .and_then(|result| {
             Ok(HttpResponse::Ok()
@@ -127,7 +318,7 @@ fn api_get_domain_latest_certificate((path, state, req): (Path<(String, String)>
 fn get_domain_certificate(db: Addr<DbExecutor>, certman: Addr<CertificateManager>, (fqdn, version, friendly_name): (String, Option<i32>, String))
     -> impl Future<Item = RawCertificate, Error = ServiceError>
 {
-    db.send(GetDomainByFqdn{ fqdn }).flatten()
+    db.send(ResolveDomain{ fqdn }).flatten()
         .from_err()
         .and_then(move |domain|
             db.send(GetCertificate { 
@@ -141,6 +332,7 @@ fn get_domain_certificate(db: Addr<DbExecutor>, certman: Addr<CertificateManager
                     .map_err(|_| ServiceError::InternalServerError)
                     .and_then(move |file| {
                         Ok(RawCertificate {
+                            version: cert.id,
                             raw_data: file.raw_data,
                             is_private: cert.is_private
                         })
@@ -154,7 +346,7 @@ fn api_get_domain_certificates_version((path, state): (Path<(String, i32)>, Stat
 
     let (fqdn, version) = path.into_inner();
 
-    state.db.send(GetDomainByFqdn{ fqdn }).flatten().from_err()
+    state.db.send(ResolveDomain{ fqdn }).flatten().from_err()
         .and_then(move |domain|
             get_domain_certificates_version(state.db.clone(), (domain.id, Some(version)))
         )
@@ -164,7 +356,7 @@ fn api_get_domain_certificates_version((path, state): (Path<(String, i32)>, Stat
 fn api_get_domain_latest_certificates_version((fqdn, state): (Path<String>, State<AppState>))
     -> FutureResponse<HttpResponse> {
 
-    state.db.send(GetDomainByFqdn{ fqdn: fqdn.into_inner() }).flatten().from_err()
+    state.db.send(ResolveDomain{ fqdn: fqdn.into_inner() }).flatten().from_err()
         .and_then(move |domain|
             get_domain_certificates_version(state.db.clone(), (domain.id, None))
         )
@@ -198,12 +390,24 @@ fn get_domains_groups(db: Addr<DbExecutor>, id: String)
             Ok(groups.into_iter().map(|group| PluggableGroup {
                 id: group.id,
                 friendly_name: group.friendly_name,
+                permission: group.permission,
                 domains: None,
-                users: None
+                users: None,
+                domains_next: None,
+                users_next: None
             }).collect())
         )
 }
 
+fn get_domain_aliases(db: Addr<DbExecutor>, id: String) 
+    -> impl Future<Item = Vec<String>, Error = ServiceError> {
+    db.send(GetAliasesByDomain { id }).flatten()
+        .map_err(|e| e.into())
+        .and_then(|aliases|
+            Ok(aliases.into_iter().map(|alias| alias.fqdn).collect())
+        )
+}
+
 fn get_domains_certificates(db: Addr<DbExecutor>, id: String) 
     -> impl Future<Item = Vec<Certificate>, Error = ServiceError> {
     db.send(GetCertificatesByDomain { id }).flatten()
@@ -223,16 +427,73 @@ fn get_domains_certificates(db: Addr<DbExecutor>, id: String)
 
 fn get_domain_by_fqdn(db: Addr<DbExecutor>, fqdn: String)
     -> impl Future<Item = PluggableDomain, Error = ServiceError> {
-    db.send(GetDomainByFqdn { fqdn }).flatten()
+    db.send(ResolveDomain { fqdn }).flatten()
         .from_err()
         .and_then(move |domain| 
             get_domains_groups(db.clone(), domain.id.clone())
-                .join(get_domain_certificates_version(db.clone(), (domain.id.clone(), None)))
-                .and_then(|(groups, certificates)| Ok(PluggableDomain {
+                .join3(
+                    get_domain_aliases(db.clone(), domain.id.clone()),
+                    get_domain_certificates_version(db.clone(), (domain.id.clone(), None))
+                )
+                .and_then(|(groups, aliases, certificates)| Ok(PluggableDomain {
                     id: domain.id.clone(),
                     fqdn: domain.fqdn,
+                    archived: domain.archived,
+                    aliases: Some(aliases),
                     groups: Some(groups),
                     latest_certs: Some(certificates)
                 }))
         )
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use crate::authorization::permissions::DOMAINS_MANAGE;
+    use crate::database::messages::{CreateDomain, CreateGroup, AddGroupDomain, AddGroupUser};
+    use super::super::testing::TestApp;
+
+    // Signs in a user whose group grants the given permission on example.com
+    fn member(app: &mut TestApp, name: &str, permission: &str) -> String {
+        let (user, key) = app.user(name, &[]);
+        let group = app.send(CreateGroup { friendly_name: name.into(), permission: permission.into() }).unwrap();
+        app.send(AddGroupDomain { domain: "example.com".into(), group_id: group.id.clone(), create_missing: false }).unwrap();
+        app.send(AddGroupUser { user_id: user.id, group_id: group.id }).unwrap();
+
+        app.sign_in(name, &key)
+    }
+
+    #[test]
+    fn domains_are_only_created_by_domain_managers() {
+        let mut app = TestApp::new();
+        app.send(CreateDomain { fqdn: "example.com".into() }).unwrap();
+        let caller = member(&mut app, "alice", "manage");
+        let (_, manager) = app.login("bob", &[DOMAINS_MANAGE]);
+
+        // Managing a domain is deciding which groups it belongs to, not whether it's there
+        let (status, _) = app.call(app.request(Method::POST, "/api/domains/example.com")
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::POST, "/api/domains/example.org")
+            .header("Authorization", manager.as_str()).finish());
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[test]
+    fn domains_are_seen_with_metadata_but_not_changed_with_it() {
+        let mut app = TestApp::new();
+        app.send(CreateDomain { fqdn: "example.com".into() }).unwrap();
+        let caller = member(&mut app, "alice", "metadata");
+
+        let (status, _) = app.call(app.request(Method::GET, "/api/domains/example.com/aliases")
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::OK);
+
+        for method in vec![Method::POST, Method::DELETE] {
+            let (status, _) = app.call(app.request(method, "/api/domains/example.com")
+                .header("Authorization", caller.as_str()).finish());
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+    }
+}
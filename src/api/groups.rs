@@ -1,29 +1,52 @@
+use std::collections::HashMap;
 use actix::Addr;
-use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, Path, Json, AsyncResponder};
-use futures::future::{join_all, Future};
+use actix_web::{State, http::Method, Scope, HttpRequest, HttpResponse, FutureResponse, Path, Query, Json, AsyncResponder};
+use futures::future::{result, Future};
 use crate::app::AppState;
 use crate::errors::ServiceError;
 use crate::database::messages::*;
 use crate::database::DbExecutor;
+use crate::watcher::messages::DomainRegistered;
+use crate::database::models::{Group, User, Domain};
 use crate::authorization::ResourceAuthorization;
+use crate::authorization::permissions::{self, DEFAULT_PERMISSION, GROUPS_MANAGE};
 use super::{make_result, ResultType};
+use super::audit::audit;
+use super::roles::{check_role_changes, authorize_group_change};
+use super::pagination::{list_query, cursor_page, first_page};
 use super::models::*;
 
+// Listing groups embeds this many of the users and of the domains of each one
+const MEMBERS_PER_GROUP: i64 = 20;
+
 pub fn register(router: Scope<AppState>) -> Scope<AppState> {
     router
-        .authorize_resource("*", "*")
+        .authorize_resource("*", GROUPS_MANAGE)
         .nested("/{group_id}", |entry| {
             entry.resource("", |r| {
                 r.method(Method::GET).with_async(api_get_group);
+                r.method(Method::PATCH).with_async(api_update_group);
+            })
+            .resource("/roles", |r| {
+                r.method(Method::GET).with_async(api_get_group_roles);
+                r.method(Method::PUT).with_async(api_set_group_roles);
             })
             .nested("/users", |users| {
-                users.resource("", |r| {
+                users.resource("/{user_id}", |r| {
+                    r.method(Method::POST).with_async(api_add_group_user);
+                    r.method(Method::DELETE).with_async(api_remove_group_user);
+                })
+                .resource("", |r| {
                     r.method(Method::PUT).with_async(api_set_group_users);
                     r.method(Method::GET).with_async(api_get_group_users);
                 })
             })
             .nested("/domains", |domains| {
-                domains.resource("", |r| {
+                domains.resource("/{domain}", |r| {
+                    r.method(Method::POST).with_async(api_add_group_domain);
+                    r.method(Method::DELETE).with_async(api_remove_group_domain);
+                })
+                .resource("", |r| {
                     r.method(Method::PUT).with_async(api_set_group_domains);
                     r.method(Method::GET).with_async(api_get_group_domains);
                 })
@@ -35,72 +58,312 @@ pub fn register(router: Scope<AppState>) -> Scope<AppState> {
         })
 }
 
-fn api_get_groups(state: State<AppState>) 
+fn api_get_groups((state, params): (State<AppState>, Query<ListParams>)) 
     -> FutureResponse<HttpResponse> {
-    state.db.clone()
-        .send(GetGroups {}).flatten().from_err()
-        .and_then(move |groups|
-            join_all(groups.into_iter().map(move |group|
-                get_group(state.db.clone(), group.id)
-            ))
-        )
-        .then(make_result(ResultType::Created)).responder()
+
+    result(list_query(params.into_inner()))
+        .and_then(move |query| {
+            let db = state.db.clone();
+
+            state.db
+                .send(ListGroups { query: query.clone() }).flatten().from_err()
+                .and_then(move |groups| {
+                    let page = cursor_page(groups, &query, |group| (&group.friendly_name, &group.id), |group| group);
+                    let ids = page.items.iter().map(|group| group.id.clone()).collect();
+
+                    // The members of every group on the page are fetched all at once
+                    db.send(GetGroupMembers { ids, query: first_page(MEMBERS_PER_GROUP) }).flatten().from_err()
+                        .and_then(move |members| Ok(group_page(page, members)))
+                })
+        })
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn group_page(page: CursorPage<Group>, members: Vec<(String, Vec<User>, Vec<Domain>)>) -> CursorPage<PluggableGroup> {
+    let mut members: HashMap<String, (Vec<User>, Vec<Domain>)> = members.into_iter()
+        .map(|(group_id, users, domains)| (group_id, (users, domains)))
+        .collect();
+
+    CursorPage {
+        items: page.items.into_iter().map(|group| {
+            let (users, domains) = members.remove(&group.id).unwrap_or_default();
+            with_members(group, users, domains)
+        }).collect(),
+        next: page.next
+    }
 }
 
-fn api_get_group_users((group_id, state): (Path<String>, State<AppState>))
+// Only the first page of each member list is embedded, the rest can be had from
+// /groups/{id}/users and /groups/{id}/domains starting at the cursors given along
+fn with_members(group: Group, users: Vec<User>, domains: Vec<Domain>) -> PluggableGroup {
+    let query = first_page(MEMBERS_PER_GROUP);
+
+    let users = cursor_page(users, &query, |user| (&user.friendly_name, &user.id), |user| PluggableUser {
+        id: user.id,
+        friendly_name: user.friendly_name,
+        disabled: user.disabled,
+        secret_key: None,
+        groups: None
+    });
+
+    let domains = cursor_page(domains, &query, |domain| (&domain.fqdn, &domain.id), |domain| PluggableDomain {
+        id: domain.id,
+        fqdn: domain.fqdn,
+        archived: domain.archived,
+        aliases: None,
+        groups: None,
+        latest_certs: None
+    });
+
+    PluggableGroup {
+        id: group.id,
+        friendly_name: group.friendly_name,
+        permission: group.permission,
+        domains: Some(domains.items),
+        users: Some(users.items),
+        domains_next: domains.next,
+        users_next: users.next
+    }
+}
+
+fn api_get_group_users((group_id, state, params): (Path<String>, State<AppState>, Query<ListParams>))
     -> FutureResponse<HttpResponse> {
-    get_group_users(state.db.clone(), group_id.into_inner())
-        .then(make_result(ResultType::Created)).responder()
+
+    result(list_query(params.into_inner()))
+        .and_then(move |query| {
+            state.db
+                .send(ListGroupUsers { id: group_id.into_inner(), query: query.clone() }).flatten().from_err()
+                .and_then(move |users| Ok(cursor_page(users, &query,
+                    |user| (&user.friendly_name, &user.id),
+                    |user| PluggableUser {
+                        id: user.id,
+                        friendly_name: user.friendly_name,
+                        disabled: user.disabled,
+                        secret_key: None,
+                        groups: None
+                    })))
+        })
+        .then(make_result(ResultType::Data)).responder()
 }
 
-fn api_get_group_domains((group_id, state): (Path<String>, State<AppState>))
+fn api_get_group_domains((group_id, state, params): (Path<String>, State<AppState>, Query<ListParams>))
     -> FutureResponse<HttpResponse> {
-    get_group_domains(state.db.clone(), group_id.into_inner())
-        .then(make_result(ResultType::Created)).responder()
+
+    result(list_query(params.into_inner()))
+        .and_then(move |query| {
+            state.db
+                .send(ListGroupDomains { id: group_id.into_inner(), query: query.clone() }).flatten().from_err()
+                .and_then(move |domains| Ok(cursor_page(domains, &query,
+                    |domain| (&domain.fqdn, &domain.id),
+                    |domain| PluggableDomain {
+                        id: domain.id,
+                        fqdn: domain.fqdn,
+                        archived: domain.archived,
+                        aliases: None,
+                        groups: None,
+                        latest_certs: None
+                    })))
+        })
+        .then(make_result(ResultType::Data)).responder()
 }
 
-fn api_set_group_users((group_id, users, state): (Path<String>, Json<Vec<String>>, State<AppState>))
+fn api_set_group_users((group_id, users, state, req): (Path<String>, Json<Vec<String>>, State<AppState>, HttpRequest<AppState>))
     -> FutureResponse<HttpResponse> {
-        
-    state.db.clone()
-        .send(SetGroupUsers { 
-            user_ids: users.into_inner(), 
-            group_id: group_id.clone() 
-        }).flatten().from_err()
+
+    let audit = audit(&req, "set_group_users").target(group_id.clone()).details(users.join(","));
+
+    let db = state.db.clone();
+    let id = group_id.clone();
+    let changed = authorize_group_change(&req, id.clone())
+        .and_then(move |_| db.send(SetGroupUsers { user_ids: users.into_inner(), group_id: id }).flatten().from_err());
+
+    audit.change(changed)
         .and_then(move |_| {
             get_group(state.db.clone(), group_id.into_inner())
         })
         .then(make_result(ResultType::Created)).responder()
 }
 
-fn api_set_group_domains((group_id, fqdns, state): (Path<String>, Json<Vec<String>>, State<AppState>))
+// Domains are given by id or by name, so a list of names from elsewhere can be used as it is
+fn api_set_group_domains((group_id, domains, query, state, req): (Path<String>, Json<Vec<String>>, Query<GroupDomainsQuery>, State<AppState>, HttpRequest<AppState>))
     -> FutureResponse<HttpResponse> {
 
-    state.db.clone()
-        .send(SetGroupDomains { 
-            fqdns: fqdns.into_inner(), 
-            group_id: group_id.clone() 
-        }).flatten().from_err()
-        .and_then(move |_| {
+    let audit = audit(&req, "set_group_domains").target(group_id.clone()).details(domains.join(","));
+
+    let db = state.db.clone();
+    let id = group_id.clone();
+    let changed = authorize_group_change(&req, id.clone())
+        .and_then(move |_| db.send(SetGroupDomains {
+            domains: domains.into_inner(),
+            group_id: id,
+            create_missing: query.create_missing
+        }).flatten().from_err());
+
+    audit.change(changed)
+        .and_then(move |created| {
+            register_placeholders(&state, created);
             get_group(state.db.clone(), group_id.into_inner())
         })
         .then(make_result(ResultType::Created)).responder()
 }
 
-fn api_create_group((group, state): (Json<NewGroupRequest>, State<AppState>))
+fn api_add_group_user((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (group_id, user_id) = path.into_inner();
+    let audit = audit(&req, "add_group_user").target(group_id.clone()).details(user_id.clone());
+
+    let db = state.db.clone();
+    let changed = authorize_group_change(&req, group_id.clone())
+        .and_then(move |_| db.send(AddGroupUser { user_id, group_id }).flatten().from_err());
+
+    audit.change(changed)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_remove_group_user((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (group_id, user_id) = path.into_inner();
+    let audit = audit(&req, "remove_group_user").target(group_id.clone()).details(user_id.clone());
+
+    let db = state.db.clone();
+    let changed = authorize_group_change(&req, group_id.clone())
+        .and_then(move |_| db.send(RemoveGroupUser { user_id, group_id }).flatten().from_err());
+
+    audit.change(changed)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_add_group_domain((path, query, state, req): (Path<(String, String)>, Query<GroupDomainsQuery>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (group_id, domain) = path.into_inner();
+    let audit = audit(&req, "add_group_domain").target(group_id.clone()).details(domain.clone());
+
+    let db = state.db.clone();
+    let changed = authorize_group_change(&req, group_id.clone())
+        .and_then(move |_| db.send(AddGroupDomain { domain, group_id, create_missing: query.create_missing }).flatten().from_err());
+
+    audit.change(changed)
+        .and_then(move |created| Ok(register_placeholders(&state, created)))
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+// In manual mode the watcher may have been waiting for placeholder domains to be registered
+fn register_placeholders(state: &AppState, created: Vec<String>) {
+    for fqdn in created {
+        state.watcher.do_send(DomainRegistered { fqdn });
+    }
+}
+
+fn api_remove_group_domain((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (group_id, domain) = path.into_inner();
+    let audit = audit(&req, "remove_group_domain").target(group_id.clone()).details(domain.clone());
+
+    let db = state.db.clone();
+    let changed = authorize_group_change(&req, group_id.clone())
+        .and_then(move |_| db.send(RemoveGroupDomain { domain, group_id }).flatten().from_err());
+
+    audit.change(changed)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_create_group((group, state, req): (Json<NewGroupRequest>, State<AppState>, HttpRequest<AppState>))
     -> FutureResponse<HttpResponse> {
 
-    state.db.clone()
-        .send(CreateGroup { friendly_name: group.friendly_name.clone() }).flatten().from_err()
+    let group = group.into_inner();
+    let audit = audit(&req, "create_group")
+        .target(group.friendly_name.clone())
+        .details(group.permission.clone());
+
+    let db = state.db.clone();
+    let friendly_name = group.friendly_name;
+    let created = result(check_permission(group.permission)).and_then(move |permission| db
+        .send(CreateGroup {
+            friendly_name,
+            permission: permission.unwrap_or_else(|| DEFAULT_PERMISSION.into())
+        }).flatten().from_err());
+
+    audit.change(created)
         .and_then(|group| Ok(PluggableGroup {
             id: group.id,
             friendly_name: group.friendly_name,
+            permission: group.permission,
             domains: Some(Vec::new()),
-            users: Some(Vec::new())
+            users: Some(Vec::new()),
+            domains_next: None,
+            users_next: None
         }))
         .then(make_result(ResultType::Created)).responder()
 }
 
+fn api_update_group((group_id, group, state, req): (Path<String>, Json<UpdateGroupRequest>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let group = group.into_inner();
+    let group_id = group_id.into_inner();
+    let audit = audit(&req, "update_group")
+        .target(group_id.clone())
+        .friendly_name(group.friendly_name.clone())
+        .details(group.permission.clone());
+
+    let db = state.db.clone();
+    let friendly_name = group.friendly_name;
+    let updated = result(check_permission(group.permission))
+        .join(authorize_group_change(&req, group_id.clone()))
+        .and_then(move |(permission, _)| db
+            .send(UpdateGroup { id: group_id.clone(), friendly_name, permission }).flatten().from_err()
+            .and_then(move |_| get_group(db, group_id)));
+
+    audit.change(updated)
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_get_group_roles((group_id, state): (Path<String>, State<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    get_group_roles(state.db.clone(), group_id.into_inner())
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_set_group_roles((group_id, roles, state, req): (Path<String>, Json<Vec<String>>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let group_id = group_id.into_inner();
+    let roles = roles.into_inner();
+    let audit = audit(&req, "set_group_roles").target(group_id.clone()).details(roles.join(","));
+
+    let db = state.db.clone();
+    let id = group_id.clone();
+    let changed = db.send(GetGroupRoles { id: id.clone() }).flatten().from_err()
+        .and_then(move |current| result(check_role_changes(&req, &current, &roles))
+            .and_then(move |_| db.send(SetGroupRoles { id, roles }).flatten().from_err()));
+
+    let db = state.db.clone();
+    audit.change(changed)
+        .and_then(move |_| get_group_roles(db, group_id))
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn get_group_roles(db: Addr<DbExecutor>, id: String)
+    -> impl Future<Item = Vec<String>, Error = ServiceError> {
+
+    db.send(GetGroup { id: id.clone() }).flatten().from_err()
+        .and_then(move |_| db.send(GetGroupRoles { id }).flatten().from_err())
+}
+
+fn check_permission(permission: Option<String>) -> Result<Option<String>, ServiceError> {
+    match permission {
+        Some(ref name) if !permissions::is_known(name) => Err(ServiceError::BadRequest(
+            format!("unknown permission {}, expected one of {}", name, permissions::known().join(", "))
+        )),
+        permission => Ok(permission)
+    }
+}
+
 fn api_get_group((group_id, state): (Path<String>, State<AppState>))
     -> FutureResponse<HttpResponse> {
   
@@ -113,46 +376,41 @@ fn get_group(db: Addr<DbExecutor>, id: String)
 
     db.clone()
         .send(GetGroup { id: id.clone() }).flatten().from_err()
-        .join3(
-            get_group_users(db.clone(), id.clone()),
-            get_group_domains(db.clone(), id.clone())
-        )
-        .and_then(|(group, users, domains)| {
-            Ok(PluggableGroup {
-                id: group.id,
-                friendly_name: group.friendly_name,
-                domains: Some(domains),
-                users: Some(users)
-            })
+        .join(db.send(GetGroupMembers { ids: vec![id], query: first_page(MEMBERS_PER_GROUP) }).flatten().from_err())
+        .and_then(|(group, mut members)| {
+            let (users, domains) = members.pop().map(|(_, users, domains)| (users, domains)).unwrap_or_default();
+            Ok(with_members(group, users, domains))
         })
 }
 
-fn get_group_users(db: Addr<DbExecutor>, id: String) 
-    -> impl Future<Item = Vec<PluggableUser>, Error = ServiceError> {
-    db
-        .send(GetUsersByGroup { id }).flatten()
-        .map_err(|e| e.into())
-        .and_then(|users| 
-            Ok(users.into_iter().map(|user| PluggableUser {
-                id: user.id,
-                friendly_name: user.friendly_name,
-                secret_key: None,
-                groups: None
-            }).collect())
-        )
-}
-
-fn get_group_domains(db: Addr<DbExecutor>, id: String) 
-    -> impl Future<Item = Vec<PluggableDomain>, Error = ServiceError> {
-    db
-        .send(GetDomainsByGroup { id }).flatten()
-        .map_err(|e| e.into())
-        .and_then(|domains| 
-            Ok(domains.into_iter().map(|domain| PluggableDomain {
-                id: domain.id,
-                fqdn: domain.fqdn,
-                groups: None,
-                latest_certs: None
-            }).collect())
-        )
-}
\ No newline at end of file
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use crate::database::messages::{CreateGroup, SetGroupRoles, CreateUser};
+    use crate::authorization::permissions::{GROUPS_MANAGE, USERS_MANAGE};
+    use super::super::testing::TestApp;
+
+    #[test]
+    fn groups_are_only_changed_by_those_holding_their_roles() {
+        let mut app = TestApp::new();
+        let (_, caller) = app.login("alice", &[GROUPS_MANAGE]);
+        let (bob, _) = app.send(CreateUser { friendly_name: "bob".into(), hashed_key: "unused".into() }).unwrap();
+
+        let managers = app.send(CreateGroup { friendly_name: "managers".into(), permission: "public".into() }).unwrap();
+        app.send(SetGroupRoles { id: managers.id.clone(), roles: vec![USERS_MANAGE.into()] }).unwrap();
+        let plain = app.send(CreateGroup { friendly_name: "plain".into(), permission: "public".into() }).unwrap();
+
+        // Putting someone in the group would hand out users:manage, which the caller doesn't hold
+        let joining = format!("/api/groups/{}/users/{}", managers.id, bob.id);
+        let (status, _) = app.call(app.request(Method::POST, &joining).header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::PUT, &format!("/api/groups/{}/users", managers.id))
+            .header("Authorization", caller.as_str()).json(vec![bob.id.clone()]));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/groups/{}/users/{}", plain.id, bob.id))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::OK);
+    }
+}
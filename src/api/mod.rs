@@ -3,10 +3,20 @@ mod auth;
 mod domains;
 mod users;
 mod groups;
+mod watcher;
+mod reconciler;
+mod quarantine;
+mod audit;
+mod pagination;
+mod roles;
+
+#[cfg(all(test, feature = "sqlite"))]
+pub mod testing;
 
 use actix_web::{Scope, ResponseError, HttpResponse};
 use crate::errors::ServiceError;
 use super::app::AppState;
+use self::models::ErrorResponse;
 
 pub fn register(scope: Scope<AppState>) -> Scope<AppState> {
     scope
@@ -16,6 +26,10 @@ pub fn register(scope: Scope<AppState>) -> Scope<AppState> {
         .nested("/domains", domains::register)
         .nested("/users", users::register)
         .nested("/groups", groups::register)
+        .nested("/watcher", watcher::register)
+        .nested("/reconciler", reconciler::register)
+        .nested("/quarantine", quarantine::register)
+        .nested("/audit", audit::register)
 }
 
 pub enum ResultType {
@@ -51,7 +65,26 @@ pub enum ApiResult<T>
 impl ResponseError for ServiceError {
     fn error_response(&self) -> HttpResponse {
         error!("{:?}", self);
-        HttpResponse::InternalServerError().finish()
+
+        // Only what is wrong with the request itself is explained, anything
+        // else could leak details about the database and the like
+        match self {
+            ServiceError::BadRequest(message) => HttpResponse::BadRequest().json(ErrorResponse {
+                error: message.clone(),
+                ids: None
+            }),
+            ServiceError::UnknownReferences(kind, ids) => HttpResponse::BadRequest().json(ErrorResponse {
+                error: format!("unknown {}", kind),
+                ids: Some(ids.clone())
+            }),
+            ServiceError::NotFound(message) => HttpResponse::NotFound().json(ErrorResponse {
+                error: message.clone(),
+                ids: None
+            }),
+            ServiceError::Conflict(_) => HttpResponse::Conflict().finish(),
+            ServiceError::Unauthorized => HttpResponse::Unauthorized().finish(),
+            ServiceError::InternalServerError => HttpResponse::InternalServerError().finish()
+        }
     }
 }
 
@@ -1,17 +1,44 @@
 use serde_derive::{Serialize, Deserialize};
 use chrono::NaiveDateTime;
 
+#[derive(Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ids: Option<Vec<String>>
+}
+
 #[derive(Deserialize)]
 pub struct NewUserRequest {
     pub friendly_name: String
 }
 
+#[derive(Deserialize)]
+pub struct UpdateUserRequest {
+    pub friendly_name: Option<String>,
+    pub disabled: Option<bool>
+}
+
+#[derive(Serialize)]
+pub struct RevokedSessions {
+    pub revoked: usize
+}
+
 #[derive(Deserialize)]
 pub struct NewGroupRequest {
-    pub friendly_name: String
+    pub friendly_name: String,
+    pub permission: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct UpdateGroupRequest {
+    pub friendly_name: Option<String>,
+    pub permission: Option<String>
 }
 
 pub struct RawCertificate {
+    pub version: i32,
     pub is_private: bool,
     pub raw_data: Vec<u8>
 }
@@ -33,6 +60,10 @@ pub struct Certificate {
 pub struct PluggableDomain {
     pub id: String,
     pub fqdn: String,
+    pub archived: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
         
     #[serde(skip_serializing_if = "Option::is_none")]
     pub groups: Option<Vec<PluggableGroup>>,
@@ -45,18 +76,28 @@ pub struct PluggableDomain {
 pub struct PluggableGroup {
     pub id: String,
     pub friendly_name: String,
+    pub permission: String,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub domains: Option<Vec<PluggableDomain>>,
     
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub users: Option<Vec<PluggableUser>>
+    pub users: Option<Vec<PluggableUser>>,
+
+    // Where the embedded lists continue in /groups/{id}/domains and /groups/{id}/users,
+    // if they had more than a page's worth
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domains_next: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users_next: Option<String>
 }
 
 #[derive(Serialize)]
 pub struct PluggableUser {
     pub id: String,
     pub friendly_name: String,
+    pub disabled: bool,
 
     // This is only ever populated when a new user is created
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -66,6 +107,216 @@ pub struct PluggableUser {
     pub groups: Option<Vec<PluggableGroup>>
 }
 
+#[derive(Deserialize)]
+pub struct RotateKeyQuery {
+    // Seconds during which the replaced key keeps working
+    pub grace_period: Option<i64>
+}
+
+#[derive(Deserialize)]
+pub struct NewApiKeyRequest {
+    pub label: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub domains: Option<Vec<String>>,
+    pub permissions: Option<Vec<String>>
+}
+
+#[derive(Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub label: String,
+    pub created_at: NaiveDateTime,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<NaiveDateTime>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<NaiveDateTime>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_key_expires_at: Option<NaiveDateTime>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domains: Option<Vec<String>>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<Vec<String>>,
+
+    // This is only ever populated when a key is created or rotated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_key: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct UserRoles {
+    // Assigned to the user directly
+    pub assigned: Vec<String>,
+    // Including those held through the user's groups
+    pub effective: Vec<String>
+}
+
+#[derive(Serialize)]
+pub struct WatcherStatus {
+    pub archive: String,
+    pub backend: String,
+    pub domains: Vec<WatchedDomain>,
+    pub rejected: Vec<RejectedDirectory>,
+    pub watches: usize,
+    pub max_watches: Option<usize>,
+    pub recoveries: usize
+}
+
+#[derive(Serialize)]
+pub struct WatchedDomain {
+    pub dir: String,
+    pub state: String,
+    pub last_event: Option<NaiveDateTime>,
+    pub ingested: usize,
+    pub errors: Vec<FileError>
+}
+
+#[derive(Serialize)]
+pub struct RejectedDirectory {
+    pub dir: String,
+    pub reason: String
+}
+
+#[derive(Serialize)]
+pub struct FileError {
+    pub path: String,
+    pub at: NaiveDateTime,
+    pub message: String
+}
+
+#[derive(Deserialize)]
+pub struct GroupDomainsQuery {
+    #[serde(default)]
+    pub create_missing: bool
+}
+
+#[derive(Deserialize)]
+pub struct QuarantineQuery {
+    pub fqdn: Option<String>
+}
+
+#[derive(Serialize)]
+pub struct QuarantinedFile {
+    pub path: String,
+    pub fqdn: String,
+    pub kind: String,
+    pub message: String,
+    pub occurred_at: NaiveDateTime
+}
+
+#[derive(Deserialize)]
+pub struct ListParams {
+    pub q: Option<String>,
+    pub sort: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub subject: Option<String>,
+    pub fqdn: Option<String>,
+    pub action: Option<String>,
+    pub result: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub cursor: Option<String>,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: NaiveDateTime,
+    pub subject: Option<String>,
+    pub client_ip: Option<String>,
+    pub action: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fqdn: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub friendly_name: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_private: Option<bool>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+
+    pub result: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_hash: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub cursor: Option<String>,
+    pub limit: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct AuditVerification {
+    pub intact: bool,
+    pub entries: i64,
+    pub unchained: i64,
+    pub checkpoints: i64,
+    pub head: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_break: Option<AuditChainBreak>
+}
+
+#[derive(Serialize)]
+pub struct AuditChainBreak {
+    pub id: i64,
+    pub reason: String
+}
+
+#[derive(Serialize)]
+pub struct AuditCheckpoint {
+    pub id: i64,
+    pub created_at: NaiveDateTime,
+    pub entry_id: i64,
+    pub entry_hash: String,
+    pub public_key: String,
+    pub signature: String,
+    pub message: String
+}
+
+#[derive(Serialize)]
+pub struct ReconcileResult {
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub inserted: usize,
+    pub reparsed: usize,
+    pub removed: usize,
+    pub failed: usize,
+    pub errors: Vec<String>
+}
+
 #[derive(Deserialize)]
 pub struct PasswordGrant {
     pub grant_type: String,
@@ -79,6 +330,11 @@ pub struct RefreshGrant {
     pub refresh_token: String
 }
 
+#[derive(Deserialize)]
+pub struct TokenRevocation {
+    pub token: String
+}
+
 #[derive(Deserialize)]
 #[serde(untagged)]
 pub enum Grant {
@@ -0,0 +1,126 @@
+use crate::errors::ServiceError;
+use crate::cryptoutil::CryptoUtil;
+use crate::database::models::ListQuery;
+use super::models::{ListParams, CursorPage};
+
+const DEFAULT_LIMIT: i64 = 50;
+const MAX_LIMIT: i64 = 500;
+
+// Cursors are opaque to clients, but are really just the name and id of the last item
+fn encode_cursor(name: &str, id: &str) -> String {
+    CryptoUtil::to_hex(format!("{}\0{}", name, id).as_bytes())
+}
+
+fn decode_cursor(cursor: &str) -> Option<(String, String)> {
+    let decoded = String::from_utf8(CryptoUtil::from_hex(cursor)?).ok()?;
+    let mut parts = decoded.splitn(2, '\0');
+
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+fn decode_id_cursor(cursor: &str) -> Option<i64> {
+    String::from_utf8(CryptoUtil::from_hex(cursor)?).ok()?.parse().ok()
+}
+
+fn limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT)
+}
+
+pub fn list_query(params: ListParams) -> Result<ListQuery, ServiceError> {
+    let descending = match params.sort.as_ref().map(String::as_str) {
+        None | Some("name") => false,
+        Some("-name") => true,
+        Some(sort) => return Err(ServiceError::BadRequest(format!("unable to sort by {}", sort)))
+    };
+
+    let after = match params.cursor {
+        Some(cursor) => Some(decode_cursor(&cursor)
+            .ok_or_else(|| ServiceError::BadRequest("invalid cursor".into()))?),
+        None => None
+    };
+
+    Ok(ListQuery {
+        q: params.q.filter(|q| !q.is_empty()),
+        descending,
+        after,
+        limit: limit(params.limit)
+    })
+}
+
+// The first page of a list, as embedded in whatever the list belongs to
+pub fn first_page(limit: i64) -> ListQuery {
+    ListQuery {
+        q: None,
+        descending: false,
+        after: None,
+        limit
+    }
+}
+
+// Logs are listed newest first, and a page picks up below the id of the last
+// item of the previous one. Returns that id, if any, and the size of the page
+pub fn log_query(cursor: Option<String>, size: Option<i64>) -> Result<(Option<i64>, i64), ServiceError> {
+    let before = match cursor {
+        Some(cursor) => Some(decode_id_cursor(&cursor)
+            .ok_or_else(|| ServiceError::BadRequest("invalid cursor".into()))?),
+        None => None
+    };
+
+    Ok((before, limit(size)))
+}
+
+// Like cursor_page, for logs
+pub fn log_page<T, U, K, F>(mut items: Vec<T>, limit: i64, id: K, map: F) -> CursorPage<U>
+    where K: Fn(&T) -> i64, F: FnMut(T) -> U {
+
+    let next = if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        items.last().map(|item| CryptoUtil::to_hex(id(item).to_string().as_bytes()))
+    } else {
+        None
+    };
+
+    CursorPage {
+        items: items.into_iter().map(map).collect(),
+        next
+    }
+}
+
+// Turns what a paginated query returned into a page, with a cursor
+// for the next one if there was more than a page's worth
+pub fn cursor_page<T, U, K, F>(mut items: Vec<T>, query: &ListQuery, key: K, map: F) -> CursorPage<U>
+    where K: Fn(&T) -> (&str, &str), F: FnMut(T) -> U {
+
+    let next = if items.len() as i64 > query.limit {
+        items.truncate(query.limit as usize);
+        items.last().map(|item| {
+            let (name, id) = key(item);
+            encode_cursor(name, id)
+        })
+    } else {
+        None
+    };
+
+    CursorPage {
+        items: items.into_iter().map(map).collect(),
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_pages_pick_up_below_the_last_id() {
+        let page = log_page(vec![9, 8, 7], 2, |id| *id, |id| id);
+        assert_eq!(page.items, vec![9, 8]);
+
+        let (before, limit) = log_query(page.next, Some(2)).unwrap();
+        assert_eq!(before, Some(8));
+        assert_eq!(limit, 2);
+
+        assert!(log_page(vec![2, 1], 2, |id| *id, |id| id).next.is_none());
+        assert!(log_query(Some("not a cursor".into()), None).is_err());
+    }
+}
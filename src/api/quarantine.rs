@@ -0,0 +1,40 @@
+use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, Query, AsyncResponder};
+use futures::future::{result, Future};
+use crate::app::AppState;
+use crate::database::messages::*;
+use crate::authorization::ResourceAuthorization;
+use crate::authorization::permissions::DOMAINS_MANAGE;
+use super::{make_result, ResultType};
+use super::pagination::{list_query, cursor_page};
+use super::models::*;
+
+pub fn register(router: Scope<AppState>) -> Scope<AppState> {
+    router
+        .authorize_resource("*", DOMAINS_MANAGE)
+        .resource("", |r| {
+            r.method(Method::GET).with_async(api_get_quarantine);
+        })
+}
+
+// Listed by path, which q searches in
+fn api_get_quarantine((state, query, params): (State<AppState>, Query<QuarantineQuery>, Query<ListParams>))
+    -> FutureResponse<HttpResponse> {
+
+    let fqdn = query.into_inner().fqdn;
+
+    result(list_query(params.into_inner()))
+        .and_then(move |query| {
+            state.db
+                .send(ListQuarantine { fqdn, query: query.clone() }).flatten().from_err()
+                .and_then(move |files| Ok(cursor_page(files, &query,
+                    |file| (&file.path, &file.hashed_path),
+                    |file| QuarantinedFile {
+                        path: file.path,
+                        fqdn: file.fqdn,
+                        kind: file.kind,
+                        message: file.message,
+                        occurred_at: file.occurred_at
+                    })))
+        })
+        .then(make_result(ResultType::Data)).responder()
+}
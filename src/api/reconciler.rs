@@ -0,0 +1,50 @@
+use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, AsyncResponder};
+use futures::future::Future;
+use crate::app::AppState;
+use crate::reconciler::messages::*;
+use crate::reconciler::models::ReconcileReport;
+use crate::authorization::ResourceAuthorization;
+use crate::authorization::permissions::DOMAINS_MANAGE;
+use super::{make_result, ResultType};
+use super::models::*;
+
+pub fn register(router: Scope<AppState>) -> Scope<AppState> {
+    router
+        .authorize_resource("*", DOMAINS_MANAGE)
+        .resource("", |r| {
+            r.method(Method::GET).with_async(api_get_reconcile_report);
+            r.method(Method::POST).with_async(api_reconcile);
+        })
+}
+
+impl From<ReconcileReport> for ReconcileResult {
+    fn from(report: ReconcileReport) -> Self {
+        ReconcileResult {
+            started_at: report.started_at,
+            finished_at: report.finished_at,
+            inserted: report.inserted,
+            reparsed: report.reparsed,
+            removed: report.removed,
+            failed: report.failed,
+            errors: report.errors
+        }
+    }
+}
+
+fn api_get_reconcile_report(state: State<AppState>)
+    -> FutureResponse<HttpResponse> {
+
+    state.reconciler
+        .send(GetReconcileReport {}).flatten().from_err()
+        .and_then(|report| Ok(report.map(ReconcileResult::from)))
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_reconcile(state: State<AppState>)
+    -> FutureResponse<HttpResponse> {
+
+    state.reconciler
+        .send(Reconcile {}).flatten().from_err()
+        .and_then(|report| Ok(ReconcileResult::from(report)))
+        .then(make_result(ResultType::Data)).responder()
+}
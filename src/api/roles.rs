@@ -0,0 +1,77 @@
+use actix_web::HttpRequest;
+use futures::future::{result, Future};
+use crate::app::AppState;
+use crate::errors::ServiceError;
+use crate::database::messages::{GetGroupRoles, GetUserRoles};
+use crate::authorization::ValidateClaim;
+use crate::authorization::models::Claim;
+use crate::authorization::permissions;
+
+// Roles can only be handed out or taken away by someone who holds them, so
+// managing users or groups is no way of gaining any other role
+pub fn check_role_changes(req: &HttpRequest<AppState>, current: &[String], requested: &[String]) -> Result<(), ServiceError> {
+    if let Some(unknown) = requested.iter().find(|role| !permissions::is_role(role)) {
+        return Err(ServiceError::BadRequest(
+            format!("unknown role {}, expected one of {}", unknown, permissions::roles().join(", "))
+        ));
+    }
+
+    let changed: Vec<Claim> = requested.iter()
+        .filter(|role| !current.contains(role))
+        .chain(current.iter().filter(|role| !requested.contains(role)))
+        .map(|role| permissions::role(role))
+        .collect();
+
+    req.validate_claims(&changed).map_err(|_| ServiceError::Unauthorized)
+}
+
+// Some changes hand out whatever roles a group or user holds without touching them:
+// changing who is in a group, or what it grants on its domains, and issuing keys
+// for a user. Those take holding every one of those roles, as handing them out does
+pub fn check_held_roles<S>(req: &HttpRequest<S>, roles: &[String]) -> Result<(), ServiceError> {
+    let claims: Vec<Claim> = roles.iter().map(|role| permissions::role(role)).collect();
+    req.validate_claims(&claims).map_err(|_| ServiceError::Unauthorized)
+}
+
+pub fn authorize_group_change(req: &HttpRequest<AppState>, group_id: String) -> impl Future<Item = (), Error = ServiceError> {
+    let req = req.clone();
+
+    req.state().db.send(GetGroupRoles { id: group_id }).flatten().from_err()
+        .and_then(move |roles| result(check_held_roles(&req, &roles)))
+}
+
+// Whoever holds a key of a user's holds the roles of their groups as well. Renaming,
+// disabling or deleting a user, or revoking their keys, takes the same, or managing
+// users would be a way of locking out whoever holds roles the caller doesn't
+pub fn authorize_key_change(req: &HttpRequest<AppState>, user_id: String) -> impl Future<Item = (), Error = ServiceError> {
+    let req = req.clone();
+
+    req.state().db.send(GetUserRoles { id: user_id, inherited: true }).flatten().from_err()
+        .and_then(move |roles| result(check_held_roles(&req, &roles)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use crate::authorization::permissions::{self, GROUPS_MANAGE, USERS_MANAGE};
+    use crate::errors::ServiceError;
+    use super::check_held_roles;
+
+    #[test]
+    fn changes_handing_out_roles_take_every_one_of_them() {
+        let req = TestRequest::default().finish();
+        req.extensions_mut().insert(vec![permissions::role(GROUPS_MANAGE)]);
+
+        assert!(check_held_roles(&req, &[]).is_ok());
+        assert!(check_held_roles(&req, &[GROUPS_MANAGE.to_string()]).is_ok());
+
+        match check_held_roles(&req, &[GROUPS_MANAGE.to_string(), USERS_MANAGE.to_string()]) {
+            Err(ServiceError::Unauthorized) => (),
+            _ => panic!("roles the caller lacks shouldn't be handed out by them")
+        }
+
+        let admin = TestRequest::default().finish();
+        admin.extensions_mut().insert(vec![permissions::administrator()]);
+        assert!(check_held_roles(&admin, &[USERS_MANAGE.to_string()]).is_ok());
+    }
+}
@@ -0,0 +1,120 @@
+// The whole app, served over HTTP against a throwaway database for tests
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use actix::{Addr, Arbiter, Handler, Message, SyncArbiter, System};
+use actix_web::{HttpMessage, Error as HttpError};
+use actix_web::client::{ClientRequest, ClientRequestBuilder};
+use actix_web::http::{Method, StatusCode};
+use actix_web::test::TestServer;
+use futures::Future;
+use serde_json::Value;
+use crate::app::create_app;
+use crate::auditor::Auditor;
+use crate::authorization::AuthorizationManager;
+use crate::certificates::CertificateManager;
+use crate::cryptoutil::CryptoUtil;
+use crate::database::{DbExecutor, testing};
+use crate::database::errors::Error;
+use crate::database::messages::{CreateUser, SetUserRoles};
+use crate::database::models::{User, ApiKey};
+use crate::reconciler::Reconciler;
+use crate::watcher::ArchiveWatcher;
+use crate::watcher::models::Backend;
+
+pub struct TestApp {
+    pub server: TestServer,
+    pub db: Addr<DbExecutor>
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        std::env::set_var("RUBLIC_SHARED_SECRET", "test secret");
+
+        let archive = std::env::temp_dir().join(format!("rublic-app-{}", std::process::id()));
+        std::fs::create_dir_all(&archive).unwrap();
+
+        // Like main, the actors run on a system of their own, so the workers serving
+        // requests can block on them. The executor is fed from that system's thread
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let sys = System::new("test-app");
+            let pool = testing::pool();
+            let db = SyncArbiter::start(1, move || DbExecutor(pool.clone()));
+
+            // As in main, whatever blocks on the executor gets an arbiter of its own
+            let dbref = db.clone();
+            let certman = Arbiter::start(move |_| CertificateManager { db: dbref });
+
+            let dbref = db.clone();
+            let authman = Arbiter::start(move |_| AuthorizationManager { db: dbref });
+
+            let (dbref, certmanref, dir) = (db.clone(), certman.clone(), archive.clone());
+            let watcher = Arbiter::start(move |_| ArchiveWatcher::new(dbref, certmanref, dir, Backend::Polling(Duration::from_secs(60)), None));
+
+            let (dbref, certmanref) = (db.clone(), certman.clone());
+            let reconciler = Arbiter::start(move |_| Reconciler::new(dbref, certmanref, archive.clone(), archive));
+
+            let dbref = db.clone();
+            let auditor = Arbiter::start(move |_| Auditor::new(dbref, None, None));
+
+            tx.send((db, certman, authman, watcher, reconciler, auditor)).unwrap();
+            sys.run();
+        });
+
+        let actors = rx.recv().unwrap();
+        let db = actors.0.clone();
+        let server = TestServer::with_factory(move || {
+            let (db, certman, authman, watcher, reconciler, auditor) = actors.clone();
+            create_app(db, certman, authman, watcher, reconciler, auditor)
+        });
+
+        TestApp { server, db }
+    }
+
+    // Sends a message to the executor and waits for its reply
+    pub fn send<M, T>(&self, msg: M) -> Result<T, Error>
+        where M: Message<Result = Result<T, Error>> + Send + 'static,
+              DbExecutor: Handler<M>,
+              T: Send + 'static {
+        self.db.send(msg).flatten().wait()
+    }
+
+    // Creates a user holding the given roles, whose default key has "secret" for a secret
+    pub fn user(&self, name: &str, roles: &[&str]) -> (User, ApiKey) {
+        let (user, key) = self.send(CreateUser { friendly_name: name.into(), hashed_key: CryptoUtil::hash_key("secret") }).unwrap();
+        self.send(SetUserRoles { id: user.id.clone(), roles: roles.iter().map(|role| role.to_string()).collect() }).unwrap();
+
+        (user, key)
+    }
+
+    // Signs a user in with their key, returning what goes in the Authorization header of their requests
+    pub fn sign_in(&mut self, name: &str, key: &ApiKey) -> String {
+        let grant = format!("/api/auth/token?grant_type=password&username={}&password={}", name, key.credential("secret"));
+        let (status, tokens) = self.call(self.request(Method::POST, &grant).finish());
+        assert_eq!(status, StatusCode::CREATED);
+
+        format!("Bearer {}", tokens["access_token"].as_str().unwrap())
+    }
+
+    // Creates a user holding the given roles and signs them in, returning their id along with sign_in's header
+    pub fn login(&mut self, name: &str, roles: &[&str]) -> (String, String) {
+        let (user, key) = self.user(name, roles);
+        let authorization = self.sign_in(name, &key);
+
+        (user.id, authorization)
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> ClientRequestBuilder {
+        self.server.client(method, path)
+    }
+
+    // Makes the request, returning the status of the response and its body, if it's JSON
+    pub fn call(&mut self, request: Result<ClientRequest, HttpError>) -> (StatusCode, Value) {
+        let response = self.server.execute(request.unwrap().send()).unwrap();
+        let body = self.server.execute(response.body()).unwrap();
+
+        (response.status(), serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+}
@@ -1,49 +1,298 @@
 use actix::Addr;
-use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, Path, Json, AsyncResponder};
-use futures::future::Future;
+use actix_web::{State, http::Method, Scope, HttpRequest, HttpResponse, FutureResponse, Path, Query, Json, AsyncResponder};
+use futures::future::{result, ok, join_all, Either, Future};
+use chrono::{Utc, Duration};
 use crate::app::AppState;
 use crate::errors::ServiceError;
 use crate::database::DbExecutor;
 use crate::database::messages::*;
 use crate::authorization::ResourceAuthorization;
+use crate::authorization::permissions::{self, USERS_MANAGE};
+use crate::database::models::{self, DEFAULT_KEY_LABEL};
 use crate::cryptoutil::CryptoUtil;
 use super::{make_result, ResultType};
+use super::audit::audit;
+use super::pagination::{list_query, cursor_page};
+use super::roles::{check_role_changes, authorize_key_change};
 use super::models::*;
 
 
 pub fn register(router: Scope<AppState>) -> Scope<AppState> {
     router
-        .authorize_resource("*", "*")
+        .authorize_resource("*", USERS_MANAGE)
         .nested("/{user_id}", |entry| {
             entry.resource("", |r| {
                 r.method(Method::GET).with_async(api_get_user);
+                r.method(Method::PATCH).with_async(api_update_user);
+                r.method(Method::DELETE).with_async(api_delete_user);
+            })
+            .resource("/revoke-sessions", |r| {
+                r.method(Method::POST).with_async(api_revoke_user_sessions);
+            })
+            .resource("/rotate-key", |r| {
+                r.method(Method::POST).with_async(api_rotate_user_default_key);
+            })
+            .nested("/keys", |keys| {
+                keys.resource("/{key}/rotate", |r| {
+                    r.method(Method::POST).with_async(api_rotate_user_key);
+                })
+                .resource("/{key}", |r| {
+                    r.method(Method::DELETE).with_async(api_revoke_user_key);
+                })
+                .resource("", |r| {
+                    r.method(Method::GET).with_async(api_get_user_keys);
+                    r.method(Method::POST).with_async(api_create_user_key);
+                })
+            })
+            .resource("/roles", |r| {
+                r.method(Method::GET).with_async(api_get_user_roles);
+                r.method(Method::PUT).with_async(api_set_user_roles);
             })
         })
         .resource("", |r| {
+            r.method(Method::GET).with_async(api_get_users);
             r.method(Method::POST).with_async(api_create_user);
         })
 }
 
-fn api_create_user((new_user, state): (Json<NewUserRequest>, State<AppState>)) 
+fn api_create_user((new_user, state, req): (Json<NewUserRequest>, State<AppState>, HttpRequest<AppState>)) 
     -> FutureResponse<HttpResponse> {
     
     let key = CryptoUtil::generate_key();
     let hashed_key = CryptoUtil::hash_key(&key);
 
-    state.db
+    let audit = audit(&req, "create_user").target(new_user.friendly_name.clone());
+
+    audit.change(state.db
         .send(CreateUser { 
             friendly_name: new_user.friendly_name.clone(), 
             hashed_key 
-        }).flatten().from_err()
-        .and_then(|user| Ok(PluggableUser {
+        }).flatten().from_err())
+        .and_then(move |(user, default_key)| Ok(PluggableUser {
             id: user.id,
             friendly_name: user.friendly_name,
-            secret_key: Some(key),
+            disabled: user.disabled,
+            secret_key: Some(default_key.credential(&key)),
             groups: None
         }))
         .then(make_result(ResultType::Created)).responder()
 }
 
+fn api_get_user_keys((user_id, state): (Path<String>, State<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let db = state.db.clone();
+    let user_id = user_id.into_inner();
+
+    state.db
+        .send(GetUser { id: user_id.clone() }).flatten().from_err()
+        .and_then(move |_| db.send(GetApiKeys { user_id }).flatten().from_err())
+        .and_then(|keys| Ok(keys.into_iter().map(|key| api_key(key, None)).collect::<Vec<_>>()))
+        .then(make_result(ResultType::Data)).responder()
+}
+
+// The key itself is only ever shown here and when it is rotated
+fn api_create_user_key((user_id, new_key, state, req): (Path<String>, Json<NewApiKeyRequest>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let key = CryptoUtil::generate_key();
+    let hashed_key = CryptoUtil::hash_key(&key);
+    let new_key = new_key.into_inner();
+
+    let audit = audit(&req, "create_user_key").target(user_id.clone()).details(new_key.label.clone());
+
+    let unknown = new_key.permissions.iter().flatten()
+        .find(|permission| !permissions::is_known(permission) && !permissions::is_role(permission));
+
+    let checked = match unknown {
+        Some(permission) => Err(ServiceError::BadRequest(format!("unknown permission {}", permission))),
+        None => Ok(())
+    };
+
+    let db = state.db.clone();
+    let created = result(checked)
+        .join3(authorize_key_change(&req, user_id.clone()), scope_domains(&state.db, new_key.domains.clone()))
+        .and_then(move |(_, _, scope_domains)| db.send(CreateApiKey {
+            user_id: user_id.into_inner(),
+            label: new_key.label,
+            hashed_key,
+            expires_at: new_key.expires_at,
+            scope_domains,
+            scope_permissions: new_key.permissions
+        }).flatten().from_err());
+
+    audit.change(created)
+        .and_then(move |created| {
+            let credential = created.credential(&key);
+            Ok(api_key(created, Some(credential)))
+        })
+        .then(make_result(ResultType::Created)).responder()
+}
+
+fn api_revoke_user_key((path, state, req): (Path<(String, String)>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (user_id, key) = path.into_inner();
+    let audit = audit(&req, "revoke_user_key").target(user_id.clone()).details(key.clone());
+
+    let db = state.db.clone();
+    let revoked = authorize_key_change(&req, user_id.clone())
+        .and_then(move |_| db.send(RevokeApiKey { user_id, key }).flatten().from_err());
+
+    audit.change(revoked)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+fn api_rotate_user_key((path, query, state, req): (Path<(String, String)>, Query<RotateKeyQuery>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let (user_id, key) = path.into_inner();
+    rotate_user_key(state, req, user_id, Some(key), query.grace_period)
+}
+
+// Users created before they could have several keys only have their default one
+fn api_rotate_user_default_key((user_id, query, state, req): (Path<String>, Query<RotateKeyQuery>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    rotate_user_key(state, req, user_id.into_inner(), None, query.grace_period)
+}
+
+fn rotate_user_key(state: State<AppState>, req: HttpRequest<AppState>, user_id: String, key: Option<String>, grace_period: Option<i64>)
+    -> FutureResponse<HttpResponse> {
+
+    let new_key = CryptoUtil::generate_key();
+    let hashed_key = CryptoUtil::hash_key(&new_key);
+
+    let name = key.clone().unwrap_or_else(|| DEFAULT_KEY_LABEL.into());
+    let audit = audit(&req, "rotate_user_key").target(user_id.clone()).details(match grace_period {
+        Some(seconds) => format!("{} grace_period={}", name, seconds),
+        None => name
+    });
+
+    let previous_key_expires_at = match grace_period {
+        Some(seconds) if seconds < 0 => Err(ServiceError::BadRequest("the grace period can't be negative".into())),
+        Some(seconds) if seconds > 0 => Ok(Some(Utc::now().naive_utc() + Duration::seconds(seconds))),
+        _ => Ok(None)
+    };
+
+    let db = state.db.clone();
+    let rotated = result(previous_key_expires_at)
+        .join(authorize_key_change(&req, user_id.clone()))
+        .and_then(move |(previous_key_expires_at, _)| db
+            .send(RotateApiKey { user_id, key, hashed_key, previous_key_expires_at }).flatten().from_err());
+
+    audit.change(rotated)
+        .and_then(move |rotated| {
+            let credential = rotated.credential(&new_key);
+            Ok(api_key(rotated, Some(credential)))
+        })
+        .then(make_result(ResultType::Created)).responder()
+}
+
+// Scopes are stored under the names domains go by, so a key restricted
+// to an alias of a domain is restricted to the domain itself
+fn scope_domains(db: &Addr<DbExecutor>, domains: Option<Vec<String>>)
+    -> impl Future<Item = Option<Vec<String>>, Error = ServiceError> {
+
+    let db = db.clone();
+    let resolved = domains.map(|domains| join_all(domains.into_iter().map(move |fqdn| db
+        .send(ResolveDomain { fqdn: fqdn.clone() }).flatten()
+        .map(|domain| domain.fqdn)
+        .map_err(move |e| match e {
+            crate::database::errors::Error::DataNotFound(_) => ServiceError::BadRequest(format!("unknown domain {}", fqdn)),
+            e => e.into()
+        }))));
+
+    match resolved {
+        Some(resolved) => Either::A(resolved.map(Some)),
+        None => Either::B(ok(None))
+    }
+}
+
+fn api_key(key: models::ApiKey, secret_key: Option<String>) -> ApiKey {
+    ApiKey {
+        domains: key.domains(),
+        permissions: key.permissions(),
+        id: key.id,
+        label: key.label,
+        created_at: key.created_at,
+        expires_at: key.expires_at,
+        last_used_at: key.last_used_at,
+        previous_key_expires_at: key.previous_key_expires_at,
+        secret_key
+    }
+}
+
+fn api_get_users((state, params): (State<AppState>, Query<ListParams>))
+    -> FutureResponse<HttpResponse> {
+
+    result(list_query(params.into_inner()))
+        .and_then(move |query| {
+            state.db
+                .send(ListUsers { query: query.clone() }).flatten().from_err()
+                .and_then(move |users| Ok(cursor_page(users, &query,
+                    |user| (&user.friendly_name, &user.id),
+                    |user| PluggableUser {
+                        id: user.id,
+                        friendly_name: user.friendly_name,
+                        disabled: user.disabled,
+                        secret_key: None,
+                        groups: None
+                    })))
+        })
+        .then(make_result(ResultType::Data)).responder()
+}
+
+// Disabling a user is how they're locked out without losing track of what they had
+fn api_update_user((user_id, user, state, req): (Path<String>, Json<UpdateUserRequest>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let user = user.into_inner();
+    let user_id = user_id.into_inner();
+    let audit = audit(&req, "update_user")
+        .target(user_id.clone())
+        .friendly_name(user.friendly_name.clone())
+        .details(user.disabled.map(|disabled| format!("disabled={}", disabled)));
+
+    let (db, id) = (state.db.clone(), user_id.clone());
+    let updated = authorize_key_change(&req, user_id.clone())
+        .and_then(move |_| db.send(UpdateUser { id, friendly_name: user.friendly_name, disabled: user.disabled }).flatten().from_err());
+
+    let db = state.db.clone();
+    audit.change(updated)
+        .and_then(move |_| get_user(db, user_id))
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_delete_user((user_id, state, req): (Path<String>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let user_id = user_id.into_inner();
+    let audit = audit(&req, "delete_user").target(user_id.clone());
+
+    let db = state.db.clone();
+    let deleted = authorize_key_change(&req, user_id.clone())
+        .and_then(move |_| db.send(DeleteUser { id: user_id }).flatten().from_err());
+
+    audit.change(deleted)
+        .then(make_result::<()>(ResultType::Acknowledged)).responder()
+}
+
+// Every refresh token the user holds stops working, access tokens run out on their own shortly
+fn api_revoke_user_sessions((user_id, state, req): (Path<String>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let user_id = user_id.into_inner();
+    let audit = audit(&req, "revoke_user_sessions").target(user_id.clone());
+
+    let db = state.db.clone();
+    audit.change(state.db
+        .send(GetUser { id: user_id }).flatten().from_err()
+        .and_then(move |user| db.send(RevokeRefreshTokens { user_id: user.id }).flatten().from_err()))
+        .and_then(|revoked| Ok(RevokedSessions { revoked }))
+        .then(make_result(ResultType::Data)).responder()
+}
+
 fn api_get_user((user_id, state): (Path<String>, State<AppState>))
     -> FutureResponse<HttpResponse> {
   
@@ -51,6 +300,42 @@ fn api_get_user((user_id, state): (Path<String>, State<AppState>))
         .then(make_result(ResultType::Created)).responder()
 }
 
+fn api_get_user_roles((user_id, state): (Path<String>, State<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    get_user_roles(state.db.clone(), user_id.into_inner())
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn api_set_user_roles((user_id, roles, state, req): (Path<String>, Json<Vec<String>>, State<AppState>, HttpRequest<AppState>))
+    -> FutureResponse<HttpResponse> {
+
+    let user_id = user_id.into_inner();
+    let roles = roles.into_inner();
+    let audit = audit(&req, "set_user_roles").target(user_id.clone()).details(roles.join(","));
+
+    let db = state.db.clone();
+    let id = user_id.clone();
+    let changed = db.send(GetUserRoles { id: id.clone(), inherited: false }).flatten().from_err()
+        .and_then(move |current| result(check_role_changes(&req, &current, &roles))
+            .and_then(move |_| db.send(SetUserRoles { id, roles }).flatten().from_err()));
+
+    let db = state.db.clone();
+    audit.change(changed)
+        .and_then(move |_| get_user_roles(db, user_id))
+        .then(make_result(ResultType::Data)).responder()
+}
+
+fn get_user_roles(db: Addr<DbExecutor>, id: String)
+    -> impl Future<Item = UserRoles, Error = ServiceError> {
+
+    db.send(GetUser { id: id.clone() }).flatten().from_err()
+        .and_then(move |_| db.send(GetUserRoles { id: id.clone(), inherited: false }).flatten()
+            .join(db.send(GetUserRoles { id, inherited: true }).flatten())
+            .from_err())
+        .and_then(|(assigned, effective)| Ok(UserRoles { assigned, effective }))
+}
+
 fn get_user(db: Addr<DbExecutor>, id: String)
     -> impl Future<Item = PluggableUser, Error = ServiceError> {
 
@@ -62,6 +347,7 @@ fn get_user(db: Addr<DbExecutor>, id: String)
                     Ok(PluggableUser {
                         id: user.id,
                         friendly_name: user.friendly_name,
+                        disabled: user.disabled,
                         secret_key: None,
                         groups: Some(groups)
                     })
@@ -78,8 +364,113 @@ fn get_user_groups(db: Addr<DbExecutor>, id: String)
             Ok(groups.into_iter().map(|group| PluggableGroup {
                 id: group.id,
                 friendly_name: group.friendly_name,
+                permission: group.permission,
                 users: None,
-                domains: None
+                domains: None,
+                domains_next: None,
+                users_next: None
             }).collect())
         )
-}
\ No newline at end of file
+}
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use serde_json::json;
+    use crate::authorization::permissions::{GROUPS_MANAGE, USERS_MANAGE, AUDIT_READ};
+    use crate::database::messages::{CreateDomain, CreateDomainAlias};
+    use super::super::testing::TestApp;
+
+    #[test]
+    fn keys_are_only_handed_out_by_those_holding_the_users_roles() {
+        let mut app = TestApp::new();
+        let (_, caller) = app.login("alice", &[USERS_MANAGE]);
+        let (bob, _) = app.login("bob", &[GROUPS_MANAGE]);
+        let (carol, _) = app.login("carol", &[]);
+
+        // A key of bob's would carry groups:manage, which the caller doesn't hold
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/users/{}/keys", bob))
+            .header("Authorization", caller.as_str()).json(json!({ "label": "ci" })));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/users/{}/rotate-key", bob))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/users/{}/keys", carol))
+            .header("Authorization", caller.as_str()).json(json!({ "label": "ci" })));
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/users/{}/rotate-key", carol))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::CREATED);
+    }
+
+    #[test]
+    fn users_are_only_changed_by_those_holding_their_roles() {
+        let mut app = TestApp::new();
+        let (_, caller) = app.login("alice", &[USERS_MANAGE]);
+        let (bob, bobs_key) = app.user("bob", &[AUDIT_READ]);
+        let (carol, carols_key) = app.user("carol", &[]);
+
+        // Bob holds audit:read, which the caller doesn't
+        let (status, _) = app.call(app.request(Method::PATCH, &format!("/api/users/{}", bob.id))
+            .header("Authorization", caller.as_str()).json(json!({ "disabled": true })));
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::DELETE, &format!("/api/users/{}/keys/{}", bob.id, bobs_key.id))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = app.call(app.request(Method::DELETE, &format!("/api/users/{}", bob.id))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // None of which was done, so bob can still sign in with the key
+        app.sign_in("bob", &bobs_key);
+
+        let (status, _) = app.call(app.request(Method::PATCH, &format!("/api/users/{}", carol.id))
+            .header("Authorization", caller.as_str()).json(json!({ "disabled": true })));
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = app.call(app.request(Method::DELETE, &format!("/api/users/{}/keys/{}", carol.id, carols_key.id))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = app.call(app.request(Method::DELETE, &format!("/api/users/{}", carol.id))
+            .header("Authorization", caller.as_str()).finish());
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn keys_are_scoped_to_domains_and_named_in_their_credentials() {
+        let mut app = TestApp::new();
+        let (_, caller) = app.login("alice", &[USERS_MANAGE]);
+        let (carol, _) = app.login("carol", &[]);
+
+        let domain = app.send(CreateDomain { fqdn: "example.com".into() }).unwrap();
+        app.send(CreateDomainAlias { fqdn: "www.example.org".into(), domain_id: domain.id }).unwrap();
+
+        let keys = format!("/api/users/{}/keys", carol);
+        let (status, _) = app.call(app.request(Method::POST, &keys)
+            .header("Authorization", caller.as_str()).json(json!({ "label": "ci", "domains": ["nowhere.example.net"] })));
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // The scope goes by the domain, not the alias it was asked for by
+        let (status, key) = app.call(app.request(Method::POST, &keys)
+            .header("Authorization", caller.as_str()).json(json!({ "label": "ci", "domains": ["www.example.org"] })));
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(key["domains"], json!(["example.com"]));
+
+        let id = key["id"].as_str().unwrap();
+        let credential = key["secret_key"].as_str().unwrap();
+        assert!(credential.starts_with(&format!("{}.", id)));
+
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/auth/token?grant_type=password&username=carol&password={}", credential)).finish());
+        assert_eq!(status, StatusCode::CREATED);
+
+        // The secret is only good for the key it belongs to
+        let (_, secret) = credential.split_at(id.len() + 1);
+        let (status, _) = app.call(app.request(Method::POST, &format!("/api/auth/token?grant_type=password&username=carol&password={}", secret)).finish());
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+}
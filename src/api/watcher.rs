@@ -0,0 +1,57 @@
+use actix_web::{State, http::Method, Scope, HttpResponse, FutureResponse, AsyncResponder};
+use futures::future::Future;
+use crate::app::AppState;
+use crate::watcher::messages::*;
+use crate::authorization::ResourceAuthorization;
+use crate::authorization::permissions::DOMAINS_MANAGE;
+use super::{make_result, ResultType};
+use super::models::*;
+
+pub fn register(router: Scope<AppState>) -> Scope<AppState> {
+    router
+        .authorize_resource("*", DOMAINS_MANAGE)
+        .resource("", |r| {
+            r.method(Method::GET).with_async(api_get_watcher_status);
+        })
+}
+
+fn api_get_watcher_status(state: State<AppState>)
+    -> FutureResponse<HttpResponse> {
+
+    state.watcher
+        .send(GetArchiveStatus {}).flatten().from_err()
+        .and_then(|status| Ok(WatcherStatus {
+            archive: status.dir.to_string_lossy().into(),
+            backend: status.backend.name().into(),
+            watches: status.watches,
+            max_watches: status.max_watches,
+            rejected: status.rejected.into_iter()
+                .map(|(dir, reason)| RejectedDirectory {
+                    dir: dir.to_string_lossy().into(),
+                    reason
+                })
+                .collect(),
+            recoveries: status.recoveries,
+            domains: status.domains.into_iter()
+                .map(|domain| {
+                    let mut errors: Vec<_> = domain.errors.into_iter()
+                        .map(|(path, error)| FileError {
+                            path: path.to_string_lossy().into(),
+                            at: error.at,
+                            message: error.message
+                        })
+                        .collect();
+                    errors.sort_by(|a, b| a.path.cmp(&b.path));
+
+                    WatchedDomain {
+                        dir: domain.dir.to_string_lossy().into(),
+                        state: domain.state.name().into(),
+                        last_event: domain.last_event,
+                        ingested: domain.ingested,
+                        errors
+                    }
+                })
+                .collect()
+        }))
+        .then(make_result(ResultType::Data)).responder()
+}
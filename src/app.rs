@@ -3,19 +3,28 @@ use actix_web::{middleware, App};
 use crate::database::DbExecutor;
 use crate::certificates::CertificateManager;
 use crate::authorization::AuthorizationManager;
+use crate::watcher::ArchiveWatcher;
+use crate::reconciler::Reconciler;
+use crate::auditor::Auditor;
 
 pub struct AppState {
     pub db: Addr<DbExecutor>,
     pub certman: Addr<CertificateManager>,
-    pub authman: Addr<AuthorizationManager>
+    pub authman: Addr<AuthorizationManager>,
+    pub watcher: Addr<ArchiveWatcher>,
+    pub reconciler: Addr<Reconciler>,
+    pub auditor: Addr<Auditor>
 }
 
 // helper function to create and returns the app after mounting all routes/resources
-pub fn create_app(db: Addr<DbExecutor>, certman: Addr<CertificateManager>, authman: Addr<AuthorizationManager>) -> App<AppState> {
+pub fn create_app(db: Addr<DbExecutor>, certman: Addr<CertificateManager>, authman: Addr<AuthorizationManager>, watcher: Addr<ArchiveWatcher>, reconciler: Addr<Reconciler>, auditor: Addr<Auditor>) -> App<AppState> {
     let state = AppState { 
         db,
         certman,
-        authman
+        authman,
+        watcher,
+        reconciler,
+        auditor
     };
     
     App::with_state(state)
@@ -0,0 +1,32 @@
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display = "Database Error: {}", _0)]
+    DatabaseError(crate::database::errors::Error),
+
+    #[fail(display = "Export Error: {}", _0)]
+    ExportError(std::io::Error),
+
+    #[fail(display = "No Signing Key")]
+    NoSigningKey,
+
+    #[fail(display = "Unknown Error")]
+    Unknown
+}
+
+impl From<actix::MailboxError> for Error {
+    fn from(_: actix::MailboxError) -> Self {
+        Error::Unknown
+    }
+}
+
+impl From<crate::database::errors::Error> for Error {
+    fn from(e: crate::database::errors::Error) -> Self {
+        Error::DatabaseError(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::ExportError(e)
+    }
+}
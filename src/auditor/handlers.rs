@@ -0,0 +1,16 @@
+use actix::{Handler, ResponseActFuture, fut};
+use crate::database::models::AuditCheckpoint;
+use super::Auditor;
+use super::messages::*;
+use super::errors::Error;
+
+impl Handler<Checkpoint> for Auditor {
+    type Result = ResponseActFuture<Self, Option<AuditCheckpoint>, Error>;
+
+    fn handle(&mut self, _: Checkpoint, _: &mut Self::Context) -> Self::Result {
+        match self.signing_key.clone() {
+            Some(signing_key) => Box::new(self.checkpoint(signing_key)),
+            None => Box::new(fut::err(Error::NoSigningKey))
+        }
+    }
+}
@@ -0,0 +1,5 @@
+use crate::database::models::AuditCheckpoint;
+use super::errors::Error;
+
+// Nothing is checkpointed if the log hasn't changed since the last checkpoint
+actor_command_new! (Checkpoint() -> Result<Option<AuditCheckpoint>, Error>);
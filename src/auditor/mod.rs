@@ -0,0 +1,188 @@
+pub mod errors;
+pub mod messages;
+mod handlers;
+
+use std::fs;
+use std::path::PathBuf;
+use chrono::{Utc, Timelike};
+use futures::{Future, future};
+use actix::{Actor, ActorFuture, Context, Addr, AsyncContext, WrapFuture, fut};
+use crate::database::DbExecutor;
+use crate::database::models::{AuditCheckpoint, NewAuditCheckpoint};
+use crate::database::messages::{GetAuditHead, GetAuditCheckpoints, AddAuditCheckpoint};
+use crate::cryptoutil::CryptoUtil;
+use crate::config::AUDIT_CHECKPOINT_INTERVAL;
+use self::errors::Error;
+
+// Periodically signs the head of the audit chain, so the log can't be
+// rewritten wholesale without the checkpoints kept elsewhere giving it away
+pub struct Auditor {
+    pub db: Addr<DbExecutor>,
+    pub export: Option<PathBuf>,
+
+    // Nothing is checkpointed without a key to sign with
+    pub signing_key: Option<String>,
+    pub last_entry: Option<i64>
+}
+
+impl Auditor {
+    pub fn new(db: Addr<DbExecutor>, export: Option<PathBuf>, signing_key: Option<String>) -> Self {
+        Auditor {
+            db,
+            export,
+            signing_key,
+            last_entry: None
+        }
+    }
+
+    pub fn checkpoint(&mut self, signing_key: String) -> impl ActorFuture<Item = Option<AuditCheckpoint>, Error = Error, Actor = Self> {
+        let db = self.db.clone();
+        let known = self.last_entry;
+
+        self.db.send(GetAuditHead {}).flatten().from_err()
+            .and_then(move |head| {
+                // Only the first checkpoint after starting has to look up where the last one was
+                let last: Box<dyn Future<Item = Option<i64>, Error = Error>> = match known {
+                    Some(_) => Box::new(future::ok(known)),
+                    None => Box::new(db.send(GetAuditCheckpoints { before: None, limit: 1 }).flatten().from_err()
+                        .map(|latest| latest.first().map(|checkpoint| checkpoint.entry_id)))
+                };
+
+                last.and_then(move |last| {
+                    let (entry_id, entry_hash) = match head {
+                        Some((entry_id, _)) if Some(entry_id) == last => return future::Either::A(future::ok((last, None))),
+                        Some(head) => head,
+                        None => return future::Either::A(future::ok((last, None)))
+                    };
+
+                    let mut checkpoint = NewAuditCheckpoint {
+                        created_at: Utc::now().naive_utc().with_nanosecond(0).unwrap(),
+                        entry_id,
+                        entry_hash,
+                        public_key: CryptoUtil::public_key(&signing_key),
+                        signature: String::new()
+                    };
+
+                    checkpoint.signature = CryptoUtil::sign(&signing_key, &checkpoint.message());
+
+                    future::Either::B(db.send(AddAuditCheckpoint { checkpoint }).flatten().from_err()
+                        .map(move |checkpoint| (last, Some(checkpoint))))
+                })
+            })
+            .into_actor(self)
+            .and_then(|(last, checkpoint), act, _| {
+                act.last_entry = checkpoint.as_ref().map(|checkpoint| checkpoint.entry_id).or(last);
+
+                if let Some(checkpoint) = &checkpoint {
+                    info!("audit checkpoint {} at entry {}", checkpoint.id, checkpoint.entry_id);
+
+                    if let Err(e) = act.export(checkpoint) {
+                        return fut::err(e);
+                    }
+                }
+
+                fut::ok(checkpoint)
+            })
+    }
+
+    // Written next to the previous ones under a temporary name first, so whatever
+    // picks them up never sees half a checkpoint
+    fn export(&self, checkpoint: &AuditCheckpoint) -> Result<(), Error> {
+        let dir = match &self.export {
+            Some(dir) => dir,
+            None => return Ok(())
+        };
+
+        let contents = serde_json::json!({
+            "id": checkpoint.id,
+            "created_at": checkpoint.created_at,
+            "entry_id": checkpoint.entry_id,
+            "entry_hash": checkpoint.entry_hash,
+            "public_key": checkpoint.public_key,
+            "signature": checkpoint.signature,
+            "message": checkpoint.message()
+        });
+
+        let path = dir.join(format!("checkpoint-{}.json", checkpoint.id));
+        let partial = dir.join(format!(".checkpoint-{}.json.partial", checkpoint.id));
+
+        fs::create_dir_all(dir)?;
+        fs::write(&partial, contents.to_string())?;
+        fs::rename(&partial, &path)?;
+
+        Ok(())
+    }
+}
+
+impl Actor for Auditor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let signing_key = match self.signing_key.clone() {
+            Some(key) => key,
+            None => return
+        };
+
+        ctx.run_interval(*AUDIT_CHECKPOINT_INTERVAL, move |act, ctx| {
+            ctx.spawn(act.checkpoint(signing_key.clone())
+                .map(|_, _, _| ())
+                .map_err(|e, _, _| error!("unable to checkpoint the audit log: {}", e)));
+        });
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix::{Actor, Addr, SystemRunner};
+    use futures::Future;
+    use chrono::Utc;
+    use crate::database::testing::{self, send};
+    use crate::database::messages::RecordAudit;
+    use crate::database::models::NewAuditEntry;
+    use super::Auditor;
+    use super::messages::Checkpoint;
+    use crate::database::models::AuditCheckpoint;
+
+    fn checkpoint(sys: &mut SystemRunner, auditor: &Addr<Auditor>) -> Option<AuditCheckpoint> {
+        sys.block_on(auditor.send(Checkpoint {}).flatten()).unwrap()
+    }
+
+    #[test]
+    fn the_log_is_only_checkpointed_when_it_has_changed() {
+        let (mut sys, db) = testing::executor();
+        let auditor = Auditor::new(db.clone(), None, Some("audit secret".into())).start();
+
+        assert!(checkpoint(&mut sys, &auditor).is_none());
+
+        send(&mut sys, &db, RecordAudit { entry: NewAuditEntry {
+            occurred_at: Utc::now().naive_utc(),
+            subject: None,
+            client_ip: None,
+            action: "test".into(),
+            fqdn: None,
+            version: None,
+            friendly_name: None,
+            is_private: None,
+            target: None,
+            details: None,
+            result: "success".into(),
+            previous_hash: None,
+            hash: None
+        }}).unwrap();
+
+        let first = checkpoint(&mut sys, &auditor).expect("the new entry should have been checkpointed");
+        assert_eq!(first.public_key, crate::cryptoutil::CryptoUtil::public_key("audit secret"));
+        assert!(checkpoint(&mut sys, &auditor).is_none());
+    }
+
+    #[test]
+    fn nothing_is_checkpointed_without_a_signing_key() {
+        let (mut sys, db) = testing::executor();
+        let auditor = Auditor::new(db, None, None).start();
+
+        match sys.block_on(auditor.send(Checkpoint {}).flatten()) {
+            Err(super::errors::Error::NoSigningKey) => (),
+            _ => panic!("a checkpoint can't be signed without a key")
+        }
+    }
+}
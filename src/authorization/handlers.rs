@@ -1,60 +1,100 @@
-use chrono::Utc;
-use actix::Handler;
+use chrono::{Utc, NaiveDateTime, Duration};
+use actix::{Addr, Handler};
 use futures::Future;
 use jwt::{encode, decode};
 use crate::errors::ServiceError;
-use crate::cryptoutil::CryptoUtil;
 use crate::database::messages::*;
+use crate::database::DbExecutor;
+use crate::database::models::{User, ApiKey, RefreshTokenEntry, DomainPermission, split_credential};
+use crate::cryptoutil::CryptoUtil;
 use crate::config::{ADMIN_PASSWORD, JWT_SHARED_SECRET, JWT_VALIDATION, JWT_ISSUER, JWT_AUDIENCE, JWT_HEADER};
 use super::models::*;
+use super::permissions;
 use super::messages::*;
 use super::AuthorizationManager;
 
 impl Handler<AuthorizeUser> for AuthorizationManager {
-    type Result = Result<Vec<Claim>, ServiceError>;
+    type Result = Result<Authorization, ServiceError>;
 
     fn handle(&mut self, msg: AuthorizeUser, _: &mut Self::Context) -> Self::Result {
 
-        // This is the only account that can actually make changes
-        if msg.friendly_name == "admin" {
-            if msg.password == *ADMIN_PASSWORD { 
-                return Ok(vec![Claim { subject: "*".into(), permission: "*".into() }]);
-            } else {
-                return Err(ServiceError::Unauthorized);
+        // The bootstrap administrator, unless it has been disabled
+        if let Some(admin_password) = ADMIN_PASSWORD.as_ref() {
+            if msg.friendly_name == "admin" {
+                if msg.password == *admin_password { 
+                    return Ok(Authorization { subject: "admin".into(), key_id: None, claims: vec![permissions::administrator()] });
+                } else {
+                    return Err(ServiceError::Unauthorized);
+                }
             }
         }
 
+        let (key_id, secret) = split_credential(&msg.password);
+        let key_id = key_id.map(String::from);
+        let secret = secret.to_string();
+
+        let db = self.db.clone();
         self.db.send(GetUserByName { friendly_name: msg.friendly_name.clone() }).flatten()
-            .map_err(|e| e.into())
-            .and_then(move |user|
-                if CryptoUtil::check_key(&msg.password, &user.hashed_key) {
-                    Ok(user)
-                } else {
-                    Err(ServiceError::Unauthorized)
+            .and_then(move |user: User| {
+                db.send(GetApiKey { user_id: user.id.clone(), id: key_id }).flatten()
+                    .map(move |key| (user, key))
+            })
+            .map_err(|e| match e {
+                crate::database::errors::Error::DataNotFound(_) => ServiceError::Unauthorized,
+                e => e.into()
+            })
+            .and_then(move |(user, key): (User, ApiKey)| {
+                // Only the key the credential names is checked, which decides what the claims are restricted to
+                if user.disabled || !key.accepts_key(&secret, Utc::now().naive_utc()) {
+                    return Err(ServiceError::Unauthorized);
                 }
-            ).and_then(move |user: crate::database::models::User| {
-                self.db.send(GetUserPermissions{ id: user.id }).flatten()
-                    .map_err(|e| e.into())
-                    .and_then(move |permissions: Vec<crate::database::models::DomainPermission>| {
-                        Ok(permissions.into_iter().map(|permission|
-                            Claim {
-                                subject: permission.fqdn,
-                                permission: permission.permission
-                            }
-                        ).collect())
+
+                Ok((user, key))
+            })
+            .and_then(move |(user, key)| {
+                self.db.do_send(TouchApiKey { id: key.id.clone() });
+
+                user_claims(&self.db, user.id.clone())
+                    .map(move |claims| Authorization {
+                        claims: permissions::restrict(claims, key.domains(), key.permissions()),
+                        subject: user.id,
+                        key_id: Some(key.id)
                     })
             }).wait()
     }
 }
 
+// Everything the user holds right now, expanded: the permissions their groups
+// grant on their domains, and their roles, their own and their groups'
+fn user_claims(db: &Addr<DbExecutor>, user_id: String) -> impl Future<Item = Vec<Claim>, Error = ServiceError> {
+    db.send(GetUserPermissions { id: user_id.clone() }).flatten()
+        .join(db.send(GetUserRoles { id: user_id, inherited: true }).flatten())
+        .map_err(|e| e.into())
+        .map(|(domain_permissions, roles): (Vec<DomainPermission>, Vec<String>)| {
+            let claims = domain_permissions.into_iter().map(|permission|
+                Claim {
+                    subject: permission.fqdn,
+                    permission: permission.permission
+                }
+            ).chain(roles.iter().map(|role| permissions::role(role))).collect();
+
+            permissions::expand(claims)
+        })
+}
+
 impl Handler<AuthorizeToken> for AuthorizationManager {
-    type Result = Result<Vec<Claim>, ServiceError>;
+    type Result = Result<Token, ServiceError>;
 
     fn handle(&mut self, msg: AuthorizeToken, _: &mut Self::Context) -> Self::Result {
 
         decode::<Token>(&msg.token, JWT_SHARED_SECRET.as_ref(), &JWT_VALIDATION)
             .map_err(|e| e.into())
-            .and_then(|token| Ok(token.claims.claims))
+            .and_then(|token| if token.claims.typ.is_some() {
+                // Refresh tokens are only good for getting access tokens
+                Err(ServiceError::Unauthorized)
+            } else {
+                Ok(token.claims)
+            })
     }
 }
 
@@ -65,14 +105,30 @@ impl Handler<BuildTokenFromClaims> for AuthorizationManager {
 
         let now = Utc::now().timestamp();
         let token = Token {
+            sub: msg.subject,
+            jti: CryptoUtil::generate_uuid(),
             iat: now,
             nbf: now,
             exp: now + msg.lifetime.num_seconds(),
             aud: JWT_AUDIENCE.to_string(),
             iss: JWT_ISSUER.to_string(),
-            claims: msg.claims
+            claims: msg.claims,
+            typ: if msg.refresh { Some(REFRESH_TOKEN_TYPE.into()) } else { None }
         };
 
+        if msg.refresh {
+            self.db.send(RecordRefreshToken {
+                entry: RefreshTokenEntry {
+                    jti: token.jti.clone(),
+                    subject: token.sub.clone(),
+                    issued_at: NaiveDateTime::from_timestamp(token.iat, 0),
+                    expires_at: NaiveDateTime::from_timestamp(token.exp, 0),
+                    revoked_at: None,
+                    key_id: msg.key_id
+                }
+            }).flatten().wait()?;
+        }
+
         encode::<Token>(&JWT_HEADER, &token, JWT_SHARED_SECRET.as_ref()).map_err(|e| e.into())
     }
 }
@@ -81,20 +137,222 @@ impl Handler<RefreshToken> for AuthorizationManager {
     type Result = Result<String, ServiceError>;
 
     fn handle(&mut self, msg: RefreshToken, _: &mut Self::Context) -> Self::Result {
-        decode::<Token>(&msg.token, JWT_SHARED_SECRET.as_ref(), &JWT_VALIDATION)
-            .map_err(|e| e.into())
-            .and_then(|token| {
-                let now = Utc::now().timestamp();
-                let token = Token {
-                    iat: now,
-                    nbf: now,
-                    exp: core::cmp::min(now + msg.lifetime.num_seconds(), token.claims.exp),
-                    aud: JWT_AUDIENCE.to_string(),
-                    iss: JWT_ISSUER.to_string(),
-                    claims: token.claims.claims
-                };
-
-                encode::<Token>(&JWT_HEADER, &token, JWT_SHARED_SECRET.as_ref()).map_err(|e| e.into())
-            })
+        let token = decode::<Token>(&msg.token, JWT_SHARED_SECRET.as_ref(), &JWT_VALIDATION)?;
+
+        // Only refresh tokens which were handed out and haven't been revoked since are
+        // accepted. Tokens issued before they were recorded can't be revoked, so they're refused
+        let entry = if token.claims.jti.is_empty() || token.claims.typ.as_ref().map(String::as_str) != Some(REFRESH_TOKEN_TYPE) {
+            None
+        } else {
+            match self.db.send(GetRefreshToken { jti: token.claims.jti.clone() }).flatten().wait() {
+                Ok(entry) => Some(entry).filter(|entry| entry.revoked_at.is_none() && entry.subject == token.claims.sub),
+                Err(crate::database::errors::Error::DataNotFound(_)) => None,
+                Err(e) => return Err(e.into())
+            }
+        };
+
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return Err(ServiceError::Unauthorized)
+        };
+
+        // Users who have been disabled or removed since the token was issued don't get
+        // a new one. Neither do tokens too old to say who they were issued to
+        let user = if token.claims.sub.is_empty() {
+            None
+        } else if token.claims.sub == "admin" && ADMIN_PASSWORD.is_some() {
+            return self.reissue(token.claims, vec![permissions::administrator()], msg.lifetime);
+        } else {
+            match self.db.send(GetUser { id: token.claims.sub.clone() }).flatten().wait() {
+                Ok(user) => Some(user).filter(|user| !user.disabled),
+                Err(crate::database::errors::Error::DataNotFound(_)) => None,
+                Err(e) => return Err(e.into())
+            }
+        };
+
+        let user = match user {
+            Some(user) => user,
+            None => return Err(ServiceError::Unauthorized)
+        };
+
+        // Nor do tokens whose key has expired or been revoked since, or which were
+        // recorded before their keys were. Revoking a key revokes its tokens as well,
+        // but a key which merely expired is still around
+        let key = match entry.key_id {
+            Some(key_id) => match self.db.send(GetApiKey { user_id: user.id.clone(), id: Some(key_id) }).flatten().wait() {
+                Ok(key) => Some(key).filter(|key| key.expires_at.map_or(true, |expires_at| expires_at > Utc::now().naive_utc())),
+                Err(crate::database::errors::Error::DataNotFound(_)) => None,
+                Err(e) => return Err(e.into())
+            },
+            None => None
+        };
+
+        let key = match key {
+            Some(key) => key,
+            None => return Err(ServiceError::Unauthorized)
+        };
+
+        // Claims are looked up again, so whatever was taken away from the user, their groups
+        // or the scope of their key since is gone from the new token
+        let claims = user_claims(&self.db, user.id).wait()?;
+        let claims = permissions::restrict(claims, key.domains(), key.permissions());
+
+        self.reissue(token.claims, claims, msg.lifetime)
     }
-}
\ No newline at end of file
+}
+
+impl AuthorizationManager {
+    // A new access token for the subject of a refresh token, which expires no later than the latter
+    fn reissue(&self, refresh: Token, claims: Vec<Claim>, lifetime: Duration) -> Result<String, ServiceError> {
+
+        let now = Utc::now().timestamp();
+        let token = Token {
+            sub: refresh.sub,
+            jti: CryptoUtil::generate_uuid(),
+            iat: now,
+            nbf: now,
+            exp: core::cmp::min(now + lifetime.num_seconds(), refresh.exp),
+            aud: JWT_AUDIENCE.to_string(),
+            iss: JWT_ISSUER.to_string(),
+            claims,
+            typ: None
+        };
+
+        encode::<Token>(&JWT_HEADER, &token, JWT_SHARED_SECRET.as_ref()).map_err(|e| e.into())
+    }
+}
+
+impl Handler<RevokeToken> for AuthorizationManager {
+    type Result = Result<(), ServiceError>;
+
+    // Tokens which aren't valid, or aren't refresh tokens, have nothing to revoke
+    fn handle(&mut self, msg: RevokeToken, _: &mut Self::Context) -> Self::Result {
+        match decode::<Token>(&msg.token, JWT_SHARED_SECRET.as_ref(), &JWT_VALIDATION) {
+            Ok(token) if !token.claims.jti.is_empty() => self.db
+                .send(RevokeRefreshToken { jti: token.claims.jti }).flatten().wait()
+                .map_err(|e| e.into()),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use actix::{Arbiter, Addr, SystemRunner};
+    use crate::database::{testing::{self, send}, DbExecutor};
+    use super::*;
+
+    fn manager() -> (SystemRunner, Addr<DbExecutor>, Addr<AuthorizationManager>) {
+        std::env::set_var("RUBLIC_SHARED_SECRET", "test secret");
+
+        // The manager blocks on the database, so it can't share the thread the executor is fed from
+        let (sys, db) = testing::executor();
+        let dbref = db.clone();
+        let manager = Arbiter::start(move |_| AuthorizationManager { db: dbref });
+        (sys, db, manager)
+    }
+
+    fn unauthorized<T>(result: Result<T, ServiceError>) -> bool {
+        match result {
+            Err(ServiceError::Unauthorized) => true,
+            _ => false
+        }
+    }
+
+    fn authorize(sys: &mut SystemRunner, manager: &Addr<AuthorizationManager>, credential: String) -> Result<Authorization, ServiceError> {
+        sys.block_on(manager.send(AuthorizeUser { friendly_name: "alice".into(), password: credential }).flatten())
+    }
+
+    // A refresh token for alice, as the password grant hands out
+    fn login(sys: &mut SystemRunner, manager: &Addr<AuthorizationManager>, credential: String) -> String {
+        let authorization = authorize(sys, manager, credential).unwrap();
+
+        sys.block_on(manager.send(BuildTokenFromClaims {
+            subject: authorization.subject,
+            key_id: authorization.key_id,
+            claims: authorization.claims,
+            lifetime: Duration::days(1),
+            refresh: true
+        }).flatten()).unwrap()
+    }
+
+    fn refresh(sys: &mut SystemRunner, manager: &Addr<AuthorizationManager>, token: &str) -> Result<Vec<Claim>, ServiceError> {
+        let access = sys.block_on(manager.send(RefreshToken { token: token.into(), lifetime: Duration::hours(1) }).flatten())?;
+        let token = sys.block_on(manager.send(AuthorizeToken { token: access }).flatten())?;
+        Ok(token.claims)
+    }
+
+    fn create_key(sys: &mut SystemRunner, db: &Addr<DbExecutor>, user_id: &str, label: &str, secret: &str,
+        expires_at: Option<NaiveDateTime>, scope_permissions: Option<Vec<String>>) -> ApiKey {
+
+        send(sys, db, CreateApiKey {
+            user_id: user_id.into(),
+            label: label.into(),
+            hashed_key: CryptoUtil::hash_key(secret),
+            expires_at,
+            scope_domains: None,
+            scope_permissions
+        }).unwrap()
+    }
+
+    #[test]
+    fn credentials_are_only_checked_against_the_key_they_name() {
+        let (mut sys, db, manager) = manager();
+        let (user, default) = send(&mut sys, &db, CreateUser { friendly_name: "alice".into(), hashed_key: CryptoUtil::hash_key("first") }).unwrap();
+        let other = create_key(&mut sys, &db, &user.id, "laptop", "second", None, None);
+
+        let authorization = authorize(&mut sys, &manager, default.credential("first")).unwrap();
+        assert_eq!(authorization.subject, user.id);
+        assert_eq!(authorization.key_id, Some(default.id.clone()));
+        assert_eq!(authorize(&mut sys, &manager, other.credential("second")).unwrap().key_id, Some(other.id.clone()));
+        assert!(unauthorized(authorize(&mut sys, &manager, other.credential("first"))));
+        assert!(unauthorized(authorize(&mut sys, &manager, format!("{}.first", CryptoUtil::generate_uuid()))));
+
+        // Credentials from before they named their keys can only be default keys
+        assert_eq!(authorize(&mut sys, &manager, "first".into()).unwrap().key_id, Some(default.id));
+        assert!(unauthorized(authorize(&mut sys, &manager, "second".into())));
+    }
+
+    #[test]
+    fn refreshed_tokens_only_keep_what_the_user_and_their_key_still_hold() {
+        let (mut sys, db, manager) = manager();
+        let (user, _) = send(&mut sys, &db, CreateUser { friendly_name: "alice".into(), hashed_key: String::new() }).unwrap();
+        let group = send(&mut sys, &db, CreateGroup { friendly_name: "managers".into(), permission: "public".into() }).unwrap();
+        send(&mut sys, &db, SetGroupRoles { id: group.id.clone(), roles: vec![permissions::USERS_MANAGE.into(), permissions::GROUPS_MANAGE.into()] }).unwrap();
+        send(&mut sys, &db, SetGroupUsers { user_ids: vec![user.id.clone()], group_id: group.id.clone() }).unwrap();
+
+        let key = create_key(&mut sys, &db, &user.id, "scoped", "secret", None, Some(vec![permissions::USERS_MANAGE.into()]));
+        let token = login(&mut sys, &manager, key.credential("secret"));
+        assert_eq!(refresh(&mut sys, &manager, &token).unwrap(), vec![permissions::role(permissions::USERS_MANAGE)]);
+
+        // A role taken away from the user's group doesn't survive a refresh
+        send(&mut sys, &db, SetGroupRoles { id: group.id.clone(), roles: vec![permissions::GROUPS_MANAGE.into()] }).unwrap();
+        assert!(refresh(&mut sys, &manager, &token).unwrap().is_empty());
+
+        // Nor does the token itself once its key is gone
+        send(&mut sys, &db, RevokeApiKey { user_id: user.id.clone(), key: key.id.clone() }).unwrap();
+        assert!(unauthorized(refresh(&mut sys, &manager, &token)));
+
+        // Or has expired
+        let expired = create_key(&mut sys, &db, &user.id, "expired", "secret", Some(Utc::now().naive_utc()), None);
+        let token = sys.block_on(manager.send(BuildTokenFromClaims {
+            subject: user.id.clone(),
+            key_id: Some(expired.id),
+            claims: vec![],
+            lifetime: Duration::days(1),
+            refresh: true
+        }).flatten()).unwrap();
+        assert!(unauthorized(refresh(&mut sys, &manager, &token)));
+
+        let key = create_key(&mut sys, &db, &user.id, "unscoped", "secret", None, None);
+        let token = login(&mut sys, &manager, key.credential("secret"));
+        assert_eq!(refresh(&mut sys, &manager, &token).unwrap(), vec![permissions::role(permissions::GROUPS_MANAGE)]);
+
+        // Tokens follow the user rather than their name
+        send(&mut sys, &db, UpdateUser { id: user.id.clone(), friendly_name: Some("bob".into()), disabled: None }).unwrap();
+        assert!(refresh(&mut sys, &manager, &token).is_ok());
+
+        send(&mut sys, &db, UpdateUser { id: user.id, friendly_name: None, disabled: Some(true) }).unwrap();
+        assert!(unauthorized(refresh(&mut sys, &manager, &token)));
+    }
+}
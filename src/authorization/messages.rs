@@ -1,7 +1,8 @@
 use chrono::Duration;
 use super::models::*;
 
-actor_command! (AuthorizeUser(friendly_name: String, password: String) -> Vec<Claim>);
-actor_command! (AuthorizeToken(token: String) -> Vec<Claim>);
-actor_command! (BuildTokenFromClaims(claims: Vec<Claim>, lifetime: Duration) -> String);
-actor_command! (RefreshToken(token: String, lifetime: Duration) -> String);
\ No newline at end of file
+actor_command! (AuthorizeUser(friendly_name: String, password: String) -> Authorization);
+actor_command! (AuthorizeToken(token: String) -> Token);
+actor_command! (BuildTokenFromClaims(subject: String, key_id: Option<String>, claims: Vec<Claim>, lifetime: Duration, refresh: bool) -> String);
+actor_command! (RefreshToken(token: String, lifetime: Duration) -> String);
+actor_command! (RevokeToken(token: String) -> ());
\ No newline at end of file
@@ -1,5 +1,4 @@
 use futures::Future;
-use std::collections::HashSet;
 use actix_web::{HttpRequest, Result, FromRequest, ResponseError};
 use actix_web::middleware::{Middleware, Started};
 use actix_web_httpauth::extractors::{
@@ -9,18 +8,11 @@ use actix_web_httpauth::extractors::{
 
 use crate::errors::ServiceError;
 use crate::app::AppState;
+use crate::database::messages::ResolveDomain;
 use super::messages::*;
 use super::models::*;
 use super::ValidateClaim;
-
-fn expand_implied_claims(claim: &str) -> &[&str] {
-    if claim == "private" {
-        return &["public", "private"];
-    } else if claim == "public" {
-        return &["public"];
-    }
-    &[]
-}
+use super::permissions;
 
 pub struct ClaimsProviderMiddleware { }
 
@@ -34,17 +26,9 @@ impl Middleware<AppState> for ClaimsProviderMiddleware {
                     friendly_name: basic.username().into(),
                     password: basic.password().unwrap_or("").into()
                 }).flatten().wait()
-                .and_then(|claims| {
-                    let expanded_claims: HashSet<Claim> = claims.iter().map(move |claim|{
-                        expand_implied_claims(&claim.permission).iter().map(move |permission| {
-                            Claim {
-                                subject: claim.subject.clone(),
-                                permission: permission.to_string()
-                            }
-                        })
-                    }).flatten().collect();
-
-                    req.extensions_mut().insert(expanded_claims);
+                .and_then(|authorization| {
+                    req.extensions_mut().insert(permissions::expand(authorization.claims));
+                    req.extensions_mut().insert(Identity(authorization.subject));
                     Ok(())
                 });
 
@@ -56,8 +40,9 @@ impl Middleware<AppState> for ClaimsProviderMiddleware {
                 .send(AuthorizeToken { 
                     token: bearer.token().into()
                 }).flatten().wait()
-                .and_then(|claims| {
-                    req.extensions_mut().insert(claims);
+                .and_then(|token| {
+                    req.extensions_mut().insert(permissions::expand(token.claims));
+                    req.extensions_mut().insert(Identity(token.sub));
                     Ok(())
                 });
 
@@ -81,4 +66,27 @@ impl Middleware<AppState> for ClaimsCheckerMiddleware {
             Err(e) => Ok(Started::Response(ServiceError::from(e).error_response()))
         }
     }
+}
+
+pub struct DomainResolverMiddleware {
+    pub resource: String
+}
+
+impl Middleware<AppState> for DomainResolverMiddleware {
+    fn start(&self, req: &HttpRequest<AppState>) -> Result<Started> {
+        let fqdn = match req.match_info().get(&self.resource) {
+            Some(fqdn) => fqdn.to_string(),
+            None => return Ok(Started::Done)
+        };
+
+        // Names that don't resolve are left as they are, since it's up
+        // to the endpoint to decide whether a missing domain is an error
+        if let Ok(domain) = req.state().db.send(ResolveDomain { fqdn }).flatten().wait() {
+            let mut resolved = req.extensions_mut().remove::<ResolvedResources>().unwrap_or_default();
+            resolved.0.insert(self.resource.clone(), domain.fqdn);
+            req.extensions_mut().insert(resolved);
+        }
+
+        Ok(Started::Done)
+    }
 }
\ No newline at end of file
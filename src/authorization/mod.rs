@@ -2,6 +2,7 @@ pub mod models;
 pub mod messages;
 pub mod middleware;
 pub mod errors;
+pub mod permissions;
 mod handlers;
 
 use actix::{Actor, Addr, Context};
@@ -11,7 +12,7 @@ use crate::database::DbExecutor;
 use crate::app::AppState;
 use self::models::*;
 use self::errors::Error;
-pub use self::middleware::{ClaimsCheckerMiddleware, ClaimsProviderMiddleware};
+pub use self::middleware::{ClaimsCheckerMiddleware, ClaimsProviderMiddleware, DomainResolverMiddleware};
 
 
 pub struct AuthorizationManager {
@@ -24,20 +25,51 @@ impl Actor for AuthorizationManager {
 
 pub trait ValidateClaim {
     fn validate_claims(&self, required_claims: &[Claim]) -> Result<(), Error>;
+    fn claimed_subjects(&self, permission: &str) -> Option<Vec<String>>;
 }
 
 impl<S> ValidateClaim for HttpRequest<S> {
+    // Every subject the request has the given permission for, or nothing at
+    // all for the administrator and domain managers, who have it for everything
+    fn claimed_subjects(&self, permission: &str) -> Option<Vec<String>> {
+        match self.extensions().get::<Vec<Claim>>() {
+            Some(actual_claims) => {
+                if actual_claims.contains(&permissions::administrator())
+                    || actual_claims.contains(&permissions::role(permissions::DOMAINS_MANAGE)) {
+                    return None;
+                }
+
+                Some(actual_claims.iter()
+                    .filter(|claim| claim.permission == permission)
+                    .map(|claim| claim.subject.clone())
+                    .collect())
+            },
+            None => Some(Vec::new())
+        }
+    }
+
     fn validate_claims(&self, required_claims: &[Claim]) -> Result<(), Error> {
         if let Some(actual_claims) = self.extensions().get::<Vec<Claim>>() {
 
             // Short-circuit in the case of the administrator
-            if actual_claims.contains(&Claim { subject: "*".into(), permission: "*".into()}) {
+            if actual_claims.contains(&permissions::administrator()) {
                 return Ok(())
             }
 
             let params = self.match_info();
+            let resolved = self.extensions();
+            let resolved = resolved.get::<ResolvedResources>();
 
             for required_claim in required_claims {
+                // Roles aren't tied to any resource, they're either held or not
+                if required_claim.subject == "*" {
+                    if actual_claims.contains(required_claim) {
+                        continue;
+                    }
+
+                    return Err(Error::NotAuthorized(required_claim.permission.clone(), required_claim.subject.clone()));
+                }
+
                 // Required claims are stated in the form of (parameter_name, permission)
                 // where the parameter is extracted from the URL using the parameter_name
                 // and checked against  the actual claims attached to the request.
@@ -55,14 +87,22 @@ impl<S> ValidateClaim for HttpRequest<S> {
                 // 
                 // ... was present in the HttpRequest's claims.
 
-                match params.get(&required_claim.subject) {
+                // ... unless the parameter was resolved to another name, like a domain alias
+                // pointing to its canonical domain, in which case the latter is checked instead
+
+                let subject = resolved.and_then(|r| r.0.get(&required_claim.subject))
+                    .map(|subject| subject.as_str())
+                    .or_else(|| params.get(&required_claim.subject));
+
+                match subject {
                     Some(subject) => {
                         let resolved_claim = Claim { 
                             subject: subject.into(), 
                             permission: required_claim.permission.clone()
                         };
 
-                        if actual_claims.contains(&resolved_claim) {
+                        if actual_claims.contains(&resolved_claim)
+                            || actual_claims.contains(&permissions::role(permissions::DOMAINS_MANAGE)) {
                             continue;
                         }
 
@@ -81,6 +121,7 @@ impl<S> ValidateClaim for HttpRequest<S> {
 
 pub trait ResourceAuthorization {
     fn authorize_resource(self, resource: &str, permission: &str) -> Self;
+    fn resolve_domain(self, resource: &str) -> Self;
 }
 
 impl ResourceAuthorization for actix_web::Scope<AppState> {
@@ -92,4 +133,10 @@ impl ResourceAuthorization for actix_web::Scope<AppState> {
             })
         })
     }
+
+    fn resolve_domain(self, resource: &str) -> Self {
+        self.middleware(DomainResolverMiddleware {
+            resource: resource.to_string()
+        })
+    }
 }
\ No newline at end of file
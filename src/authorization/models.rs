@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use serde_derive::{Serialize, Deserialize};
 
 #[derive(PartialEq, Eq, Hash, Debug, Serialize, Deserialize, Clone)]
@@ -6,13 +7,41 @@ pub struct Claim {
     pub permission: String
 }
 
+// Maps resource parameters onto the name they resolved to, so aliased
+// resources are checked against the claims of what they point to
+#[derive(Default)]
+pub struct ResolvedResources(pub HashMap<String, String>);
+
+pub const REFRESH_TOKEN_TYPE: &str = "refresh";
+
+// The id of the user a request was made by, whether they logged in directly
+// or through a token issued to them, or admin for the bootstrap administrator
+pub struct Identity(pub String);
+
+// Who a user is, as their id, what they were authorized for, and with
+// which of their keys. The bootstrap administrator has none
+pub struct Authorization {
+    pub subject: String,
+    pub key_id: Option<String>,
+    pub claims: Vec<Claim>
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Token {
+    // The id of the user, which unlike their name never changes, or admin for the bootstrap
+    // administrator. Tokens issued before subjects were recorded have none
+    #[serde(default)]
+    pub sub: String,
+    // Refresh tokens are looked up by it when they're used, so they can be revoked
+    #[serde(default)]
+    pub jti: String,
     pub iat: i64,
     pub exp: i64,
     pub nbf: i64,
     pub iss: String,
     pub aud: String,
-    pub claims: Vec<Claim>
+    pub claims: Vec<Claim>,
+    // Refresh tokens say they are, so they can't be passed off as access tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub typ: Option<String>
 }
\ No newline at end of file
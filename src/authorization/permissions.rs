@@ -0,0 +1,107 @@
+use super::models::Claim;
+
+// Every permission a group can grant its members on its domains, from the least
+// to the most, along with everything it implies. This is the only place the
+// hierarchy is declared, claims are expanded according to it once a request
+// has been authenticated
+const PERMISSIONS: &[(&str, &[&str])] = &[
+    // Versions and dates of the certificates, but none of the files
+    ("metadata", &["metadata"]),
+    ("public", &["metadata", "public"]),
+    ("private", &["metadata", "public", "private"]),
+    // Deciding which groups the domain belongs to
+    ("manage", &["metadata", "public", "private", "manage"])
+];
+
+pub const DEFAULT_PERMISSION: &str = "public";
+
+// Administrative roles, held by users directly or through their groups. They
+// aren't tied to any one domain, so they're claimed on the "*" subject
+pub const USERS_MANAGE: &str = "users:manage";
+pub const GROUPS_MANAGE: &str = "groups:manage";
+// Every domain at every permission level, along with creating and removing them
+pub const DOMAINS_MANAGE: &str = "domains:manage";
+pub const AUDIT_READ: &str = "audit:read";
+
+const ROLES: &[&str] = &[USERS_MANAGE, GROUPS_MANAGE, DOMAINS_MANAGE, AUDIT_READ];
+
+// Only held by the bootstrap administrator, and implies everything else
+pub fn administrator() -> Claim {
+    role("*")
+}
+
+pub fn role(name: &str) -> Claim {
+    Claim {
+        subject: "*".into(),
+        permission: name.into()
+    }
+}
+
+pub fn is_role(name: &str) -> bool {
+    ROLES.contains(&name)
+}
+
+pub fn roles() -> &'static [&'static str] {
+    ROLES
+}
+
+pub fn is_known(permission: &str) -> bool {
+    PERMISSIONS.iter().any(|(name, _)| *name == permission)
+}
+
+pub fn known() -> Vec<&'static str> {
+    PERMISSIONS.iter().map(|(name, _)| *name).collect()
+}
+
+fn implied(permission: &str) -> &'static [&'static str] {
+    PERMISSIONS.iter()
+        .find(|(name, _)| *name == permission)
+        .map(|(_, implied)| *implied)
+        .unwrap_or(&[])
+}
+
+// Only those of the claims, which have to be expanded already, that a scoped key
+// allows. Permissions in the scope imply others just like claims do, and a key
+// restricted to some domains can't be used for any roles
+pub fn restrict(claims: Vec<Claim>, domains: Option<Vec<String>>, allowed: Option<Vec<String>>) -> Vec<Claim> {
+    let allowed: Option<Vec<&str>> = allowed.as_ref().map(|allowed| allowed.iter()
+        .flat_map(|permission| if is_role(permission) {
+            vec![permission.as_str()]
+        } else {
+            implied(permission).to_vec()
+        })
+        .collect());
+
+    claims.into_iter()
+        .filter(|claim| domains.as_ref().map_or(true, |domains| domains.contains(&claim.subject)))
+        .filter(|claim| allowed.as_ref().map_or(true, |allowed| allowed.contains(&claim.permission.as_str())))
+        .collect()
+}
+
+// Every claim along with those it implies. Roles stand on their own,
+// and permissions which aren't known grant nothing
+pub fn expand(claims: Vec<Claim>) -> Vec<Claim> {
+    let mut expanded: Vec<Claim> = Vec::with_capacity(claims.len());
+
+    for claim in claims {
+        if claim.subject == "*" {
+            if !expanded.contains(&claim) {
+                expanded.push(claim);
+            }
+            continue;
+        }
+
+        for permission in implied(&claim.permission) {
+            let implied_claim = Claim {
+                subject: claim.subject.clone(),
+                permission: permission.to_string()
+            };
+
+            if !expanded.contains(&implied_claim) {
+                expanded.push(implied_claim);
+            }
+        }
+    }
+
+    expanded
+}
@@ -6,9 +6,15 @@ pub enum Error {
     #[fail(display = "Certificate Parse Error")]
     ParseError,
 
+    #[fail(display = "Unrecognized File: {}", _0)]
+    UnrecognizedFile(String),
+
     #[fail(display = "Invalid Certificate: {}", _0)]
     InvalidCertificate(String),
 
+    #[fail(display = "Incomplete Version: {}", _0)]
+    IncompleteVersion(String),
+
     #[fail(display = "Service Error: {}", _0)]
     ServiceError(crate::errors::ServiceError),
 
@@ -19,6 +25,22 @@ pub enum Error {
     Unknown
 }
 
+impl Error {
+    // A short, stable name for the kind of error, as recorded in the quarantine
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::FileError(_) => "file",
+            Error::ParseError => "parse",
+            Error::UnrecognizedFile(_) => "unrecognized",
+            Error::InvalidCertificate(_) => "invalid",
+            Error::IncompleteVersion(_) => "incomplete",
+            Error::ServiceError(_) => "service",
+            Error::DatabaseError(_) => "database",
+            Error::Unknown => "unknown"
+        }
+    }
+}
+
 impl From<actix::MailboxError> for Error {
     fn from(_: actix::MailboxError) -> Self {
         Error::Unknown
@@ -2,37 +2,18 @@ use actix::Handler;
 use futures::Future;
 use std::io::Read;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use openssl::x509::X509;
-use chrono::{NaiveDateTime};
-use crate::database::messages::{GetDomainByFqdn, DeleteCertificateByPath, AddCertificateToDomain};
-use crate::database::models::Certificate;
-use crate::config::CERT_PATTERN;
-use super::CertificateManager;
+use chrono::{NaiveDateTime, Utc};
+use crate::database::messages::{GetDomainByFqdn, DeleteCertificateByPath, DeleteCertificatesExcept, AddCertificatesToDomain,
+    SetLiveVersion, ClearLiveVersions, QuarantineFiles, ReleaseFiles};
+use crate::database::models::{Certificate, QuarantinedFile};
+use crate::cryptoutil::CryptoUtil;
+use super::{CertificateManager, parse_filename, fingerprint, resolve_link};
 use super::messages::*;
 use super::models::*;
 use super::errors::Error;
 
-fn parse_filename(filename: &str) -> Result<(String, i32), Error> {
-    match CERT_PATTERN.captures(&filename) {
-        Some(names) => {
-            if names.len() != 4 {
-                return Err(Error::Unknown);
-            }
-
-            if let (Some(name), Some(version), Some(ext)) = (names.get(1), names.get(2), names.get(3)) {
-                if let Ok(version) = version.as_str().parse::<i32>() {
-                    return Ok((format!("{}.{}", name.as_str(), ext.as_str()), version));
-                } else {
-                    return Err(Error::ParseError);
-                }
-            } else {
-                return Err(Error::ParseError);
-            }
-        },
-        None => Err(Error::Unknown)
-    }
-}
-
 fn parse_date(date: &openssl::asn1::Asn1TimeRef) -> Result<NaiveDateTime, Error> {
     let datestr = &format!("{}", &date);
 
@@ -80,53 +61,149 @@ fn read_pem_file(filename: &str) -> Result<PemFileContents, Error> {
 use std::io::Error as IoError;
 use std::io::ErrorKind::InvalidInput;
 
-impl Handler<CertificateDiscovered> for CertificateManager {
-    type Result = Result<Certificate, Error>;
-
-    fn handle(&mut self, msg: CertificateDiscovered, _: &mut Self::Context) -> Self::Result {
-        let path = msg.path;
-        let fqdn = msg.fqdn;
-
-        let path_str: String = path.to_string_lossy().into();
-        let filename: String = path.file_name()
-            .ok_or_else(|| Error::FileError(IoError::from(InvalidInput)))?
-            .to_string_lossy().into();
-
-        parse_filename(&filename).and_then(|(friendly_name, version)| {
-        read_pem_file(&path_str).and_then(|contents| {
-            self.db.send(GetDomainByFqdn { fqdn }).flatten()
-                .and_then(|domain| {
-                    match contents {
-                        PemFileContents::PublicCertificate(cert) => {
-                            Ok(Certificate {
-                                is_private: false,
-                                id: version,
-                                domain_id: domain.id,
-                                friendly_name,
-                                path: path_str,
-                                not_after: Some(cert.not_after),
-                                not_before: Some(cert.not_before)
-                            })
-                        },
-                        PemFileContents::PrivateKey(_) => {
-                            Ok(Certificate {
-                                is_private: true,
-                                id: version,
-                                domain_id: domain.id,
-                                friendly_name,
-                                path: path_str,
-                                not_before: None,
-                                not_after: None
-                            })
-                        }
-                    }
-                })
-                .from_err()
-                .wait()
+fn read_certificate(domain_id: &str, version: i32, path: &Path) -> Result<Certificate, Error> {
+    let path_str: String = path.to_string_lossy().into();
+    let filename: String = path.file_name()
+        .ok_or_else(|| Error::FileError(IoError::from(InvalidInput)))?
+        .to_string_lossy().into();
+
+    let (friendly_name, file_version) = parse_filename(&filename)?;
+
+    if file_version != version {
+        return Err(Error::InvalidCertificate(format!("{} does not belong to version {}", filename, version)));
+    }
+
+    let (modified_at, hash) = fingerprint(path)?;
+
+    match read_pem_file(&path_str)? {
+        PemFileContents::PublicCertificate(cert) => {
+            Ok(Certificate {
+                is_private: false,
+                id: version,
+                domain_id: domain_id.to_string(),
+                friendly_name,
+                path: path_str,
+                not_after: Some(cert.not_after),
+                not_before: Some(cert.not_before),
+                modified_at: Some(modified_at),
+                hash: Some(hash)
+            })
+        },
+        PemFileContents::PrivateKey(_) => {
+            Ok(Certificate {
+                is_private: true,
+                id: version,
+                domain_id: domain_id.to_string(),
+                friendly_name,
+                path: path_str,
+                not_before: None,
+                not_after: None,
+                modified_at: Some(modified_at),
+                hash: Some(hash)
             })
-        }).and_then(|cert| {
-            self.db.send(AddCertificateToDomain { cert }).flatten().from_err().wait()
-        })
+        }
+    }
+}
+
+fn quarantined(fqdn: &str, path: &Path, error: &Error) -> QuarantinedFile {
+    let path_str: String = path.to_string_lossy().into();
+
+    QuarantinedFile {
+        hashed_path: CryptoUtil::hash_string(&path_str),
+        path: path_str,
+        fqdn: fqdn.to_string(),
+        kind: error.kind().into(),
+        message: error.to_string(),
+        hash: fingerprint(path).ok().map(|(_, hash)| hash),
+        occurred_at: Utc::now().naive_utc()
+    }
+}
+
+impl CertificateManager {
+    // Records why files couldn't be ingested. Failing to do so shouldn't hide the original error
+    fn quarantine(&self, files: Vec<QuarantinedFile>) {
+        for file in &files {
+            warn!("quarantining {}: {}", file.path, file.message);
+        }
+
+        if let Err(e) = self.db.send(QuarantineFiles { files }).flatten().wait() {
+            error!("unable to quarantine files: {}", e);
+        }
+    }
+
+    fn release(&self, paths: &[PathBuf]) {
+        let paths = paths.iter().map(|path| path.to_string_lossy().into()).collect();
+
+        if let Err(e) = self.db.send(ReleaseFiles { paths }).flatten().wait() {
+            error!("unable to release files from quarantine: {}", e);
+        }
+    }
+}
+
+impl Handler<VersionDiscovered> for CertificateManager {
+    type Result = Result<Vec<Certificate>, Error>;
+
+    fn handle(&mut self, msg: VersionDiscovered, _: &mut Self::Context) -> Self::Result {
+        let VersionDiscovered { fqdn, version, paths } = msg;
+
+        let domain = match self.db.send(GetDomainByFqdn { fqdn: fqdn.clone() }).flatten().wait() {
+            Ok(domain) => domain,
+            Err(e) => {
+                let e = Error::from(e);
+                self.quarantine(paths.iter().map(|path| quarantined(&fqdn, path, &e)).collect());
+                return Err(e);
+            }
+        };
+
+        // Every file has to parse before any of them are added, otherwise
+        // a half-written version could end up being served as the latest
+        let (certs, failures): (Vec<_>, Vec<_>) = paths.iter()
+            .map(|path| (path, read_certificate(&domain.id, version, path)))
+            .partition(|(_, result)| result.is_ok());
+
+        if !failures.is_empty() {
+            self.quarantine(failures.iter()
+                .filter_map(|(path, result)| result.as_ref().err().map(|e| quarantined(&fqdn, path, e)))
+                .collect());
+
+            return Err(failures.into_iter().filter_map(|(_, result)| result.err()).next().unwrap());
+        }
+
+        let certs = certs.into_iter().filter_map(|(_, result)| result.ok()).collect();
+
+        match self.db.send(AddCertificatesToDomain { certs }).flatten().wait() {
+            Ok(certs) => {
+                self.release(&paths);
+                Ok(certs)
+            },
+            Err(e) => {
+                let e = Error::from(e);
+                self.quarantine(paths.iter().map(|path| quarantined(&fqdn, path, &e)).collect());
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Handler<VersionIncomplete> for CertificateManager {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: VersionIncomplete, _: &mut Self::Context) -> Self::Result {
+        let error = Error::IncompleteVersion(format!("version {} is missing {}", msg.version, msg.missing.join(", ")));
+
+        self.quarantine(msg.paths.iter().map(|path| quarantined(&msg.fqdn, path, &error)).collect());
+        Ok(())
+    }
+}
+
+impl Handler<FileUnrecognized> for CertificateManager {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: FileUnrecognized, _: &mut Self::Context) -> Self::Result {
+        let filename: String = msg.path.file_name().unwrap().to_string_lossy().into();
+
+        self.quarantine(vec![quarantined(&msg.fqdn, &msg.path, &Error::UnrecognizedFile(filename))]);
+        Ok(())
     }
 }
 
@@ -134,6 +211,9 @@ impl Handler<CertificateDisappeared> for CertificateManager {
     type Result = Result<(), Error>;
 
     fn handle(&mut self, msg: CertificateDisappeared, _: &mut Self::Context) -> Self::Result {
+        // Whatever was wrong with the file went away along with it
+        self.release(&[msg.path.clone()]);
+
         self.db.send(DeleteCertificateByPath{ 
                 path: msg.path.to_string_lossy().into()  
             })
@@ -144,6 +224,71 @@ impl Handler<CertificateDisappeared> for CertificateManager {
     }
 }
 
+impl Handler<ReconcileDomain> for CertificateManager {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: ReconcileDomain, _: &mut Self::Context) -> Self::Result {
+        let domain = self.db.send(GetDomainByFqdn { fqdn: msg.fqdn }).flatten().wait()?;
+        let removed = self.db.send(DeleteCertificatesExcept {
+                domain_id: domain.id,
+                paths: msg.paths.iter().map(|path| path.to_string_lossy().into()).collect()
+            }).flatten().wait()?;
+
+        if removed > 0 {
+            info!("removed {} certificates of {} which no longer exist", removed, domain.fqdn);
+        }
+
+        Ok(())
+    }
+}
+
+impl Handler<LiveLinkChanged> for CertificateManager {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: LiveLinkChanged, _: &mut Self::Context) -> Self::Result {
+        let friendly_name: String = msg.link.file_name().unwrap().to_string_lossy().into();
+
+        // Certbot replaces links by renaming a new one over them, so
+        // one that has disappeared is really gone
+        if std::fs::symlink_metadata(&msg.link).is_err() {
+            info!("live link removed: {}", msg.link.to_string_lossy());
+            return self.db.send(ClearLiveVersions {
+                    fqdn: msg.fqdn,
+                    friendly_name: Some(friendly_name)
+                })
+                .flatten()
+                .from_err()
+                .wait();
+        }
+
+        let (friendly_name, version) = resolve_link(&msg.link)?;
+
+        info!("live {} of {} is version {}", friendly_name, msg.fqdn, version);
+        self.db.send(SetLiveVersion {
+                fqdn: msg.fqdn,
+                friendly_name,
+                version
+            })
+            .flatten()
+            .from_err()
+            .wait()
+    }
+}
+
+impl Handler<LiveDomainRemoved> for CertificateManager {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: LiveDomainRemoved, _: &mut Self::Context) -> Self::Result {
+        self.db.send(ClearLiveVersions {
+                fqdn: msg.fqdn,
+                friendly_name: None
+            })
+            .flatten()
+            .from_err()
+            .wait()
+    }
+}
+
 impl Handler<GetCertificateByPath> for CertificateManager {
     type Result = Result<SingleCertificate, Error>;
 
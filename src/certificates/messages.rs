@@ -3,6 +3,11 @@ use crate::database::models::Certificate;
 use super::errors::Error;
 use super::models::*;
 
-actor_command_new! (CertificateDiscovered(path: PathBuf, fqdn: String) -> Result<Certificate, Error>);
+actor_command_new! (VersionDiscovered(fqdn: String, version: i32, paths: Vec<PathBuf>) -> Result<Vec<Certificate>, Error>);
 actor_command_new! (CertificateDisappeared(path: PathBuf) -> Result<(), Error>);
+actor_command_new! (VersionIncomplete(fqdn: String, version: i32, paths: Vec<PathBuf>, missing: Vec<String>) -> Result<(), Error>);
+actor_command_new! (FileUnrecognized(fqdn: String, path: PathBuf) -> Result<(), Error>);
+actor_command_new! (ReconcileDomain(fqdn: String, paths: Vec<PathBuf>) -> Result<(), Error>);
+actor_command_new! (LiveLinkChanged(fqdn: String, link: PathBuf) -> Result<(), Error>);
+actor_command_new! (LiveDomainRemoved(fqdn: String) -> Result<(), Error>);
 actor_command_new! (GetCertificateByPath(path: String) -> Result<SingleCertificate, Error>);
\ No newline at end of file
@@ -3,8 +3,17 @@ pub mod models;
 pub mod errors;
 mod handlers;
 
+use std::io::Read;
+use std::fs::{File, read_dir};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 use actix::{Actor, Context, Addr};
+use chrono::NaiveDateTime;
 use crate::database::DbExecutor;
+use crate::cryptoutil::CryptoUtil;
+use crate::config::{CERT_PATTERN, EXPECTED_FILES};
+use self::errors::Error;
 
 pub struct CertificateManager {
     pub db: Addr<DbExecutor>
@@ -12,4 +21,109 @@ pub struct CertificateManager {
 
 impl Actor for CertificateManager {
     type Context = Context<Self>;
-}
\ No newline at end of file
+}
+
+// Splits a certbot archive filename like cert3.pem into its
+// friendly name (cert.pem) and version (3)
+pub fn parse_filename(filename: &str) -> Result<(String, i32), Error> {
+    match CERT_PATTERN.captures(&filename) {
+        Some(names) => {
+            if names.len() != 4 {
+                return Err(Error::Unknown);
+            }
+
+            if let (Some(name), Some(version), Some(ext)) = (names.get(1), names.get(2), names.get(3)) {
+                if let Ok(version) = version.as_str().parse::<i32>() {
+                    return Ok((format!("{}.{}", name.as_str(), ext.as_str()), version));
+                } else {
+                    return Err(Error::ParseError);
+                }
+            } else {
+                return Err(Error::ParseError);
+            }
+        },
+        None => Err(Error::UnrecognizedFile(filename.to_string()))
+    }
+}
+
+// The files of a single version of a certificate, recognised the same way ingestion does
+// rather than by the names they're expected to have, along with the expected ones still missing
+pub fn version_paths(dir: &Path, version: i32) -> std::io::Result<(Vec<PathBuf>, Vec<String>)> {
+    let mut paths = Vec::new();
+    let mut names = HashSet::new();
+
+    for path in read_dir(dir)?.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|path| path.is_file()) {
+        if let Ok((friendly_name, file_version)) = parse_filename(&path.file_name().unwrap().to_string_lossy()) {
+            if file_version == version {
+                names.insert(friendly_name.split('.').next().unwrap_or("").to_string());
+                paths.push(path);
+            }
+        }
+    }
+
+    paths.sort();
+
+    let missing = EXPECTED_FILES.iter()
+        .filter(|name| !names.contains(*name))
+        .cloned()
+        .collect();
+
+    Ok((paths, missing))
+}
+
+// The friendly name and version a live/ symlink like cert.pem -> ../../archive/example.com/cert3.pem points at
+pub fn resolve_link(link: &Path) -> Result<(String, i32), Error> {
+    let target = std::fs::read_link(link).map_err(Error::FileError)?;
+
+    let filename = target.file_name()
+        .ok_or_else(|| Error::InvalidCertificate(format!("{} points at a directory", link.to_string_lossy())))?;
+
+    let (friendly_name, version) = parse_filename(&filename.to_string_lossy())?;
+
+    // A cert.pem pointing at a chain would have the wrong file served as the latest one
+    if link.file_name().map_or(true, |name| name.to_string_lossy() != friendly_name) {
+        return Err(Error::InvalidCertificate(format!("{} points at a {}", link.to_string_lossy(), friendly_name)));
+    }
+
+    Ok((friendly_name, version))
+}
+
+// Modification time and hash of a file, used to tell whether it changed after it was ingested
+pub fn fingerprint(path: &Path) -> Result<(NaiveDateTime, String), Error> {
+    let mut bytes = Vec::new();
+
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map_err(Error::FileError)?;
+
+    let modified = modified.duration_since(UNIX_EPOCH)
+        .map_err(|_| Error::InvalidCertificate(format!("{} was modified before 1970", path.to_string_lossy())))?;
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(Error::FileError)?;
+
+    Ok((NaiveDateTime::from_timestamp(modified.as_secs() as i64, 0), CryptoUtil::hash_bytes(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use super::version_paths;
+
+    #[test]
+    fn versions_are_made_up_of_whatever_ingestion_recognises() {
+        let dir = std::env::temp_dir().join(format!("rublic-version-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+
+        for name in &["cert2.crt", "chain2.pem", "fullchain2.pem", "cert3.pem", "README"] {
+            write(dir.join(name), b"").unwrap();
+        }
+
+        let (paths, missing) = version_paths(&dir, 2).unwrap();
+        assert_eq!(paths, vec![dir.join("cert2.crt"), dir.join("chain2.pem"), dir.join("fullchain2.pem")]);
+        assert_eq!(missing, vec!["privkey".to_string()]);
+
+        remove_dir_all(&dir).ok();
+    }
+}
@@ -1,21 +1,115 @@
 use std::env;
+use std::net::IpAddr;
 use std::path::PathBuf;
 use regex::Regex;
 use chrono::Duration;
+use std::time::Duration as StdDuration;
 use jwt::{Header, Algorithm, Validation};
+use crate::cryptoutil::CryptoUtil;
 
 lazy_static! {
-    pub static ref ADMIN_PASSWORD: String = env::var("RUBLIC_ADMIN_PASSWORD")
-        .expect("RUBLIC_ADMIN_PASSWORD was not defined!");
+    // The bootstrap administrator can do anything, and is only meant for handing out
+    // roles to the first few users. Leaving the password empty or unset disables it
+    pub static ref ADMIN_PASSWORD: Option<String> = env::var("RUBLIC_ADMIN_PASSWORD").ok()
+        .filter(|password| !password.is_empty());
 
-    pub static ref CERT_PATTERN: Regex = Regex::new(r"(\w+)([0-9]+)\.(\w+)").unwrap();
+    // The name must not swallow digits, or cert12.pem would be read as version 2
+    pub static ref CERT_PATTERN: Regex = Regex::new(r"^([^0-9]+)([0-9]+)\.(\w+)$").unwrap();
+
+    // certbot appends -0001, -0002 etc. to a lineage whose name is already taken
+    pub static ref LINEAGE_PATTERN: Regex = Regex::new(r"^(.+)-([0-9]{4})$").unwrap();
 
     pub static ref DATABASE_URL: String = env::var("RUBLIC_DATABASE_URL")
         .expect("RUBLIC_DATABASE_URL must be set");
 
+    // Apply pending migrations at startup, rather than refusing to start until they are
+    pub static ref AUTO_MIGRATE: bool = env::var("RUBLIC_AUTO_MIGRATE")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
     pub static ref LETSENCRYPT_ARCHIVE: PathBuf = PathBuf::from(env::var("LETSENCRYPT_ARCHIVE")
         .unwrap_or_else(|_| "/etc/letsencrypt/archive".into()));
 
+    // Only archive directories whose names match INCLUDE_DOMAINS and don't
+    // match EXCLUDE_DOMAINS become domains. Both are optional
+    pub static ref INCLUDE_DOMAINS: Option<Regex> = env::var("RUBLIC_INCLUDE_DOMAINS").ok()
+        .map(|pattern| Regex::new(&pattern).expect("RUBLIC_INCLUDE_DOMAINS is not a valid regex!"));
+
+    pub static ref EXCLUDE_DOMAINS: Option<Regex> = env::var("RUBLIC_EXCLUDE_DOMAINS").ok()
+        .map(|pattern| Regex::new(&pattern).expect("RUBLIC_EXCLUDE_DOMAINS is not a valid regex!"));
+
+    // In manual mode, only directories of domains registered through the API are watched,
+    // rather than every directory becoming a domain of its own
+    pub static ref MANUAL_WATCH_MODE: bool = env::var("RUBLIC_WATCH_MODE")
+        .map(|mode| mode.eq_ignore_ascii_case("manual"))
+        .unwrap_or(false);
+
+    // Certbot's notion of the current version of each file, as symlinks into the archive
+    pub static ref LETSENCRYPT_LIVE: PathBuf = env::var("LETSENCRYPT_LIVE")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| LETSENCRYPT_ARCHIVE.with_file_name("live"));
+
+    // Serve the highest version as the latest one, regardless of where live/ points
+    pub static ref LATEST_BY_MAX_ID: bool = env::var("RUBLIC_LATEST_BY_MAX_ID")
+        .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+
+    // Archive and live roots on filesystems which never deliver inotify events
+    // (NFS and the like) are scanned every POLL_INTERVAL instead
+    pub static ref POLLED_ARCHIVES: Vec<PathBuf> = env::var("RUBLIC_POLLED_ARCHIVES")
+        .map(|archives| archives.split(',')
+            .filter(|archive| !archive.trim().is_empty())
+            .map(|archive| PathBuf::from(archive.trim()))
+            .collect())
+        .unwrap_or_else(|_| Vec::new());
+
+    pub static ref POLL_INTERVAL: StdDuration = StdDuration::from_secs(env::var("RUBLIC_POLL_INTERVAL")
+        .ok().and_then(|interval| interval.parse().ok())
+        .unwrap_or(10));
+
+    // Certbot writes the files of a new version one by one, so a version is only
+    // ingested once all of these exist and nothing has changed for SETTLE_TIMEOUT
+    pub static ref EXPECTED_FILES: Vec<String> = env::var("RUBLIC_EXPECTED_FILES")
+        .unwrap_or_else(|_| "cert,chain,fullchain,privkey".into())
+        .split(',').map(|name| name.trim().to_string()).collect();
+
+    pub static ref RECONCILE_INTERVAL: StdDuration = StdDuration::from_secs(env::var("RUBLIC_RECONCILE_INTERVAL")
+        .ok().and_then(|interval| interval.parse().ok())
+        .unwrap_or(3600));
+
+    pub static ref SETTLE_TIMEOUT: StdDuration = StdDuration::from_millis(env::var("RUBLIC_SETTLE_TIMEOUT_MS")
+        .ok().and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(2000));
+
+    // Signs the checkpoints of the audit chain, which are optionally also written to
+    // AUDIT_EXPORT_DIR to be kept somewhere else. Without one, the chain is still kept
+    // and can be verified, but nothing vouches for it from outside the database
+    pub static ref AUDIT_SIGNING_KEY: Option<String> = env::var("RUBLIC_AUDIT_SIGNING_KEY").ok()
+        .filter(|key| !key.is_empty());
+
+    // Public keys of earlier signing keys, whose checkpoints still verify after the key has been replaced
+    pub static ref AUDIT_TRUSTED_KEYS: Vec<String> = env::var("RUBLIC_AUDIT_TRUSTED_KEYS")
+        .map(|keys| keys.split(',')
+            .filter(|key| !key.trim().is_empty())
+            .map(|key| key.trim().to_lowercase())
+            .collect())
+        .unwrap_or_else(|_| Vec::new());
+
+    pub static ref AUDIT_CHECKPOINT_INTERVAL: StdDuration = StdDuration::from_secs(env::var("RUBLIC_AUDIT_CHECKPOINT_INTERVAL")
+        .ok().and_then(|interval| interval.parse().ok())
+        .unwrap_or(3600));
+
+    pub static ref AUDIT_EXPORT_DIR: Option<PathBuf> = env::var("RUBLIC_AUDIT_EXPORT_DIR").ok()
+        .map(PathBuf::from);
+
+    // Reverse proxies in front of rublic, whose X-Forwarded-For is believed when recording
+    // the client of a request. Anyone else could claim any address in there
+    pub static ref TRUSTED_PROXIES: Vec<IpAddr> = env::var("RUBLIC_TRUSTED_PROXIES")
+        .map(|proxies| proxies.split(',')
+            .filter(|proxy| !proxy.trim().is_empty())
+            .map(|proxy| proxy.trim().parse().expect("RUBLIC_TRUSTED_PROXIES must be a list of IP addresses!"))
+            .collect())
+        .unwrap_or_else(|_| Vec::new());
 
     // JWT settings
     pub static ref JWT_ACCESS_LIFETIME: Duration = Duration::hours(1);
@@ -43,9 +137,32 @@ lazy_static! {
     };
 }
 
+// Checkpoints verify when they are signed by one of these: the configured ones and the
+// current signing key, if it's set. Verifying the log doesn't take the signing key itself
+pub fn audit_trusted_keys() -> Vec<String> {
+    let mut keys = AUDIT_TRUSTED_KEYS.clone();
+    keys.extend(AUDIT_SIGNING_KEY.as_ref().map(|secret| CryptoUtil::public_key(secret)));
+    keys
+}
+
 pub fn initialize() {
     lazy_static::initialize(&ADMIN_PASSWORD);
     lazy_static::initialize(&DATABASE_URL);
     lazy_static::initialize(&LETSENCRYPT_ARCHIVE);
+    lazy_static::initialize(&INCLUDE_DOMAINS);
+    lazy_static::initialize(&EXCLUDE_DOMAINS);
     lazy_static::initialize(&JWT_SHARED_SECRET);
+    lazy_static::initialize(&AUDIT_SIGNING_KEY);
+    lazy_static::initialize(&TRUSTED_PROXIES);
+
+    // Whoever can mint tokens mustn't also be able to vouch for a rewritten log
+    match AUDIT_SIGNING_KEY.as_ref() {
+        Some(key) if *key == *JWT_SHARED_SECRET => panic!("RUBLIC_AUDIT_SIGNING_KEY must differ from RUBLIC_SHARED_SECRET!"),
+        Some(_) => (),
+        None => warn!("RUBLIC_AUDIT_SIGNING_KEY is not set, so the audit log won't be checkpointed")
+    }
+
+    if ADMIN_PASSWORD.is_none() {
+        info!("the bootstrap administrator is disabled");
+    }
 }
\ No newline at end of file
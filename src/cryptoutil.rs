@@ -4,7 +4,8 @@ use uuid::Uuid;
 use crypto::{
     pbkdf2::{pbkdf2_check, pbkdf2_simple}, 
     sha2::Sha256,
-    digest::Digest
+    digest::Digest,
+    ed25519
 };
 
 pub struct CryptoUtil {}
@@ -30,6 +31,53 @@ impl CryptoUtil {
         hasher.result_str()
     }
 
+    pub fn hash_bytes(bytes: &[u8]) -> String {
+        let mut hasher = Sha256::new();
+        hasher.input(bytes);
+
+        hasher.result_str()
+    }
+
+    pub fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+        if hex.len() % 2 != 0 {
+            return None;
+        }
+
+        (0..hex.len()).step_by(2)
+            .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+            .collect()
+    }
+
+    // The ed25519 key pair derived from the given secret, so the same secret always signs the same way
+    fn keypair(secret: &str) -> ([u8; 64], [u8; 32]) {
+        let mut seed = [0u8; 32];
+        let mut hasher = Sha256::new();
+        hasher.input_str(secret);
+        hasher.result(&mut seed);
+
+        ed25519::keypair(&seed)
+    }
+
+    pub fn public_key(secret: &str) -> String {
+        Self::to_hex(&Self::keypair(secret).1)
+    }
+
+    pub fn sign(secret: &str, message: &str) -> String {
+        Self::to_hex(&ed25519::signature(message.as_bytes(), &Self::keypair(secret).0))
+    }
+
+    pub fn verify_signature(public_key: &str, message: &str, signature: &str) -> bool {
+        match (Self::from_hex(public_key), Self::from_hex(signature)) {
+            (Some(public_key), Some(signature)) => public_key.len() == 32 && signature.len() == 64
+                && ed25519::verify(message.as_bytes(), &public_key, &signature),
+            _ => false
+        }
+    }
+
     pub fn generate_uuid() -> String {
         Uuid::new_v4().to_string()
     }
@@ -0,0 +1,235 @@
+use std::sync::Mutex;
+use diesel::prelude::*;
+use chrono::Timelike;
+use crate::schema::*;
+use crate::cryptoutil::CryptoUtil;
+use super::DbConnection;
+use super::models::*;
+use super::errors::Error;
+
+const BATCH_SIZE: i64 = 1000;
+
+lazy_static! {
+    // Two entries recorded at once would otherwise both link to the same
+    // predecessor, forking the chain. Rublic is the only writer of the log
+    static ref CHAIN: Mutex<()> = Mutex::new(());
+}
+
+// Links the entry to the current head of the chain and records it
+pub fn append(conn: &DbConnection, mut entry: NewAuditEntry) -> Result<(), Error> {
+    let _guard = CHAIN.lock().unwrap_or_else(|e| e.into_inner());
+
+    // Not every backend keeps fractions of seconds, and the hash has
+    // to match whatever is read back when the chain is verified
+    entry.occurred_at = entry.occurred_at.with_nanosecond(0).unwrap();
+
+    conn.transaction::<_, Error, _>(|| {
+        entry.previous_hash = head(conn)?.map(|(_, hash)| hash);
+        entry.hash = Some(entry.compute_hash());
+
+        diesel::insert_into(audit_log::table)
+            .values(&entry)
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+// The id and hash of the latest chained entry
+pub fn head(conn: &DbConnection) -> Result<Option<(i64, String)>, Error> {
+    let latest = audit_log::table
+        .filter(audit_log::hash.is_not_null())
+        .order(audit_log::id.desc())
+        .select((audit_log::id, audit_log::hash))
+        .first::<(i64, Option<String>)>(conn)
+        .optional()?;
+
+    Ok(latest.and_then(|(id, hash)| hash.map(|hash| (id, hash))))
+}
+
+// Walks the whole chain from the first chained entry, and checks every checkpoint
+// against it, stopping at the first break. Checkpoints have to be signed by one of
+// the trusted public keys
+pub fn verify(conn: &DbConnection, trusted_keys: &[String]) -> Result<ChainVerification, Error> {
+    let mut verification = ChainVerification {
+        entries: 0,
+        unchained: 0,
+        checkpoints: 0,
+        head: None,
+        first_break: None
+    };
+
+    let mut last_id = 0;
+    let mut previous: Option<(i64, String)> = None;
+
+    'walk: loop {
+        let entries = audit_log::table
+            .filter(audit_log::id.gt(last_id))
+            .order(audit_log::id.asc())
+            .limit(BATCH_SIZE)
+            .load::<AuditEntry>(conn)?;
+
+        if entries.is_empty() {
+            break;
+        }
+
+        for entry in &entries {
+            last_id = entry.id;
+
+            let reason = match (&entry.hash, &previous) {
+                (None, None) => {
+                    verification.unchained += 1;
+                    continue;
+                },
+                (None, Some(_)) => Some("the entry has no hash".to_string()),
+                (Some(hash), previous) => {
+                    let expected_previous = previous.as_ref().map(|(_, hash)| hash);
+
+                    if entry.previous_hash.as_ref() != expected_previous {
+                        Some(match previous {
+                            Some((id, _)) => format!("the entry doesn't follow entry {}", id),
+                            None => "the first chained entry links to another entry".to_string()
+                        })
+                    } else if &NewAuditEntry::from(entry).compute_hash() != hash {
+                        Some("the contents of the entry don't match its hash".to_string())
+                    } else {
+                        None
+                    }
+                }
+            };
+
+            if let Some(reason) = reason {
+                verification.first_break = Some(ChainBreak { id: entry.id, reason });
+                break 'walk;
+            }
+
+            verification.entries += 1;
+            previous = entry.hash.clone().map(|hash| (entry.id, hash));
+        }
+    }
+
+    verification.head = previous.map(|(_, hash)| hash);
+
+    // Checkpoints catch what the chain itself can't, like entries removed from its end
+    let checkpoints = audit_checkpoints::table
+        .order(audit_checkpoints::entry_id.asc())
+        .load::<AuditCheckpoint>(conn)?;
+
+    for checkpoint in checkpoints {
+        if let Some(ChainBreak { id, .. }) = verification.first_break {
+            if id <= checkpoint.entry_id {
+                break;
+            }
+        }
+
+        let entry_hash = audit_log::table
+            .find(checkpoint.entry_id)
+            .select(audit_log::hash)
+            .first::<Option<String>>(conn)
+            .optional()?;
+
+        // The key stored along with a checkpoint only says which key to check it
+        // against, anyone rewriting the log could have stored one of their own
+        let reason = if !trusted_keys.contains(&checkpoint.public_key.to_lowercase()) {
+            Some(format!("checkpoint {} is signed by a key which isn't trusted", checkpoint.id))
+        } else if !CryptoUtil::verify_signature(&checkpoint.public_key, &checkpoint.message(), &checkpoint.signature) {
+            Some(format!("the signature of checkpoint {} doesn't match", checkpoint.id))
+        } else {
+            match entry_hash {
+                None => Some(format!("the entry of checkpoint {} is missing", checkpoint.id)),
+                Some(hash) if hash.as_ref() != Some(&checkpoint.entry_hash) =>
+                    Some(format!("the entry doesn't match checkpoint {}", checkpoint.id)),
+                Some(_) => None
+            }
+        };
+
+        if let Some(reason) = reason {
+            verification.first_break = Some(ChainBreak { id: checkpoint.entry_id, reason });
+            break;
+        }
+
+        verification.checkpoints += 1;
+    }
+
+    Ok(verification)
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use chrono::Utc;
+    use crate::database::testing;
+    use super::*;
+
+    fn entry(action: &str) -> NewAuditEntry {
+        NewAuditEntry {
+            occurred_at: Utc::now().naive_utc(),
+            subject: Some("admin".into()),
+            client_ip: None,
+            action: action.into(),
+            fqdn: None,
+            version: None,
+            friendly_name: None,
+            is_private: None,
+            target: None,
+            details: None,
+            result: "success".into(),
+            previous_hash: None,
+            hash: None
+        }
+    }
+
+    fn checkpoint(conn: &DbConnection, secret: &str, public_key: String) {
+        let (entry_id, entry_hash) = head(conn).unwrap().unwrap();
+        let mut checkpoint = NewAuditCheckpoint {
+            created_at: Utc::now().naive_utc().with_nanosecond(0).unwrap(),
+            entry_id,
+            entry_hash,
+            public_key,
+            signature: String::new()
+        };
+        checkpoint.signature = CryptoUtil::sign(secret, &checkpoint.message());
+
+        diesel::insert_into(audit_checkpoints::table).values(&checkpoint).execute(conn).unwrap();
+    }
+
+    fn first_break(conn: &DbConnection, trusted_keys: &[String]) -> Option<String> {
+        verify(conn, trusted_keys).unwrap().first_break.map(|broken| broken.reason)
+    }
+
+    #[test]
+    fn checkpoints_only_verify_against_trusted_keys() {
+        let pool = testing::pool();
+        let conn = pool.get().unwrap();
+        let old_key = CryptoUtil::public_key("old secret");
+        let new_key = CryptoUtil::public_key("new secret");
+
+        append(&conn, entry("one")).unwrap();
+        checkpoint(&conn, "old secret", old_key.clone());
+        append(&conn, entry("two")).unwrap();
+        checkpoint(&conn, "new secret", new_key.clone());
+
+        // Checkpoints of a replaced key keep verifying as long as it's still trusted
+        let verification = verify(&conn, &[new_key.clone(), old_key.clone()]).unwrap();
+        assert!(verification.first_break.is_none());
+        assert_eq!(verification.checkpoints, 2);
+        assert_eq!(first_break(&conn, &[new_key.clone()]).unwrap(), "checkpoint 1 is signed by a key which isn't trusted");
+
+        // Signing with a key of one's own and storing it along with the checkpoint proves nothing
+        append(&conn, entry("three")).unwrap();
+        checkpoint(&conn, "forged secret", CryptoUtil::public_key("forged secret"));
+        assert_eq!(first_break(&conn, &[new_key.clone(), old_key.clone()]).unwrap(),
+                   "checkpoint 3 is signed by a key which isn't trusted");
+    }
+
+    #[test]
+    fn checkpoints_must_be_signed_by_the_key_they_name() {
+        let pool = testing::pool();
+        let conn = pool.get().unwrap();
+        let trusted = CryptoUtil::public_key("secret");
+
+        append(&conn, entry("one")).unwrap();
+        checkpoint(&conn, "forged secret", trusted.clone());
+
+        assert_eq!(first_break(&conn, &[trusted]).unwrap(), "the signature of checkpoint 1 doesn't match");
+    }
+}
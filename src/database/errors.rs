@@ -1,4 +1,5 @@
 use diesel::result::{
+    DatabaseErrorKind,
     Error::{NotFound, DatabaseError, QueryBuilderError, SerializationError, DeserializationError},
 };
 
@@ -16,9 +17,22 @@ pub enum Error {
     #[fail(display = "Incorrect Query: {}", _0)]
     DataIncorrect(String),
 
+    // Ids which were referred to, but don't exist, along with what they were supposed to be
+    #[fail(display = "Unknown {}: {:?}", _0, _1)]
+    UnknownReferences(String, Vec<String>),
+
     #[fail(display = "Diesel Error: {}", _0)]
     DieselError(diesel::result::Error),
 
+    #[fail(display = "Pending Migrations: {}", _0)]
+    PendingMigrations(String),
+
+    #[fail(display = "Unknown Schema Version: {}", _0)]
+    UnknownSchema(String),
+
+    #[fail(display = "Migration Failed: {}", _0)]
+    MigrationFailed(String),
+
     #[fail(display = "Unknown Error")]
     Unknown
 }
@@ -32,7 +46,10 @@ impl From<actix::MailboxError> for Error {
 impl From<diesel::result::Error> for Error {
     fn from(e: diesel::result::Error) -> Self {
         match e {
-            DatabaseError(_kind, info) => {
+            // Only rows clashing with others are conflicts, a connection
+            // dropping or a broken query is an error like any other
+            DatabaseError(DatabaseErrorKind::UniqueViolation, info)
+                | DatabaseError(DatabaseErrorKind::ForeignKeyViolation, info) => {
                 Error::DataConflict((*info).message().into())
             },
             NotFound => Error::DataNotFound("the query returned no results".into()),
@@ -44,4 +61,10 @@ impl From<diesel::result::Error> for Error {
     }
 }
 
+impl From<diesel_migrations::RunMigrationsError> for Error {
+    fn from(e: diesel_migrations::RunMigrationsError) -> Self {
+        Error::MigrationFailed(e.to_string())
+    }
+}
+
 //pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
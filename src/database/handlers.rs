@@ -1,11 +1,91 @@
-use diesel::{prelude::*};
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use diesel::prelude::*;
+use chrono::{NaiveDateTime, Utc, Duration};
 use actix::Handler;
 use crate::schema::*;
-use crate::database::DbExecutor;
+use crate::database::{DbExecutor, DbConnection};
 use crate::cryptoutil::CryptoUtil;
+use crate::config::{LINEAGE_PATTERN, LATEST_BY_MAX_ID, EXPECTED_FILES};
 use super::models::*;
 use super::messages::*;
 use super::errors::Error;
+use super::audit;
+
+// The version certbot's live/ links point at for each file of the domain,
+// or nothing if those aren't known or are not to be used
+fn live_versions(conn: &DbConnection, domain_id: &str) -> Result<HashMap<String, i32>, Error> {
+    if *LATEST_BY_MAX_ID {
+        return Ok(HashMap::new());
+    }
+
+    let hashed_fqdn = domains::table
+        .find(domain_id)
+        .select(domains::hashed_fqdn)
+        .first::<String>(conn)?;
+
+    Ok(live_versions::table
+        .filter(live_versions::hashed_fqdn.eq(&hashed_fqdn))
+        .load::<LiveVersion>(conn)?
+        .into_iter()
+        .map(|live| (live.friendly_name, live.version))
+        .collect())
+}
+
+// The highest version of the domain which has every one of the expected files. Live links
+// may point at a version which hasn't been ingested yet, or never will be, in which case
+// this is served instead of whatever happens to be there of it
+fn latest_complete_version(conn: &DbConnection, domain_id: &str) -> Result<Option<i32>, Error> {
+    let mut versions: HashMap<i32, HashSet<String>> = HashMap::new();
+
+    for (id, friendly_name) in certificates::table
+        .filter(certificates::domain_id.eq(domain_id))
+        .select((certificates::id, certificates::friendly_name))
+        .load::<(i32, String)>(conn)? {
+
+        let name = friendly_name.split('.').next().unwrap_or("").to_string();
+        versions.entry(id).or_insert_with(HashSet::new).insert(name);
+    }
+
+    Ok(versions.into_iter()
+        .filter(|(_, names)| EXPECTED_FILES.iter().all(|name| names.contains(name)))
+        .map(|(id, _)| id)
+        .max())
+}
+
+// Wildcards typed into a search are matched literally
+fn escape_like(q: &str) -> String {
+    q.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+// Narrows a boxed query down to the page described by a ListQuery, fetching one
+// item more than asked for, so the caller can tell whether there's another page
+macro_rules! paginate {
+    ($query:expr, $name:expr, $id:expr, $list:expr) => {{
+        let list: &ListQuery = $list;
+        let mut query = $query;
+
+        if let Some(q) = &list.q {
+            query = query.filter($name.like(format!("%{}%", escape_like(q))).escape('\\'));
+        }
+
+        if let Some((name, id)) = &list.after {
+            query = if list.descending {
+                query.filter($name.lt(name.clone()).or($name.eq(name.clone()).and($id.lt(id.clone()))))
+            } else {
+                query.filter($name.gt(name.clone()).or($name.eq(name.clone()).and($id.gt(id.clone()))))
+            };
+        }
+
+        query = if list.descending {
+            query.order(($name.desc(), $id.desc()))
+        } else {
+            query.order(($name.asc(), $id.asc()))
+        };
+
+        query.limit(list.limit + 1)
+    }};
+}
 
 fn exactly_one<T>(mut items: Vec<T>, name: &str) -> Result<T, Error> {
     match items.len() {
@@ -15,16 +95,58 @@ fn exactly_one<T>(mut items: Vec<T>, name: &str) -> Result<T, Error> {
     }
 }
 
+// Names resolve to the domain carrying them before any alias does. The one exception is a
+// domain which was archived when a lineage got promoted over it, as the name was handed
+// over to the lineage then
+fn lookup_domain(conn: &DbConnection, fqdn: &str) -> Result<Option<Domain>, Error> {
+    let named = domains::table
+        .filter(domains::fqdn.eq(fqdn))
+        .first::<Domain>(conn)
+        .optional()?;
+
+    if let Some(domain) = &named {
+        if !domain.archived {
+            return Ok(named);
+        }
+    }
+
+    let aliased = domain_aliases::table
+        .filter(domain_aliases::hashed_fqdn.eq(CryptoUtil::hash_string(fqdn)))
+        .inner_join(domains::table)
+        .select((domains::id, domains::fqdn, domains::hashed_fqdn, domains::archived))
+        .first::<Domain>(conn)
+        .optional()?;
+
+    Ok(aliased.or(named))
+}
+
+// A name can't be both a domain and an alias, or which of them it refers to would depend on the lookup
+fn check_unaliased(conn: &DbConnection, fqdn: &str) -> Result<(), Error> {
+    let aliased = domain_aliases::table
+        .find(CryptoUtil::hash_string(fqdn))
+        .select(domain_aliases::domain_id)
+        .first::<String>(conn)
+        .optional()?;
+
+    match aliased {
+        Some(_) => Err(Error::DataConflict(format!("{} is already an alias", fqdn))),
+        None => Ok(())
+    }
+}
+
 impl Handler<CreateDomain> for DbExecutor {
     type Result = Result<Domain, Error>;
 
     fn handle(&mut self, msg: CreateDomain, _: &mut Self::Context) -> Self::Result {
         info!("creating domain: {}", msg.fqdn);
         self.with_connection(|conn| {
+            check_unaliased(conn, &msg.fqdn)?;
+
             let domain = Domain {
                 id: CryptoUtil::generate_uuid(),
                 hashed_fqdn: CryptoUtil::hash_string(&msg.fqdn),
                 fqdn: msg.fqdn,
+                archived: false
             };
 
             diesel::insert_into(domains::table)
@@ -40,9 +162,89 @@ impl Handler<DeleteDomain> for DbExecutor {
     type Result = Result<(), Error>;
 
     fn handle(&mut self, msg: DeleteDomain, _: &mut Self::Context) -> Self::Result {
+        // Only ever the domain carrying the name, never the one an alias of that name points at
+        self.with_connection(|conn| {
+            let rows = diesel::delete(domains::table)
+                .filter(domains::fqdn.eq(&msg.fqdn))
+                .execute(conn)?;
+
+            if rows > 0 {
+                return Ok(());
+            }
+
+            check_unaliased(conn, &msg.fqdn)?;
+            Err(Error::DataNotFound("domain not found".into()))
+        })
+    }
+}
+
+impl Handler<RenameDomain> for DbExecutor {
+    type Result = Result<Domain, Error>;
+
+    fn handle(&mut self, msg: RenameDomain, _: &mut Self::Context) -> Self::Result {
+        info!("renaming domain {} to {}", msg.fqdn, msg.new_fqdn);
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                let domain = domains::table
+                    .filter(domains::fqdn.eq(&msg.fqdn))
+                    .load::<Domain>(conn)
+                    .map_err(|e| e.into())
+                    .and_then(move |f| exactly_one(f, "domain"))?;
+
+                check_unaliased(conn, &msg.new_fqdn)?;
+
+                let domain = Domain {
+                    hashed_fqdn: CryptoUtil::hash_string(&msg.new_fqdn),
+                    fqdn: msg.new_fqdn,
+                    archived: false,
+                    ..domain
+                };
+
+                // Fails on the unique hash if the new name is already taken
+                diesel::update(domains::table.find(&domain.id))
+                    .set((
+                        domains::fqdn.eq(&domain.fqdn),
+                        domains::hashed_fqdn.eq(&domain.hashed_fqdn),
+                        domains::archived.eq(false)
+                    ))
+                    .execute(conn)?;
+
+                // The files haven't changed, only the directory holding them has
+                let certs = certificates::table
+                    .filter(certificates::domain_id.eq(&domain.id))
+                    .load::<Certificate>(conn)?;
+
+                for cert in certs {
+                    let old_path = Path::new(&cert.path);
+
+                    if let (Some(archive), Some(name)) = (old_path.parent().and_then(|dir| dir.parent()), old_path.file_name()) {
+                        let path: String = archive.join(&domain.fqdn).join(name).to_string_lossy().into();
+
+                        diesel::update(certificates::table.find((&cert.domain_id, cert.id, &cert.friendly_name)))
+                            .set(certificates::path.eq(path))
+                            .execute(conn)?;
+                    }
+                }
+
+                Ok(domain)
+            })
+        })
+    }
+}
+
+impl Handler<SetDomainArchived> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SetDomainArchived, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            diesel::delete(domains::table)
+            // A domain a lineage was promoted over stays archived, even while its directory is still around
+            if !msg.archived {
+                check_unaliased(conn, &msg.fqdn)?;
+            }
+
+            diesel::update(domains::table)
                 .filter(domains::fqdn.eq(&msg.fqdn))
+                .set(domains::archived.eq(msg.archived))
                 .execute(conn)
                 .map_err(|e| e.into())
                 .and_then(|rows| match rows {
@@ -53,6 +255,37 @@ impl Handler<DeleteDomain> for DbExecutor {
     }
 }
 
+impl Handler<GetDomains> for DbExecutor {
+    type Result = Result<Vec<Domain>, Error>;
+
+    fn handle(&mut self, _: GetDomains, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            domains::table
+                .order(domains::fqdn.asc())
+                .load::<Domain>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<ListDomains> for DbExecutor {
+    type Result = Result<Vec<Domain>, Error>;
+
+    fn handle(&mut self, msg: ListDomains, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let mut query = domains::table.into_boxed();
+
+            if let Some(fqdns) = &msg.fqdns {
+                query = query.filter(domains::fqdn.eq_any(fqdns));
+            }
+
+            paginate!(query, domains::fqdn, domains::id, &msg.query)
+                .load::<Domain>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
 impl Handler<GetDomainByFqdn> for DbExecutor {
     type Result = Result<Domain, Error>;
 
@@ -67,6 +300,165 @@ impl Handler<GetDomainByFqdn> for DbExecutor {
     }
 }
 
+impl Handler<ResolveDomain> for DbExecutor {
+    type Result = Result<Domain, Error>;
+
+    fn handle(&mut self, msg: ResolveDomain, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            lookup_domain(conn, &msg.fqdn)?
+                .ok_or_else(|| Error::DataNotFound("domain not found".into()))
+        })
+    }
+}
+
+impl Handler<PromoteDomainLineage> for DbExecutor {
+    type Result = Result<Domain, Error>;
+
+    fn handle(&mut self, msg: PromoteDomainLineage, _: &mut Self::Context) -> Self::Result {
+        let canonical_fqdn = match LINEAGE_PATTERN.captures(&msg.fqdn).and_then(|names| names.get(1)) {
+            Some(name) => name.as_str().to_string(),
+            None => return Err(Error::DataIncorrect(format!("{} is not a numbered lineage", msg.fqdn)))
+        };
+
+        info!("promoting lineage {} to {}", msg.fqdn, canonical_fqdn);
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                let lineage = domains::table
+                    .filter(domains::fqdn.eq(&msg.fqdn))
+                    .load::<Domain>(conn)
+                    .map_err(|e| e.into())
+                    .and_then(move |f| exactly_one(f, "domain"))?;
+
+                let alias = DomainAlias {
+                    hashed_fqdn: CryptoUtil::hash_string(&canonical_fqdn),
+                    fqdn: canonical_fqdn.clone(),
+                    domain_id: lineage.id.clone()
+                };
+
+                // Upserts are written as a delete followed by an insert throughout,
+                // as only MySQL and SQLite have REPLACE, and only Postgres has ON CONFLICT
+                diesel::delete(domain_aliases::table.find(&alias.hashed_fqdn))
+                    .execute(conn)?;
+
+                diesel::insert_into(domain_aliases::table)
+                    .values(&alias)
+                    .execute(conn)?;
+
+                let existing: HashSet<String> = domain_group_mappings::table
+                    .filter(domain_group_mappings::domain_id.eq(&lineage.id))
+                    .select(domain_group_mappings::group_id)
+                    .load::<String>(conn)?
+                    .into_iter()
+                    .collect();
+
+                // Carry the groups of the domain being superseded over to the lineage,
+                // so whoever had access under the canonical name still does
+                let inherited: Vec<DomainGroupMapping> = domain_group_mappings::table
+                    .inner_join(domains::table)
+                    .filter(domains::fqdn.eq(&canonical_fqdn))
+                    .select(domain_group_mappings::group_id)
+                    .load::<String>(conn)?
+                    .into_iter()
+                    .filter(|group_id| !existing.contains(group_id))
+                    .map(|group_id| DomainGroupMapping {
+                        domain_id: lineage.id.clone(),
+                        group_id
+                    }).collect();
+
+                diesel::insert_into(domain_group_mappings::table)
+                    .values(&inherited)
+                    .execute(conn)?;
+
+                // The superseded domain stays around as history, but gives up its name
+                diesel::update(domains::table)
+                    .filter(domains::fqdn.eq(&canonical_fqdn))
+                    .set(domains::archived.eq(true))
+                    .execute(conn)?;
+
+                Ok(lineage)
+            })
+        })
+    }
+}
+
+impl Handler<CreateDomainAlias> for DbExecutor {
+    type Result = Result<DomainAlias, Error>;
+
+    fn handle(&mut self, msg: CreateDomainAlias, _: &mut Self::Context) -> Self::Result {
+        info!("creating alias {} for domain {}", msg.fqdn, msg.domain_id);
+        self.with_connection(|conn| {
+            let named = domains::table
+                .filter(domains::fqdn.eq(&msg.fqdn))
+                .select(domains::id)
+                .first::<String>(conn)
+                .optional()?;
+
+            if named.is_some() {
+                return Err(Error::DataConflict(format!("{} is already a domain", msg.fqdn)));
+            }
+
+            let alias = DomainAlias {
+                hashed_fqdn: CryptoUtil::hash_string(&msg.fqdn),
+                fqdn: msg.fqdn,
+                domain_id: msg.domain_id
+            };
+
+            diesel::insert_into(domain_aliases::table)
+                .values(&alias)
+                .execute(conn)?;
+
+            Ok(alias)
+        })
+    }
+}
+
+impl Handler<DeleteDomainAlias> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: DeleteDomainAlias, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            diesel::delete(domain_aliases::table)
+                .filter(domain_aliases::hashed_fqdn.eq(CryptoUtil::hash_string(&msg.fqdn)))
+                .filter(domain_aliases::domain_id.eq(&msg.domain_id))
+                .execute(conn)
+                .map_err(|e| e.into())
+                .and_then(|rows| match rows {
+                    0 => Err(Error::DataNotFound("alias not found".into())),
+                    _ => Ok(())
+                })
+        })
+    }
+}
+
+impl Handler<ListDomainAliases> for DbExecutor {
+    type Result = Result<Vec<DomainAlias>, Error>;
+
+    fn handle(&mut self, msg: ListDomainAliases, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let query = domain_aliases::table
+                .filter(domain_aliases::domain_id.eq(&msg.id))
+                .into_boxed();
+
+            paginate!(query, domain_aliases::fqdn, domain_aliases::hashed_fqdn, &msg.query)
+                .load::<DomainAlias>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<GetAliasesByDomain> for DbExecutor {
+    type Result = Result<Vec<DomainAlias>, Error>;
+
+    fn handle(&mut self, msg: GetAliasesByDomain, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            domain_aliases::table
+                .filter(domain_aliases::domain_id.eq(&msg.id))
+                .load::<DomainAlias>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
 impl Handler<GetGroupsByDomain> for DbExecutor {
     type Result = Result<Vec<Group>, Error>;
 
@@ -98,227 +490,1166 @@ impl Handler<GetGroupsByUser> for DbExecutor {
 }
 
 impl Handler<CreateUser> for DbExecutor {
-    type Result = Result<User, Error>;
+    type Result = Result<(User, ApiKey), Error>;
 
     fn handle(&mut self, msg: CreateUser, _: &mut Self::Context) -> Self::Result {
         if msg.friendly_name == "admin" {
             Err(Error::DataConflict("admin username is reserved".into()))
         } else {
             self.with_connection(|conn| {
-                let new_user = User {
-                    id: CryptoUtil::generate_uuid(),
-                    friendly_name: msg.friendly_name,
-                    hashed_key: msg.hashed_key
-                };
+                conn.transaction::<_, Error, _>(|| {
+                    let new_user = User {
+                        id: CryptoUtil::generate_uuid(),
+                        friendly_name: msg.friendly_name,
+                        disabled: false
+                    };
 
-                diesel::insert_into(users::table)
-                    .values(&new_user)
-                    .execute(conn)?;
+                    diesel::insert_into(users::table)
+                        .values(&new_user)
+                        .execute(conn)?;
 
-                Ok(new_user)
+                    let key = insert_api_key(conn, &new_user.id, DEFAULT_KEY_LABEL, msg.hashed_key, None, None, None)?;
+
+                    Ok((new_user, key))
+                })
             })
         }
     }
 }
 
-impl Handler<GetUserByName> for DbExecutor {
-    type Result = Result<User, Error>;
+fn insert_api_key(conn: &DbConnection, user_id: &str, label: &str, hashed_key: String, expires_at: Option<NaiveDateTime>,
+    scope_domains: Option<Vec<String>>, scope_permissions: Option<Vec<String>>) -> Result<ApiKey, Error> {
 
-    fn handle(&mut self, msg: GetUserByName, _: &mut Self::Context) -> Self::Result {
+    let scope = |scope: Option<Vec<String>>| scope.map(|scope| serde_json::to_string(&scope).unwrap());
+    let key = ApiKey {
+        id: CryptoUtil::generate_uuid(),
+        user_id: user_id.into(),
+        label: label.into(),
+        hashed_key,
+        previous_hashed_key: None,
+        previous_key_expires_at: None,
+        created_at: Utc::now().naive_utc(),
+        expires_at,
+        last_used_at: None,
+        scope_domains: scope(scope_domains),
+        scope_permissions: scope(scope_permissions)
+    };
+
+    diesel::insert_into(api_keys::table)
+        .values(&key)
+        .execute(conn)?;
+
+    Ok(key)
+}
+
+// Keys are referred to by their id only, any label could be mistaken for
+// another key's id. Without one, it's the user's default key
+fn find_api_key(conn: &DbConnection, user_id: &str, id: Option<&str>) -> Result<ApiKey, Error> {
+    let keys = api_keys::table
+        .filter(api_keys::user_id.eq(user_id))
+        .into_boxed();
+
+    let keys = match id {
+        Some(id) => keys.filter(api_keys::id.eq(id)),
+        None => keys.filter(api_keys::label.eq(DEFAULT_KEY_LABEL))
+    };
+
+    keys.first::<ApiKey>(conn)
+        .optional()?
+        .ok_or_else(|| Error::DataNotFound("key not found".into()))
+}
+
+impl Handler<CreateApiKey> for DbExecutor {
+    type Result = Result<ApiKey, Error>;
+
+    fn handle(&mut self, msg: CreateApiKey, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            users::table
-                .filter(users::friendly_name.eq(&msg.friendly_name))
-                .load::<User>(conn)
-                .map_err(|e| e.into())
-                .and_then(move |f| exactly_one(f, "user"))
+            conn.transaction::<_, Error, _>(|| {
+                users::table
+                    .find(&msg.user_id)
+                    .select(users::id)
+                    .first::<String>(conn)
+                    .optional()?
+                    .ok_or_else(|| Error::DataNotFound("user not found".into()))?;
+
+                insert_api_key(conn, &msg.user_id, &msg.label, msg.hashed_key, msg.expires_at, msg.scope_domains, msg.scope_permissions)
+            })
         })
     }
 }
 
-impl Handler<GetUser> for DbExecutor {
-    type Result = Result<User, Error>;
+impl Handler<GetApiKeys> for DbExecutor {
+    type Result = Result<Vec<ApiKey>, Error>;
 
-    fn handle(&mut self, msg: GetUser, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: GetApiKeys, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            users::table
-                .filter(users::id.eq(&msg.id))
-                .load::<User>(conn)
+            api_keys::table
+                .filter(api_keys::user_id.eq(&msg.user_id))
+                .order(api_keys::label.asc())
+                .load::<ApiKey>(conn)
                 .map_err(|e| e.into())
-                .and_then(move |f| exactly_one(f, "user"))
         })
     }
 }
 
+impl Handler<GetApiKey> for DbExecutor {
+    type Result = Result<ApiKey, Error>;
 
-impl Handler<GetUserPermissions> for DbExecutor {
-    type Result = Result<Vec<DomainPermission>, Error>;
-
-    fn handle(&mut self, msg: GetUserPermissions, _: &mut Self::Context) -> Self::Result {
-        self.with_connection(|conn| {
-            user_group_mappings::table
-            .filter(user_group_mappings::user_id.eq(msg.id))
-            .inner_join(groups::table)
-            .inner_join(domain_group_mappings::table.on(domain_group_mappings::group_id.eq(groups::id)))
-            .inner_join(domains::table.on(domain_group_mappings::domain_id.eq(domains::id)))
-            .select((domains::fqdn, groups::permission))
-            .load::<DomainPermission>(conn)
-            .map_err(|e| e.into())
-        })
+    fn handle(&mut self, msg: GetApiKey, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| find_api_key(conn, &msg.user_id, msg.id.as_ref().map(String::as_str)))
     }
 }
 
+impl Handler<RotateApiKey> for DbExecutor {
+    type Result = Result<ApiKey, Error>;
 
-impl Handler<CreateGroup> for DbExecutor {
-    type Result = Result<Group, Error>;
-    
-    fn handle(&mut self, msg: CreateGroup, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: RotateApiKey, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            let new_group = Group {
-                id: CryptoUtil::generate_uuid(),
-                friendly_name: msg.friendly_name,
-                permission: "public".into()
-            };
+            conn.transaction::<_, Error, _>(|| {
+                let mut key = find_api_key(conn, &msg.user_id, msg.key.as_ref().map(String::as_str))?;
 
-            diesel::insert_into(groups::table)
-                .values(&new_group)
-                .execute(conn)?;
+                // Only the key being replaced is kept around, whatever
+                // was left of an earlier rotation ends here
+                key.previous_hashed_key = msg.previous_key_expires_at.map(|_| key.hashed_key.clone());
+                key.previous_key_expires_at = msg.previous_key_expires_at;
+                key.hashed_key = msg.hashed_key;
 
-            Ok(new_group)        
+                diesel::update(api_keys::table.find(&key.id))
+                    .set((
+                        api_keys::hashed_key.eq(&key.hashed_key),
+                        api_keys::previous_hashed_key.eq(&key.previous_hashed_key),
+                        api_keys::previous_key_expires_at.eq(&key.previous_key_expires_at)
+                    ))
+                    .execute(conn)?;
+
+                Ok(key)
+            })
         })
     }
 }
 
-impl Handler<GetGroup> for DbExecutor {
-    type Result = Result<Group, Error>;
+impl Handler<RevokeApiKey> for DbExecutor {
+    type Result = Result<(), Error>;
 
-    fn handle(&mut self, msg: GetGroup, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: RevokeApiKey, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            groups::table
-                .filter(groups::id.eq(&msg.id))
-                .load::<Group>(conn)
-                .map_err(|e| e.into())
-                .and_then(move |f| exactly_one(f, "group"))
+            conn.transaction::<_, Error, _>(|| {
+                let key = find_api_key(conn, &msg.user_id, Some(&msg.key))?;
+
+                diesel::delete(api_keys::table.find(&key.id))
+                    .execute(conn)?;
+
+                // Along with whatever sessions were started with it
+                diesel::update(refresh_tokens::table)
+                    .filter(refresh_tokens::key_id.eq(&key.id))
+                    .filter(refresh_tokens::revoked_at.is_null())
+                    .set(refresh_tokens::revoked_at.eq(Utc::now().naive_utc()))
+                    .execute(conn)?;
+
+                Ok(())
+            })
         })
     }
 }
 
-impl Handler<SetGroupUsers> for DbExecutor {
+impl Handler<TouchApiKey> for DbExecutor {
     type Result = Result<(), Error>;
 
-    fn handle(&mut self, msg: SetGroupUsers, _: &mut Self::Context) -> Self::Result {
-        self.with_connection(|conn| {
-
-            let mappings: Vec<UserGroupMapping> = (&msg.user_ids).iter().map(|id| {
-                UserGroupMapping {
-                    user_id: id.clone(),
-                    group_id: msg.group_id.clone()
-                }
-            }).collect();
+    fn handle(&mut self, msg: TouchApiKey, _: &mut Self::Context) -> Self::Result {
+        let now = Utc::now().naive_utc();
 
-            diesel::delete(user_group_mappings::table)
-                .filter(user_group_mappings::group_id.eq(&msg.group_id))
+        // Keys are used for every request, so when they were last used is only recorded once a minute
+        self.with_connection(|conn| {
+            diesel::update(api_keys::table.find(&msg.id))
+                .filter(api_keys::last_used_at.is_null()
+                    .or(api_keys::last_used_at.lt(now - Duration::minutes(1))))
+                .set(api_keys::last_used_at.eq(now))
                 .execute(conn)?;
 
-            diesel::insert_into(user_group_mappings::table)
-                .values(&mappings)
-                .execute(conn)
-                .map_err(|e| e.into())
-                .and_then(move |rows| {
-                    if rows != msg.user_ids.len() {
-                        Err(Error::Unknown)
-                    } else {
-                        Ok(())
-                    }
-                })
+            Ok(())
         })
     }
 }
 
-impl Handler<SetGroupDomains> for DbExecutor {
+impl Handler<RecordRefreshToken> for DbExecutor {
     type Result = Result<(), Error>;
 
-    fn handle(&mut self, msg: SetGroupDomains, _: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: RecordRefreshToken, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
+            // Expired tokens can't be used whether they were revoked or not
+            diesel::delete(refresh_tokens::table)
+                .filter(refresh_tokens::expires_at.lt(Utc::now().naive_utc()))
+                .execute(conn)?;
+
+            diesel::insert_into(refresh_tokens::table)
+                .values(&msg.entry)
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+}
+
+impl Handler<GetRefreshToken> for DbExecutor {
+    type Result = Result<RefreshTokenEntry, Error>;
+
+    fn handle(&mut self, msg: GetRefreshToken, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            refresh_tokens::table
+                .find(&msg.jti)
+                .first::<RefreshTokenEntry>(conn)
+                .optional()?
+                .ok_or_else(|| Error::DataNotFound("refresh token not found".into()))
+        })
+    }
+}
+
+// Revoking a token which is unknown or already revoked isn't an error, there's nothing left to do
+impl Handler<RevokeRefreshToken> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: RevokeRefreshToken, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            diesel::update(refresh_tokens::table.find(&msg.jti))
+                .filter(refresh_tokens::revoked_at.is_null())
+                .set(refresh_tokens::revoked_at.eq(Utc::now().naive_utc()))
+                .execute(conn)?;
+
+            Ok(())
+        })
+    }
+}
+
+impl Handler<RevokeRefreshTokens> for DbExecutor {
+    type Result = Result<usize, Error>;
+
+    fn handle(&mut self, msg: RevokeRefreshTokens, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            diesel::update(refresh_tokens::table)
+                .filter(refresh_tokens::subject.eq(&msg.user_id))
+                .filter(refresh_tokens::revoked_at.is_null())
+                .set(refresh_tokens::revoked_at.eq(Utc::now().naive_utc()))
+                .execute(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<UpdateUser> for DbExecutor {
+    type Result = Result<User, Error>;
+
+    fn handle(&mut self, msg: UpdateUser, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                let mut user = users::table
+                    .find(&msg.id)
+                    .first::<User>(conn)
+                    .optional()?
+                    .ok_or_else(|| Error::DataNotFound("user not found".into()))?;
+
+                if let Some(friendly_name) = msg.friendly_name {
+                    if friendly_name == "admin" {
+                        return Err(Error::DataConflict("admin username is reserved".into()));
+                    }
+
+                    user.friendly_name = friendly_name;
+                }
+
+                if let Some(disabled) = msg.disabled {
+                    user.disabled = disabled;
+                }
+
+                diesel::update(users::table.find(&msg.id))
+                    .set((
+                        users::friendly_name.eq(&user.friendly_name),
+                        users::disabled.eq(user.disabled)
+                    ))
+                    .execute(conn)?;
+
+                Ok(user)
+            })
+        })
+    }
+}
+
+// Their keys, roles, group memberships and refresh tokens go along with them
+impl Handler<DeleteUser> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: DeleteUser, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                let user = users::table
+                    .find(&msg.id)
+                    .first::<User>(conn)
+                    .optional()?
+                    .ok_or_else(|| Error::DataNotFound("user not found".into()))?;
+
+                diesel::delete(refresh_tokens::table)
+                    .filter(refresh_tokens::subject.eq(&user.id))
+                    .execute(conn)?;
+
+                diesel::delete(users::table.find(&msg.id))
+                    .execute(conn)?;
+
+                Ok(())
+            })
+        })
+    }
+}
+
+impl Handler<GetUserByName> for DbExecutor {
+    type Result = Result<User, Error>;
+
+    fn handle(&mut self, msg: GetUserByName, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            users::table
+                .filter(users::friendly_name.eq(&msg.friendly_name))
+                .load::<User>(conn)
+                .map_err(|e| e.into())
+                .and_then(move |f| exactly_one(f, "user"))
+        })
+    }
+}
+
+impl Handler<GetUser> for DbExecutor {
+    type Result = Result<User, Error>;
+
+    fn handle(&mut self, msg: GetUser, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            users::table
+                .filter(users::id.eq(&msg.id))
+                .load::<User>(conn)
+                .map_err(|e| e.into())
+                .and_then(move |f| exactly_one(f, "user"))
+        })
+    }
+}
+
+
+impl Handler<GetUserPermissions> for DbExecutor {
+    type Result = Result<Vec<DomainPermission>, Error>;
+
+    fn handle(&mut self, msg: GetUserPermissions, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            user_group_mappings::table
+            .filter(user_group_mappings::user_id.eq(msg.id))
+            .inner_join(groups::table)
+            .inner_join(domain_group_mappings::table.on(domain_group_mappings::group_id.eq(groups::id)))
+            .inner_join(domains::table.on(domain_group_mappings::domain_id.eq(domains::id)))
+            .select((domains::fqdn, groups::permission))
+            .load::<DomainPermission>(conn)
+            .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<GetUserRoles> for DbExecutor {
+    type Result = Result<Vec<String>, Error>;
+
+    fn handle(&mut self, msg: GetUserRoles, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let mut roles = user_roles::table
+                .filter(user_roles::user_id.eq(&msg.id))
+                .select(user_roles::role)
+                .load::<String>(conn)?;
+
+            // Along with those held through any of the user's groups
+            if msg.inherited {
+                roles.extend(user_group_mappings::table
+                    .filter(user_group_mappings::user_id.eq(&msg.id))
+                    .inner_join(group_roles::table.on(group_roles::group_id.eq(user_group_mappings::group_id)))
+                    .select(group_roles::role)
+                    .load::<String>(conn)?);
+            }
+
+            roles.sort();
+            roles.dedup();
+            Ok(roles)
+        })
+    }
+}
+
+impl Handler<SetUserRoles> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SetUserRoles, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                users::table
+                    .find(&msg.id)
+                    .select(users::id)
+                    .first::<String>(conn)
+                    .optional()?
+                    .ok_or_else(|| Error::DataNotFound("user not found".into()))?;
+
+                let roles: Vec<UserRole> = msg.roles.iter()
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .map(|role| UserRole {
+                        user_id: msg.id.clone(),
+                        role: role.clone()
+                    })
+                    .collect();
+
+                diesel::delete(user_roles::table)
+                    .filter(user_roles::user_id.eq(&msg.id))
+                    .execute(conn)?;
+
+                if !roles.is_empty() {
+                    diesel::insert_into(user_roles::table)
+                        .values(&roles)
+                        .execute(conn)?;
+                }
+
+                Ok(())
+            })
+        })
+    }
+}
+
+impl Handler<CreateGroup> for DbExecutor {
+    type Result = Result<Group, Error>;
+    
+    fn handle(&mut self, msg: CreateGroup, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let new_group = Group {
+                id: CryptoUtil::generate_uuid(),
+                friendly_name: msg.friendly_name,
+                permission: msg.permission
+            };
+
+            diesel::insert_into(groups::table)
+                .values(&new_group)
+                .execute(conn)?;
+
+            Ok(new_group)        
+        })
+    }
+}
+
+impl Handler<UpdateGroup> for DbExecutor {
+    type Result = Result<Group, Error>;
+
+    fn handle(&mut self, msg: UpdateGroup, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                let mut group = groups::table
+                    .find(&msg.id)
+                    .first::<Group>(conn)
+                    .optional()?
+                    .ok_or_else(|| Error::DataNotFound("group not found".into()))?;
+
+                if let Some(friendly_name) = msg.friendly_name {
+                    // The unique constraint has the last word, this only tells which name clashed
+                    let taken = groups::table
+                        .filter(groups::friendly_name.eq(&friendly_name))
+                        .filter(groups::id.ne(&msg.id))
+                        .count()
+                        .get_result::<i64>(conn)?;
+
+                    if taken > 0 {
+                        return Err(Error::DataConflict(format!("a group named {} already exists", friendly_name)));
+                    }
+
+                    group.friendly_name = friendly_name;
+                }
+
+                if let Some(permission) = msg.permission {
+                    group.permission = permission;
+                }
+
+                diesel::update(groups::table.find(&msg.id))
+                    .set((
+                        groups::friendly_name.eq(&group.friendly_name),
+                        groups::permission.eq(&group.permission)
+                    ))
+                    .execute(conn)?;
+
+                Ok(group)
+            })
+        })
+    }
+}
+
+impl Handler<GetGroup> for DbExecutor {
+    type Result = Result<Group, Error>;
+
+    fn handle(&mut self, msg: GetGroup, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            groups::table
+                .filter(groups::id.eq(&msg.id))
+                .load::<Group>(conn)
+                .map_err(|e| e.into())
+                .and_then(move |f| exactly_one(f, "group"))
+        })
+    }
+}
+
+fn check_group(conn: &DbConnection, group_id: &str) -> Result<(), Error> {
+    groups::table
+        .find(group_id)
+        .select(groups::id)
+        .first::<String>(conn)
+        .optional()?
+        .map(|_| ())
+        .ok_or_else(|| Error::DataNotFound("group not found".into()))
+}
+
+// Every requested id which isn't among the known ones, so they can all be reported at once
+fn check_references(kind: &str, requested: &[String], known: Vec<String>) -> Result<(), Error> {
+    let known: HashSet<String> = known.into_iter().collect();
+    let mut unknown: Vec<String> = requested.iter()
+        .filter(|id| !known.contains(*id))
+        .cloned()
+        .collect();
+
+    if unknown.is_empty() {
+        return Ok(());
+    }
+
+    unknown.sort();
+    unknown.dedup();
+    Err(Error::UnknownReferences(kind.into(), unknown))
+}
+
+// Domains can be referred to by id or by any of their names, the same way
+// they are resolved when certificates are requested
+fn find_domain(conn: &DbConnection, reference: &str) -> Result<Option<String>, Error> {
+    let by_id = domains::table
+        .find(reference)
+        .select(domains::id)
+        .first::<String>(conn)
+        .optional()?;
+
+    if by_id.is_some() {
+        return Ok(by_id);
+    }
+
+    Ok(lookup_domain(conn, &normalize_fqdn(reference))?.map(|domain| domain.id))
+}
+
+// Names from DNS inventories come in any case, and often fully qualified with the root's dot
+fn normalize_fqdn(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_lowercase()
+}
+
+// The ids of the referenced domains, along with the names of the placeholders created for those missing
+fn resolve_domains(conn: &DbConnection, references: &[String], create_missing: bool) -> Result<(Vec<String>, Vec<String>), Error> {
+    let mut ids = Vec::with_capacity(references.len());
+    let mut created = Vec::new();
+    let mut unknown = Vec::new();
+
+    for reference in references {
+        let fqdn = normalize_fqdn(reference);
+
+        match find_domain(conn, reference)? {
+            Some(id) => ids.push(id),
+            None if create_missing && fqdn.contains('.') => {
+                info!("creating placeholder domain: {}", fqdn);
+                let domain = Domain {
+                    id: CryptoUtil::generate_uuid(),
+                    hashed_fqdn: CryptoUtil::hash_string(&fqdn),
+                    fqdn,
+                    archived: false
+                };
+
+                diesel::insert_into(domains::table)
+                    .values(&domain)
+                    .execute(conn)?;
+
+                ids.push(domain.id);
+                created.push(domain.fqdn);
+            },
+            None => unknown.push(reference.clone())
+        }
+    }
+
+    check_references("domains", &unknown, Vec::new())?;
+    Ok((ids, created))
+}
+
+// Members are only added if they aren't already, but another request can add the same one
+// between looking and inserting. That trips the primary key, and is reported as a conflict
+fn changed_concurrently(e: diesel::result::Error, members: &str) -> Error {
+    match e {
+        diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _) =>
+            Error::DataConflict(format!("the group's {} were changed concurrently, try again", members)),
+        e => e.into()
+    }
+}
+
+fn update_group_users(conn: &DbConnection, group_id: &str, add: &[String], remove: &[String]) -> Result<(), Error> {
+    check_group(conn, group_id)?;
+    check_references("users", add, users::table
+        .filter(users::id.eq_any(add))
+        .select(users::id)
+        .load::<String>(conn)?)?;
+
+    let current: HashSet<String> = user_group_mappings::table
+        .filter(user_group_mappings::group_id.eq(group_id))
+        .select(user_group_mappings::user_id)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+
+    let mappings: Vec<UserGroupMapping> = add.iter()
+        .filter(|id| !current.contains(*id))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|id| UserGroupMapping {
+            user_id: id.clone(),
+            group_id: group_id.into()
+        })
+        .collect();
+
+    if !remove.is_empty() {
+        diesel::delete(user_group_mappings::table)
+            .filter(user_group_mappings::group_id.eq(group_id))
+            .filter(user_group_mappings::user_id.eq_any(remove))
+            .execute(conn)?;
+    }
+
+    if !mappings.is_empty() {
+        diesel::insert_into(user_group_mappings::table)
+            .values(&mappings)
+            .execute(conn)
+            .map_err(|e| changed_concurrently(e, "users"))?;
+    }
+
+    Ok(())
+}
+
+fn update_group_domains(conn: &DbConnection, group_id: &str, add: &[String], remove: &[String]) -> Result<(), Error> {
+    check_group(conn, group_id)?;
+    check_references("domains", add, domains::table
+        .filter(domains::id.eq_any(add))
+        .select(domains::id)
+        .load::<String>(conn)?)?;
+
+    let current: HashSet<String> = domain_group_mappings::table
+        .filter(domain_group_mappings::group_id.eq(group_id))
+        .select(domain_group_mappings::domain_id)
+        .load::<String>(conn)?
+        .into_iter()
+        .collect();
+
+    let mappings: Vec<DomainGroupMapping> = add.iter()
+        .filter(|id| !current.contains(*id))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|id| DomainGroupMapping {
+            domain_id: id.clone(),
+            group_id: group_id.into()
+        })
+        .collect();
+
+    if !remove.is_empty() {
+        diesel::delete(domain_group_mappings::table)
+            .filter(domain_group_mappings::group_id.eq(group_id))
+            .filter(domain_group_mappings::domain_id.eq_any(remove))
+            .execute(conn)?;
+    }
+
+    if !mappings.is_empty() {
+        diesel::insert_into(domain_group_mappings::table)
+            .values(&mappings)
+            .execute(conn)
+            .map_err(|e| changed_concurrently(e, "domains"))?;
+    }
+
+    Ok(())
+}
+
+// Only the differences between the current and the requested members are applied,
+// all at once or not at all, so a bad id leaves the group as it was
+impl Handler<SetGroupUsers> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SetGroupUsers, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                let remove: Vec<String> = user_group_mappings::table
+                    .filter(user_group_mappings::group_id.eq(&msg.group_id))
+                    .filter(user_group_mappings::user_id.ne_all(&msg.user_ids))
+                    .select(user_group_mappings::user_id)
+                    .load::<String>(conn)?;
+
+                update_group_users(conn, &msg.group_id, &msg.user_ids, &remove)
+            })
+        })
+    }
+}
+
+impl Handler<SetGroupDomains> for DbExecutor {
+    type Result = Result<Vec<String>, Error>;
+
+    fn handle(&mut self, msg: SetGroupDomains, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                check_group(conn, &msg.group_id)?;
+                let (domain_ids, created) = resolve_domains(conn, &msg.domains, msg.create_missing)?;
+
+                let remove: Vec<String> = domain_group_mappings::table
+                    .filter(domain_group_mappings::group_id.eq(&msg.group_id))
+                    .filter(domain_group_mappings::domain_id.ne_all(&domain_ids))
+                    .select(domain_group_mappings::domain_id)
+                    .load::<String>(conn)?;
+
+                update_group_domains(conn, &msg.group_id, &domain_ids, &remove)?;
+                Ok(created)
+            })
+        })
+    }
+}
+
+impl Handler<AddGroupUser> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: AddGroupUser, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| update_group_users(conn, &msg.group_id, &[msg.user_id.clone()], &[]))
+        })
+    }
+}
+
+impl Handler<RemoveGroupUser> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: RemoveGroupUser, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            diesel::delete(user_group_mappings::table)
+                .filter(user_group_mappings::group_id.eq(&msg.group_id))
+                .filter(user_group_mappings::user_id.eq(&msg.user_id))
+                .execute(conn)
+                .map_err(|e| e.into())
+                .and_then(|rows| match rows {
+                    0 => Err(Error::DataNotFound("user is not a member of the group".into())),
+                    _ => Ok(())
+                })
+        })
+    }
+}
+
+impl Handler<AddGroupDomain> for DbExecutor {
+    type Result = Result<Vec<String>, Error>;
+
+    fn handle(&mut self, msg: AddGroupDomain, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                check_group(conn, &msg.group_id)?;
+                let (domain_ids, created) = resolve_domains(conn, &[msg.domain.clone()], msg.create_missing)?;
+                update_group_domains(conn, &msg.group_id, &domain_ids, &[])?;
+                Ok(created)
+            })
+        })
+    }
+}
+
+impl Handler<RemoveGroupDomain> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: RemoveGroupDomain, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let domain_id = find_domain(conn, &msg.domain)?
+                .ok_or_else(|| Error::DataNotFound("domain not found".into()))?;
+
+            diesel::delete(domain_group_mappings::table)
+                .filter(domain_group_mappings::group_id.eq(&msg.group_id))
+                .filter(domain_group_mappings::domain_id.eq(&domain_id))
+                .execute(conn)
+                .map_err(|e| e.into())
+                .and_then(|rows| match rows {
+                    0 => Err(Error::DataNotFound("domain is not a member of the group".into())),
+                    _ => Ok(())
+                })
+        })
+    }
+}
+
+impl Handler<GetGroupRoles> for DbExecutor {
+    type Result = Result<Vec<String>, Error>;
+
+    fn handle(&mut self, msg: GetGroupRoles, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            group_roles::table
+                .filter(group_roles::group_id.eq(&msg.id))
+                .order(group_roles::role.asc())
+                .select(group_roles::role)
+                .load::<String>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<SetGroupRoles> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: SetGroupRoles, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                check_group(conn, &msg.id)?;
+
+                let roles: Vec<GroupRole> = msg.roles.iter()
+                    .collect::<HashSet<_>>()
+                    .into_iter()
+                    .map(|role| GroupRole {
+                        group_id: msg.id.clone(),
+                        role: role.clone()
+                    })
+                    .collect();
+
+                diesel::delete(group_roles::table)
+                    .filter(group_roles::group_id.eq(&msg.id))
+                    .execute(conn)?;
+
+                if !roles.is_empty() {
+                    diesel::insert_into(group_roles::table)
+                        .values(&roles)
+                        .execute(conn)?;
+                }
+
+                Ok(())
+            })
+        })
+    }
+}
+
+impl Handler<ListUsers> for DbExecutor {
+    type Result = Result<Vec<User>, Error>;
+
+    fn handle(&mut self, msg: ListUsers, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            paginate!(users::table.into_boxed(), users::friendly_name, users::id, &msg.query)
+                .load::<User>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<ListGroups> for DbExecutor {
+    type Result = Result<Vec<Group>, Error>;
+
+    fn handle(&mut self, msg: ListGroups, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            paginate!(groups::table.into_boxed(), groups::friendly_name, groups::id, &msg.query)
+                .load::<Group>(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+
+fn group_users(conn: &DbConnection, id: &str, list: &ListQuery) -> Result<Vec<User>, Error> {
+    let query = user_group_mappings::table
+        .filter(user_group_mappings::group_id.eq(id))
+        .inner_join(users::table)
+        .select(users::all_columns)
+        .into_boxed();
+
+    paginate!(query, users::friendly_name, users::id, list)
+        .load::<User>(conn)
+        .map_err(|e| e.into())
+}
+
+fn group_domains(conn: &DbConnection, id: &str, list: &ListQuery) -> Result<Vec<Domain>, Error> {
+    let query = domain_group_mappings::table
+        .filter(domain_group_mappings::group_id.eq(id))
+        .inner_join(domains::table)
+        .select((domains::id, domains::fqdn, domains::hashed_fqdn, domains::archived))
+        .into_boxed();
+
+    paginate!(query, domains::fqdn, domains::id, list)
+        .load::<Domain>(conn)
+        .map_err(|e| e.into())
+}
+
+impl Handler<ListGroupUsers> for DbExecutor {
+    type Result = Result<Vec<User>, Error>;
+
+    fn handle(&mut self, msg: ListGroupUsers, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| group_users(conn, &msg.id, &msg.query))
+    }
+}
+
+impl Handler<ListGroupDomains> for DbExecutor {
+    type Result = Result<Vec<Domain>, Error>;
+
+    fn handle(&mut self, msg: ListGroupDomains, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| group_domains(conn, &msg.id, &msg.query))
+    }
+}
+
+// The first page of the users and of the domains of each of the given groups, so a
+// group with thousands of members doesn't blow up a listing of groups
+impl Handler<GetGroupMembers> for DbExecutor {
+    type Result = Result<Vec<(String, Vec<User>, Vec<Domain>)>, Error>;
+
+    fn handle(&mut self, msg: GetGroupMembers, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            msg.ids.iter()
+                .map(|id| {
+                    let users = group_users(conn, id, &msg.query)?;
+                    let domains = group_domains(conn, id, &msg.query)?;
+                    Ok((id.clone(), users, domains))
+                })
+                .collect()
+        })
+    }
+}
 
-            let mappings: Vec<DomainGroupMapping> = (&msg.fqdns).iter().map(|id| {
-                DomainGroupMapping {
-                    domain_id: id.clone(),
-                    group_id: msg.group_id.clone()
-                }
-            }).collect();
+impl Handler<SetLiveVersion> for DbExecutor {
+    type Result = Result<(), Error>;
 
-            diesel::delete(domain_group_mappings::table)
-                .filter(domain_group_mappings::group_id.eq(&msg.group_id))
-                .execute(conn)?;
+    fn handle(&mut self, msg: SetLiveVersion, _: &mut Self::Context) -> Self::Result {
+        let live = LiveVersion {
+            hashed_fqdn: CryptoUtil::hash_string(&msg.fqdn),
+            fqdn: msg.fqdn,
+            friendly_name: msg.friendly_name,
+            version: msg.version
+        };
+
+        self.with_connection(|conn| {
+            conn.transaction::<_, Error, _>(|| {
+                diesel::delete(live_versions::table.find((&live.hashed_fqdn, &live.friendly_name)))
+                    .execute(conn)?;
+
+                diesel::insert_into(live_versions::table)
+                    .values(&live)
+                    .execute(conn)?;
+
+                Ok(())
+            })
+        })
+    }
+}
+
+impl Handler<ClearLiveVersions> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: ClearLiveVersions, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let query = diesel::delete(live_versions::table)
+                .filter(live_versions::hashed_fqdn.eq(CryptoUtil::hash_string(&msg.fqdn)));
+
+            match &msg.friendly_name {
+                Some(friendly_name) => query.filter(live_versions::friendly_name.eq(friendly_name)).execute(conn),
+                None => query.execute(conn)
+            }
+            .map(|_| ())
+            .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<QuarantineFiles> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: QuarantineFiles, _: &mut Self::Context) -> Self::Result {
+        let hashes: Vec<&String> = msg.files.iter().map(|file| &file.hashed_path).collect();
+
+        self.with_connection(|conn| {
+            // Only the latest failure of a file is kept
+            conn.transaction::<_, Error, _>(|| {
+                diesel::delete(quarantine::table)
+                    .filter(quarantine::hashed_path.eq_any(&hashes))
+                    .execute(conn)?;
+
+                diesel::insert_into(quarantine::table)
+                    .values(&msg.files)
+                    .execute(conn)?;
+
+                Ok(())
+            })
+        })
+    }
+}
+
+impl Handler<ReleaseFiles> for DbExecutor {
+    type Result = Result<usize, Error>;
+
+    fn handle(&mut self, msg: ReleaseFiles, _: &mut Self::Context) -> Self::Result {
+        let hashes: Vec<String> = msg.paths.iter().map(|path| CryptoUtil::hash_string(path)).collect();
 
-            diesel::insert_into(domain_group_mappings::table)
-                .values(&mappings)
+        self.with_connection(|conn| {
+            diesel::delete(quarantine::table)
+                .filter(quarantine::hashed_path.eq_any(&hashes))
                 .execute(conn)
                 .map_err(|e| e.into())
-                .and_then(move |rows| {
-                    if rows != msg.fqdns.len() {
-                        Err(Error::Unknown)
-                    } else {
-                        Ok(())
-                    }
-                })
         })
     }
 }
 
-impl Handler<GetUsersByGroup> for DbExecutor {
-    type Result = Result<Vec<User>, Error>;
+impl Handler<GetQuarantine> for DbExecutor {
+    type Result = Result<Vec<QuarantinedFile>, Error>;
 
-    fn handle(&mut self, msg: GetUsersByGroup, _: &mut Self:: Context) -> Self::Result {
+    fn handle(&mut self, msg: GetQuarantine, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            user_group_mappings::table
-                .filter(user_group_mappings::group_id.eq(&msg.id))
-                .inner_join(users::table)
-                .select((users::id, users::friendly_name, users::hashed_key))
-                .load::<User>(conn)
+            let query = quarantine::table
+                .order(quarantine::occurred_at.desc());
+
+            match &msg.fqdn {
+                Some(fqdn) => query.filter(quarantine::fqdn.eq(fqdn)).load::<QuarantinedFile>(conn),
+                None => query.load::<QuarantinedFile>(conn)
+            }
+            .map_err(|e| e.into())
+        })
+    }
+}
+
+impl Handler<ListQuarantine> for DbExecutor {
+    type Result = Result<Vec<QuarantinedFile>, Error>;
+
+    fn handle(&mut self, msg: ListQuarantine, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let mut query = quarantine::table.into_boxed();
+
+            if let Some(fqdn) = &msg.fqdn {
+                query = query.filter(quarantine::fqdn.eq(fqdn));
+            }
+
+            paginate!(query, quarantine::path, quarantine::hashed_path, &msg.query)
+                .load::<QuarantinedFile>(conn)
                 .map_err(|e| e.into())
         })
     }
 }
 
-impl Handler<GetDomainsByGroup> for DbExecutor {
-    type Result = Result<Vec<Domain>, Error>;
+impl Handler<RecordAudit> for DbExecutor {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: RecordAudit, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| audit::append(conn, msg.entry))
+    }
+}
+
+fn filtered_audit_log<'a>(filter: &'a AuditFilter) -> audit_log::BoxedQuery<'a, <DbConnection as Connection>::Backend> {
+    let mut query = audit_log::table.into_boxed();
 
-    fn handle(&mut self, msg: GetDomainsByGroup, _: &mut Self:: Context) -> Self::Result {
+    if let Some(subject) = &filter.subject {
+        query = query.filter(audit_log::subject.eq(subject));
+    }
+
+    if let Some(fqdn) = &filter.fqdn {
+        query = query.filter(audit_log::fqdn.eq(fqdn));
+    }
+
+    if let Some(action) = &filter.action {
+        query = query.filter(audit_log::action.eq(action));
+    }
+
+    if let Some(result) = &filter.result {
+        query = query.filter(audit_log::result.eq(result));
+    }
+
+    if let Some(since) = filter.since {
+        query = query.filter(audit_log::occurred_at.ge(since));
+    }
+
+    if let Some(until) = filter.until {
+        query = query.filter(audit_log::occurred_at.lt(until));
+    }
+
+    query
+}
+
+// Newest first, fetching one entry more than asked for to tell whether there's another page
+impl Handler<GetAuditLog> for DbExecutor {
+    type Result = Result<Vec<AuditEntry>, Error>;
+
+    fn handle(&mut self, msg: GetAuditLog, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            domain_group_mappings::table
-                .filter(domain_group_mappings::group_id.eq(&msg.id))
-                .inner_join(domains::table)
-                .select((domains::id, domains::fqdn, domains::hashed_fqdn))
-                .load::<Domain>(conn)
+            let mut query = filtered_audit_log(&msg.filter);
+
+            if let Some(before) = msg.before {
+                query = query.filter(audit_log::id.lt(before));
+            }
+
+            query
+                .order(audit_log::id.desc())
+                .limit(msg.limit + 1)
+                .load::<AuditEntry>(conn)
                 .map_err(|e| e.into())
         })
     }
 }
 
-impl Handler<GetGroups> for DbExecutor {
-    type Result = Result<Vec<Group>, Error>;
+impl Handler<GetAuditHead> for DbExecutor {
+    type Result = Result<Option<(i64, String)>, Error>;
+
+    fn handle(&mut self, _: GetAuditHead, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| audit::head(conn))
+    }
+}
 
-    fn handle(&mut self, _: GetGroups, _: &mut Self::Context) -> Self::Result {
+impl Handler<VerifyAuditChain> for DbExecutor {
+    type Result = Result<ChainVerification, Error>;
+
+    fn handle(&mut self, msg: VerifyAuditChain, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| audit::verify(conn, &msg.trusted_keys))
+    }
+}
+
+impl Handler<AddAuditCheckpoint> for DbExecutor {
+    type Result = Result<AuditCheckpoint, Error>;
+
+    fn handle(&mut self, msg: AddAuditCheckpoint, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            groups::table
-                .load::<Group>(conn)
+            conn.transaction::<_, Error, _>(|| {
+                diesel::insert_into(audit_checkpoints::table)
+                    .values(&msg.checkpoint)
+                    .execute(conn)?;
+
+                audit_checkpoints::table
+                    .order(audit_checkpoints::id.desc())
+                    .first::<AuditCheckpoint>(conn)
+                    .map_err(|e| e.into())
+            })
+        })
+    }
+}
+
+impl Handler<GetAuditCheckpoints> for DbExecutor {
+    type Result = Result<Vec<AuditCheckpoint>, Error>;
+
+    fn handle(&mut self, msg: GetAuditCheckpoints, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            let mut query = audit_checkpoints::table.into_boxed();
+
+            if let Some(before) = msg.before {
+                query = query.filter(audit_checkpoints::id.lt(before));
+            }
+
+            query
+                .order(audit_checkpoints::id.desc())
+                .limit(msg.limit + 1)
+                .load::<AuditCheckpoint>(conn)
                 .map_err(|e| e.into())
         })
     }
 }
 
-impl Handler<AddCertificateToDomain> for DbExecutor {
-    type Result = Result<Certificate, Error>;
+impl Handler<AddCertificatesToDomain> for DbExecutor {
+    type Result = Result<Vec<Certificate>, Error>;
+
+    fn handle(&mut self, msg: AddCertificatesToDomain, _: &mut Self::Context) -> Self::Result {
+        for cert in &msg.certs {
+            info!("adding certificate \"{}\" version {} to domain {}", cert.friendly_name, cert.id, cert.domain_id);
+        }
 
-    fn handle(&mut self, msg: AddCertificateToDomain, _: &mut Self::Context) -> Self::Result {
-        info!("adding certificate \"{}\" to domain {}", msg.cert.friendly_name, msg.cert.domain_id);
         self.with_connection(|conn| {
-            diesel::replace_into(certificates::table)
-                .values(&msg.cert)
-                .execute(conn)?;
+            // All files of a version become visible at once
+            conn.transaction::<_, Error, _>(|| {
+                for cert in &msg.certs {
+                    diesel::delete(certificates::table.find((&cert.domain_id, cert.id, &cert.friendly_name)))
+                        .execute(conn)?;
+                }
+
+                diesel::insert_into(certificates::table)
+                    .values(&msg.certs)
+                    .execute(conn)?;
 
-            Ok(msg.cert)
+                Ok(msg.certs)
+            })
         })
     }
 }
@@ -328,25 +1659,52 @@ impl Handler<GetCertificate> for DbExecutor {
 
     fn handle(&mut self, msg: GetCertificate, _: &mut Self::Context) -> Self::Result {
         self.with_connection(|conn| {
-            if let Some(id) = &msg.id {
-                certificates::table
-                    .filter(certificates::domain_id.eq(&msg.domain_id))
-                    .filter(certificates::id.eq(id))
-                    .filter(certificates::friendly_name.eq(&msg.friendly_name))
-                    .limit(1)
-                    .load::<Certificate>(conn)
-                    .map_err(|e| e.into())
-                    .and_then(move |f| exactly_one(f, "certificate"))
-            } else {
-                certificates::table
-                    .filter(certificates::domain_id.eq(&msg.domain_id))
-                    .filter(certificates::friendly_name.eq(&msg.friendly_name))
-                    .order(certificates::id.desc())
-                    .limit(1)
-                    .load::<Certificate>(conn)
-                    .map_err(|e| e.into())
-                    .and_then(move |f| exactly_one(f, "certificate"))
+            let find = |id: i32| certificates::table
+                .filter(certificates::domain_id.eq(&msg.domain_id))
+                .filter(certificates::id.eq(id))
+                .filter(certificates::friendly_name.eq(&msg.friendly_name))
+                .limit(1)
+                .load::<Certificate>(conn)
+                .map_err(Error::from)
+                .and_then(move |f| exactly_one(f, "certificate"));
+
+            if let Some(id) = msg.id {
+                return find(id);
+            }
+
+            // Without an explicit version, go by where certbot's live/ link points, if it's known
+            // and has been ingested, and the newest complete version if it hasn't
+            if let Some(live) = live_versions(conn, &msg.domain_id)?.get(&msg.friendly_name).cloned() {
+                match find(live) {
+                    Err(Error::DataNotFound(_)) => (),
+                    found => return found
+                }
+
+                if let Some(id) = latest_complete_version(conn, &msg.domain_id)? {
+                    return find(id);
+                }
             }
+
+            certificates::table
+                .filter(certificates::domain_id.eq(&msg.domain_id))
+                .filter(certificates::friendly_name.eq(&msg.friendly_name))
+                .order(certificates::id.desc())
+                .limit(1)
+                .load::<Certificate>(conn)
+                .map_err(|e| e.into())
+                .and_then(move |f| exactly_one(f, "certificate"))
+        })
+    }
+}
+
+impl Handler<GetCertificates> for DbExecutor {
+    type Result = Result<Vec<Certificate>, Error>;
+
+    fn handle(&mut self, _: GetCertificates, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            certificates::table
+                .load::<Certificate>(conn)
+                .map_err(|e| e.into())
         })
     }
 }
@@ -376,6 +1734,30 @@ impl Handler<GetCertificatesByDomainAndId> for DbExecutor {
                     .load::<Certificate>(conn)
                     .map_err(|e| e.into())
             } else {
+                let live = live_versions(conn, &msg.domain_id)?;
+
+                if !live.is_empty() {
+                    let certs: Vec<Certificate> = certificates::table
+                        .filter(certificates::domain_id.eq(&msg.domain_id))
+                        .load::<Certificate>(conn)?
+                        .into_iter()
+                        .filter(|cert| live.get(&cert.friendly_name) == Some(&cert.id))
+                        .collect();
+
+                    // Links pointing at a version which isn't in yet would leave files out
+                    if certs.len() == live.len() {
+                        return Ok(certs);
+                    }
+
+                    if let Some(id) = latest_complete_version(conn, &msg.domain_id)? {
+                        return certificates::table
+                            .filter(certificates::domain_id.eq(&msg.domain_id))
+                            .filter(certificates::id.eq(id))
+                            .load::<Certificate>(conn)
+                            .map_err(|e| e.into());
+                    }
+                }
+
                 let latest = certificates::table
                     .filter(certificates::domain_id.eq(&msg.domain_id))
                     .order(certificates::id.desc())
@@ -413,4 +1795,343 @@ impl Handler<DeleteCertificateByPath> for DbExecutor {
                 })
         })
     }
-}
\ No newline at end of file
+}
+
+impl Handler<DeleteCertificatesExcept> for DbExecutor {
+    type Result = Result<usize, Error>;
+
+    fn handle(&mut self, msg: DeleteCertificatesExcept, _: &mut Self::Context) -> Self::Result {
+        self.with_connection(|conn| {
+            diesel::delete(certificates::table)
+                .filter(certificates::domain_id.eq(&msg.domain_id))
+                .filter(certificates::path.ne_all(&msg.paths))
+                .execute(conn)
+                .map_err(|e| e.into())
+        })
+    }
+}
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::database::testing::{self, send};
+
+    fn conflicts<T>(result: Result<T, Error>) -> bool {
+        match result {
+            Err(Error::DataConflict(_)) => true,
+            _ => false
+        }
+    }
+
+    #[test]
+    fn names_resolve_to_domains_before_aliases() {
+        let (mut sys, db) = testing::executor();
+        let target = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+        send(&mut sys, &db, CreateDomain { fqdn: "example.org".into() }).unwrap();
+
+        assert!(conflicts(send(&mut sys, &db, CreateDomainAlias { fqdn: "example.org".into(), domain_id: target.id.clone() })));
+
+        let resolved = send(&mut sys, &db, ResolveDomain { fqdn: "example.org".into() }).unwrap();
+        assert_eq!(resolved.fqdn, "example.org");
+    }
+
+    #[test]
+    fn aliases_cannot_be_taken_by_domains() {
+        let (mut sys, db) = testing::executor();
+        let target = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+        send(&mut sys, &db, CreateDomainAlias { fqdn: "www.example.com".into(), domain_id: target.id.clone() }).unwrap();
+
+        assert!(conflicts(send(&mut sys, &db, CreateDomain { fqdn: "www.example.com".into() })));
+
+        send(&mut sys, &db, CreateDomain { fqdn: "example.org".into() }).unwrap();
+        assert!(conflicts(send(&mut sys, &db, RenameDomain { fqdn: "example.org".into(), new_fqdn: "www.example.com".into() })));
+
+        let resolved = send(&mut sys, &db, ResolveDomain { fqdn: "www.example.com".into() }).unwrap();
+        assert_eq!(resolved.id, target.id);
+    }
+
+    #[test]
+    fn promoted_lineages_take_over_the_canonical_name() {
+        let (mut sys, db) = testing::executor();
+        let original = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+        let lineage = send(&mut sys, &db, CreateDomain { fqdn: "example.com-0001".into() }).unwrap();
+
+        send(&mut sys, &db, PromoteDomainLineage { fqdn: lineage.fqdn.clone() }).unwrap();
+
+        let resolved = send(&mut sys, &db, ResolveDomain { fqdn: "example.com".into() }).unwrap();
+        assert_eq!(resolved.id, lineage.id);
+
+        // The watcher reviving the superseded directory doesn't hand the name back
+        assert!(send(&mut sys, &db, SetDomainArchived { fqdn: "example.com".into(), archived: false }).is_err());
+        let superseded = send(&mut sys, &db, GetDomainByFqdn { fqdn: "example.com".into() }).unwrap();
+        assert_eq!(superseded.id, original.id);
+        assert!(superseded.archived);
+    }
+
+    #[test]
+    fn deleting_an_alias_leaves_its_domain_alone() {
+        let (mut sys, db) = testing::executor();
+        let target = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+        send(&mut sys, &db, CreateDomainAlias { fqdn: "www.example.com".into(), domain_id: target.id.clone() }).unwrap();
+
+        assert!(conflicts(send(&mut sys, &db, DeleteDomain { fqdn: "www.example.com".into() })));
+        assert!(send(&mut sys, &db, GetDomainByFqdn { fqdn: "example.com".into() }).is_ok());
+
+        send(&mut sys, &db, DeleteDomain { fqdn: "example.com".into() }).unwrap();
+        assert!(send(&mut sys, &db, ResolveDomain { fqdn: "www.example.com".into() }).is_err());
+    }
+
+    fn certificates(domain_id: &str, version: i32, names: &[&str]) -> Vec<Certificate> {
+        names.iter().map(|name| Certificate {
+            id: version,
+            domain_id: domain_id.into(),
+            friendly_name: format!("{}.pem", name),
+            path: format!("/archive/example.com/{}{}.pem", name, version),
+            is_private: *name == "privkey",
+            not_before: None,
+            not_after: None,
+            modified_at: None,
+            hash: None
+        }).collect()
+    }
+
+    #[test]
+    fn live_links_ahead_of_ingestion_fall_back_to_the_latest_complete_version() {
+        let (mut sys, db) = testing::executor();
+        let domain = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+        let all = ["cert", "chain", "fullchain", "privkey"];
+
+        send(&mut sys, &db, AddCertificatesToDomain { certs: certificates(&domain.id, 1, &all) }).unwrap();
+        send(&mut sys, &db, AddCertificatesToDomain { certs: certificates(&domain.id, 2, &all[..2]) }).unwrap();
+
+        for name in &all {
+            send(&mut sys, &db, SetLiveVersion { fqdn: domain.fqdn.clone(), friendly_name: format!("{}.pem", name), version: 3 }).unwrap();
+        }
+
+        let cert = send(&mut sys, &db, GetCertificate { domain_id: domain.id.clone(), id: None, friendly_name: "cert.pem".into() }).unwrap();
+        assert_eq!(cert.id, 1);
+
+        let latest = send(&mut sys, &db, GetCertificatesByDomainAndId { domain_id: domain.id.clone(), id: None }).unwrap();
+        assert_eq!(latest.len(), 4);
+        assert!(latest.iter().all(|cert| cert.id == 1));
+
+        // Once the version the links point at is in, it's served
+        send(&mut sys, &db, AddCertificatesToDomain { certs: certificates(&domain.id, 3, &all) }).unwrap();
+        let cert = send(&mut sys, &db, GetCertificate { domain_id: domain.id.clone(), id: None, friendly_name: "cert.pem".into() }).unwrap();
+        assert_eq!(cert.id, 3);
+    }
+
+    fn page(limit: i64, after: Option<(String, String)>) -> ListQuery {
+        ListQuery { q: None, descending: false, after, limit }
+    }
+
+    #[test]
+    fn audit_log_pages_continue_below_the_last_entry() {
+        let (mut sys, db) = testing::executor();
+
+        for action in &["one", "two", "three", "four", "five"] {
+            send(&mut sys, &db, RecordAudit { entry: NewAuditEntry {
+                occurred_at: Utc::now().naive_utc(),
+                subject: None,
+                client_ip: None,
+                action: action.to_string(),
+                fqdn: None,
+                version: None,
+                friendly_name: None,
+                is_private: None,
+                target: None,
+                details: None,
+                result: "success".into(),
+                previous_hash: None,
+                hash: None
+            }}).unwrap();
+        }
+
+        let actions = |entries: Vec<AuditEntry>| entries.into_iter().map(|entry| entry.action).collect::<Vec<_>>();
+
+        // One more than asked for, to tell there's another page
+        let first = send(&mut sys, &db, GetAuditLog { filter: AuditFilter::default(), before: None, limit: 2 }).unwrap();
+        let last_id = first[1].id;
+        assert_eq!(actions(first), vec!["five", "four", "three"]);
+
+        let second = send(&mut sys, &db, GetAuditLog { filter: AuditFilter::default(), before: Some(last_id), limit: 2 }).unwrap();
+        assert_eq!(actions(second), vec!["three", "two", "one"]);
+
+        let filtered = AuditFilter { action: Some("two".into()), ..AuditFilter::default() };
+        assert_eq!(actions(send(&mut sys, &db, GetAuditLog { filter: filtered, before: Some(last_id), limit: 2 }).unwrap()), vec!["two"]);
+    }
+
+    #[test]
+    fn quarantine_and_aliases_are_listed_by_name() {
+        let (mut sys, db) = testing::executor();
+        let domain = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+
+        for alias in &["c.example.com", "a.example.com", "b.example.com"] {
+            send(&mut sys, &db, CreateDomainAlias { fqdn: alias.to_string(), domain_id: domain.id.clone() }).unwrap();
+        }
+
+        let aliases = send(&mut sys, &db, ListDomainAliases { id: domain.id.clone(), query: page(1, None) }).unwrap();
+        assert_eq!(aliases.iter().map(|alias| alias.fqdn.as_str()).collect::<Vec<_>>(), vec!["a.example.com", "b.example.com"]);
+
+        let after = Some((aliases[0].fqdn.clone(), aliases[0].hashed_fqdn.clone()));
+        let aliases = send(&mut sys, &db, ListDomainAliases { id: domain.id.clone(), query: page(5, after) }).unwrap();
+        assert_eq!(aliases.iter().map(|alias| alias.fqdn.as_str()).collect::<Vec<_>>(), vec!["b.example.com", "c.example.com"]);
+
+        let files = ["/archive/example.com/cert2.pem", "/archive/example.com/chain2.pem", "/archive/example.org/cert1.pem"];
+        send(&mut sys, &db, QuarantineFiles { files: files.iter().map(|path| QuarantinedFile {
+            path: path.to_string(),
+            hashed_path: CryptoUtil::hash_string(path),
+            fqdn: if path.contains("example.org") { "example.org" } else { "example.com" }.into(),
+            kind: "parse".into(),
+            message: "unreadable".into(),
+            hash: None,
+            occurred_at: Utc::now().naive_utc()
+        }).collect() }).unwrap();
+
+        let quarantined = send(&mut sys, &db, ListQuarantine { fqdn: Some("example.com".into()), query: page(1, None) }).unwrap();
+        assert_eq!(quarantined.iter().map(|file| file.path.as_str()).collect::<Vec<_>>(), &files[..2]);
+    }
+
+    #[test]
+    fn group_members_are_embedded_a_page_at_a_time() {
+        let (mut sys, db) = testing::executor();
+        let big = send(&mut sys, &db, CreateGroup { friendly_name: "big".into(), permission: "public".into() }).unwrap();
+        let small = send(&mut sys, &db, CreateGroup { friendly_name: "small".into(), permission: "public".into() }).unwrap();
+
+        let users: Vec<User> = (0..5)
+            .map(|i| send(&mut sys, &db, CreateUser { friendly_name: format!("user{}", i), hashed_key: String::new() }).unwrap().0)
+            .collect();
+
+        send(&mut sys, &db, SetGroupUsers { user_ids: users.iter().map(|user| user.id.clone()).collect(), group_id: big.id.clone() }).unwrap();
+        send(&mut sys, &db, SetGroupUsers { user_ids: vec![users[0].id.clone()], group_id: small.id.clone() }).unwrap();
+
+        let members = send(&mut sys, &db, GetGroupMembers { ids: vec![big.id.clone(), small.id.clone()], query: page(2, None) }).unwrap();
+        let names = |users: &[User]| users.iter().map(|user| user.friendly_name.clone()).collect::<Vec<_>>();
+
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].0, big.id);
+        assert_eq!(names(&members[0].1), vec!["user0", "user1", "user2"]);
+        assert_eq!(members[1].0, small.id);
+        assert_eq!(names(&members[1].1), vec!["user0"]);
+    }
+
+    #[test]
+    fn only_clashing_rows_are_conflicts() {
+        use diesel::connection::SimpleConnection;
+        use crate::errors::ServiceError;
+
+        let pool = testing::pool();
+        let conn = pool.get().unwrap();
+
+        conn.batch_execute("
+            INSERT INTO users (id, friendly_name, disabled) VALUES ('user', 'user', 0);
+            INSERT INTO groups (id, friendly_name, permission) VALUES ('group', 'group', 'public');
+            INSERT INTO user_group_mappings (user_id, group_id) VALUES ('user', 'group');
+        ").unwrap();
+
+        // What a request adding the same member at the same time runs into
+        let added = diesel::insert_into(user_group_mappings::table)
+            .values(&UserGroupMapping { user_id: "user".into(), group_id: "group".into() })
+            .execute(&*conn)
+            .map_err(|e| changed_concurrently(e, "users"));
+
+        match added.map_err(ServiceError::from) {
+            Err(ServiceError::Conflict(_)) => (),
+            _ => panic!("a duplicate member should be a conflict")
+        }
+
+        match diesel::sql_query("SELECT * FROM missing").execute(&*conn).map_err(Error::from) {
+            Err(Error::DieselError(_)) => (),
+            _ => panic!("a broken query isn't a conflict")
+        }
+    }
+
+    #[test]
+    fn group_domains_are_found_by_any_spelling_of_their_names() {
+        let (mut sys, db) = testing::executor();
+        let group = send(&mut sys, &db, CreateGroup { friendly_name: "group".into(), permission: "public".into() }).unwrap();
+        let domain = send(&mut sys, &db, CreateDomain { fqdn: "example.com".into() }).unwrap();
+        send(&mut sys, &db, CreateDomainAlias { fqdn: "www.example.com".into(), domain_id: domain.id.clone() }).unwrap();
+
+        let created = send(&mut sys, &db, SetGroupDomains {
+            domains: vec!["Example.COM.".into(), "www.example.com".into(), "New.Example.org.".into()],
+            group_id: group.id.clone(),
+            create_missing: true
+        }).unwrap();
+        assert_eq!(created, vec!["new.example.org"]);
+
+        let domains = send(&mut sys, &db, ListGroupDomains { id: group.id.clone(), query: page(10, None) }).unwrap();
+        assert_eq!(domains.iter().map(|domain| domain.fqdn.as_str()).collect::<Vec<_>>(), vec!["example.com", "new.example.org"]);
+
+        // The placeholder is found again however it's written, rather than created twice
+        let created = send(&mut sys, &db, AddGroupDomain { domain: "NEW.example.org".into(), group_id: group.id.clone(), create_missing: true }).unwrap();
+        assert!(created.is_empty());
+    }
+
+    #[test]
+    fn groups_cannot_take_each_others_names() {
+        use crate::errors::ServiceError;
+
+        let (mut sys, db) = testing::executor();
+        let group = send(&mut sys, &db, CreateGroup { friendly_name: "one".into(), permission: "public".into() }).unwrap();
+        send(&mut sys, &db, CreateGroup { friendly_name: "two".into(), permission: "public".into() }).unwrap();
+
+        let renamed = send(&mut sys, &db, UpdateGroup { id: group.id.clone(), friendly_name: Some("two".into()), permission: None });
+        match renamed.map_err(ServiceError::from) {
+            Err(ServiceError::Conflict(_)) => (),
+            _ => panic!("renaming onto another group should be a conflict")
+        }
+
+        let created = send(&mut sys, &db, CreateGroup { friendly_name: "one".into(), permission: "public".into() });
+        match created.map_err(ServiceError::from) {
+            Err(ServiceError::Conflict(_)) => (),
+            _ => panic!("creating a group under a taken name should be a conflict")
+        }
+
+        // Keeping its own name isn't taking another group's
+        send(&mut sys, &db, UpdateGroup { id: group.id.clone(), friendly_name: Some("one".into()), permission: Some("private".into()) }).unwrap();
+    }
+
+    #[test]
+    fn users_hold_the_roles_of_their_groups() {
+        let (mut sys, db) = testing::executor();
+        let (user, _) = send(&mut sys, &db, CreateUser { friendly_name: "alice".into(), hashed_key: String::new() }).unwrap();
+        let group = send(&mut sys, &db, CreateGroup { friendly_name: "managers".into(), permission: "public".into() }).unwrap();
+        send(&mut sys, &db, SetUserRoles { id: user.id.clone(), roles: vec!["users:manage".into()] }).unwrap();
+        send(&mut sys, &db, SetGroupRoles { id: group.id.clone(), roles: vec!["groups:manage".into(), "users:manage".into()] }).unwrap();
+        send(&mut sys, &db, AddGroupUser { user_id: user.id.clone(), group_id: group.id.clone() }).unwrap();
+
+        let own = send(&mut sys, &db, GetUserRoles { id: user.id.clone(), inherited: false }).unwrap();
+        assert_eq!(own, vec!["users:manage".to_string()]);
+
+        // Which is what whoever hands out a key of theirs has to hold
+        let held = send(&mut sys, &db, GetUserRoles { id: user.id.clone(), inherited: true }).unwrap();
+        assert_eq!(held, vec!["groups:manage".to_string(), "users:manage".to_string()]);
+    }
+
+    #[test]
+    fn deleted_users_take_only_their_own_sessions_along() {
+        let (mut sys, db) = testing::executor();
+        let (alice, _) = send(&mut sys, &db, CreateUser { friendly_name: "alice".into(), hashed_key: String::new() }).unwrap();
+        let (bob, _) = send(&mut sys, &db, CreateUser { friendly_name: "bob".into(), hashed_key: String::new() }).unwrap();
+
+        let now = Utc::now().naive_utc();
+        for (jti, subject) in vec![("alice", &alice.id), ("bob", &bob.id)] {
+            send(&mut sys, &db, RecordRefreshToken { entry: RefreshTokenEntry {
+                jti: jti.into(),
+                subject: subject.clone(),
+                issued_at: now,
+                expires_at: now + Duration::hours(1),
+                revoked_at: None,
+                key_id: None
+            }}).unwrap();
+        }
+
+        // Names are free text, so one which looks like another user's id takes nothing of theirs
+        send(&mut sys, &db, UpdateUser { id: alice.id.clone(), friendly_name: Some(bob.id.clone()), disabled: None }).unwrap();
+        assert!(send(&mut sys, &db, GetRefreshToken { jti: "alice".into() }).is_ok());
+
+        send(&mut sys, &db, DeleteUser { id: alice.id }).unwrap();
+        assert!(send(&mut sys, &db, GetRefreshToken { jti: "alice".into() }).is_err());
+        assert!(send(&mut sys, &db, GetRefreshToken { jti: "bob".into() }).is_ok());
+    }
+}
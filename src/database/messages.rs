@@ -1,27 +1,82 @@
+use chrono::NaiveDateTime;
 use super::models::*;
 use super::errors::Error;
 
 actor_command_new! (CreateDomain(fqdn: String) -> Result<Domain, Error>);
 actor_command_new! (DeleteDomain(fqdn: String) -> Result<(), Error>);
+actor_command_new! (RenameDomain(fqdn: String, new_fqdn: String) -> Result<Domain, Error>);
+actor_command_new! (SetDomainArchived(fqdn: String, archived: bool) -> Result<(), Error>);
+actor_command_new! (GetDomains() -> Result<Vec<Domain>, Error>);
+actor_command_new! (ListDomains(query: ListQuery, fqdns: Option<Vec<String>>) -> Result<Vec<Domain>, Error>);
 actor_command_new! (GetDomainByFqdn(fqdn: String) -> Result<Domain, Error>);
+actor_command_new! (ResolveDomain(fqdn: String) -> Result<Domain, Error>);
+actor_command_new! (PromoteDomainLineage(fqdn: String) -> Result<Domain, Error>);
 
-actor_command_new! (CreateUser(friendly_name: String, hashed_key: String) -> Result<User, Error>);
+actor_command_new! (CreateDomainAlias(fqdn: String, domain_id: String) -> Result<DomainAlias, Error>);
+actor_command_new! (DeleteDomainAlias(fqdn: String, domain_id: String) -> Result<(), Error>);
+actor_command_new! (GetAliasesByDomain(id: String) -> Result<Vec<DomainAlias>, Error>);
+actor_command_new! (ListDomainAliases(id: String, query: ListQuery) -> Result<Vec<DomainAlias>, Error>);
+
+// Along with the user, returns their default key
+actor_command_new! (CreateUser(friendly_name: String, hashed_key: String) -> Result<(User, ApiKey), Error>);
+actor_command_new! (CreateApiKey(user_id: String, label: String, hashed_key: String, expires_at: Option<NaiveDateTime>, scope_domains: Option<Vec<String>>, scope_permissions: Option<Vec<String>>) -> Result<ApiKey, Error>);
+actor_command_new! (GetApiKeys(user_id: String) -> Result<Vec<ApiKey>, Error>);
+// Keys are referred to by id, and to the user's default key by none
+actor_command_new! (GetApiKey(user_id: String, id: Option<String>) -> Result<ApiKey, Error>);
+actor_command_new! (RotateApiKey(user_id: String, key: Option<String>, hashed_key: String, previous_key_expires_at: Option<NaiveDateTime>) -> Result<ApiKey, Error>);
+actor_command_new! (RevokeApiKey(user_id: String, key: String) -> Result<(), Error>);
+actor_command_new! (TouchApiKey(id: String) -> Result<(), Error>);
+actor_command_new! (RecordRefreshToken(entry: RefreshTokenEntry) -> Result<(), Error>);
+actor_command_new! (GetRefreshToken(jti: String) -> Result<RefreshTokenEntry, Error>);
+actor_command_new! (RevokeRefreshToken(jti: String) -> Result<(), Error>);
+actor_command_new! (RevokeRefreshTokens(user_id: String) -> Result<usize, Error>);
+actor_command_new! (UpdateUser(id: String, friendly_name: Option<String>, disabled: Option<bool>) -> Result<User, Error>);
+actor_command_new! (DeleteUser(id: String) -> Result<(), Error>);
+actor_command_new! (ListUsers(query: ListQuery) -> Result<Vec<User>, Error>);
 actor_command_new! (GetUserByName(friendly_name: String) -> Result<User, Error>);
 actor_command_new! (GetUser(id: String) -> Result<User, Error>);
 actor_command_new! (GetUserPermissions(id: String) -> Result<Vec<DomainPermission>, Error>);
+actor_command_new! (GetUserRoles(id: String, inherited: bool) -> Result<Vec<String>, Error>);
+actor_command_new! (SetUserRoles(id: String, roles: Vec<String>) -> Result<(), Error>);
 
-actor_command_new! (CreateGroup(friendly_name: String) -> Result<Group, Error>);
+actor_command_new! (CreateGroup(friendly_name: String, permission: String) -> Result<Group, Error>);
+actor_command_new! (UpdateGroup(id: String, friendly_name: Option<String>, permission: Option<String>) -> Result<Group, Error>);
 actor_command_new! (GetGroup(id: String) -> Result<Group, Error>);
 actor_command_new! (GetGroupsByDomain(id: String) -> Result<Vec<Group>, Error>);
 actor_command_new! (GetGroupsByUser(id: String) -> Result<Vec<Group>, Error>);
+actor_command_new! (GetGroupRoles(id: String) -> Result<Vec<String>, Error>);
+actor_command_new! (SetGroupRoles(id: String, roles: Vec<String>) -> Result<(), Error>);
 actor_command_new! (SetGroupUsers(user_ids: Vec<String>, group_id: String) -> Result<(), Error>);
-actor_command_new! (SetGroupDomains(fqdns: Vec<String>, group_id: String) -> Result<(), Error>);
-actor_command_new! (GetUsersByGroup(id: String) -> Result<Vec<User>, Error>);
-actor_command_new! (GetDomainsByGroup(id: String) -> Result<Vec<Domain>, Error>);
-actor_command_new! (GetGroups() -> Result<Vec<Group>, Error>);
+// Returns the names of the placeholder domains created for missing ones, as does AddGroupDomain
+actor_command_new! (SetGroupDomains(domains: Vec<String>, group_id: String, create_missing: bool) -> Result<Vec<String>, Error>);
+actor_command_new! (AddGroupUser(user_id: String, group_id: String) -> Result<(), Error>);
+actor_command_new! (RemoveGroupUser(user_id: String, group_id: String) -> Result<(), Error>);
+actor_command_new! (AddGroupDomain(domain: String, group_id: String, create_missing: bool) -> Result<Vec<String>, Error>);
+actor_command_new! (RemoveGroupDomain(domain: String, group_id: String) -> Result<(), Error>);
+actor_command_new! (ListGroups(query: ListQuery) -> Result<Vec<Group>, Error>);
+actor_command_new! (ListGroupUsers(id: String, query: ListQuery) -> Result<Vec<User>, Error>);
+actor_command_new! (ListGroupDomains(id: String, query: ListQuery) -> Result<Vec<Domain>, Error>);
+actor_command_new! (GetGroupMembers(ids: Vec<String>, query: ListQuery) -> Result<Vec<(String, Vec<User>, Vec<Domain>)>, Error>);
+
+actor_command_new! (SetLiveVersion(fqdn: String, friendly_name: String, version: i32) -> Result<(), Error>);
+actor_command_new! (ClearLiveVersions(fqdn: String, friendly_name: Option<String>) -> Result<(), Error>);
+
+actor_command_new! (QuarantineFiles(files: Vec<QuarantinedFile>) -> Result<(), Error>);
+actor_command_new! (ReleaseFiles(paths: Vec<String>) -> Result<usize, Error>);
+actor_command_new! (GetQuarantine(fqdn: Option<String>) -> Result<Vec<QuarantinedFile>, Error>);
+actor_command_new! (ListQuarantine(fqdn: Option<String>, query: ListQuery) -> Result<Vec<QuarantinedFile>, Error>);
+
+actor_command_new! (RecordAudit(entry: NewAuditEntry) -> Result<(), Error>);
+actor_command_new! (GetAuditLog(filter: AuditFilter, before: Option<i64>, limit: i64) -> Result<Vec<AuditEntry>, Error>);
+actor_command_new! (GetAuditHead() -> Result<Option<(i64, String)>, Error>);
+actor_command_new! (VerifyAuditChain(trusted_keys: Vec<String>) -> Result<ChainVerification, Error>);
+actor_command_new! (AddAuditCheckpoint(checkpoint: NewAuditCheckpoint) -> Result<AuditCheckpoint, Error>);
+actor_command_new! (GetAuditCheckpoints(before: Option<i64>, limit: i64) -> Result<Vec<AuditCheckpoint>, Error>);
 
-actor_command_new! (AddCertificateToDomain(cert: Certificate) -> Result<Certificate, Error>);
+actor_command_new! (AddCertificatesToDomain(certs: Vec<Certificate>) -> Result<Vec<Certificate>, Error>);
 actor_command_new! (DeleteCertificateByPath(path: String) -> Result<(), Error>);
+actor_command_new! (DeleteCertificatesExcept(domain_id: String, paths: Vec<String>) -> Result<usize, Error>);
+actor_command_new! (GetCertificates() -> Result<Vec<Certificate>, Error>);
 actor_command_new! (GetCertificatesByDomain(id: String) -> Result<Vec<Certificate>, Error>);
 actor_command_new! (GetCertificatesByDomainAndId(domain_id: String, id: Option<i32>) -> Result<Vec<Certificate>, Error>);
 actor_command_new! (GetCertificate(domain_id: String, id: Option<i32>, friendly_name: String) -> Result<Certificate, Error>);
\ No newline at end of file
@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use diesel_migrations::MigrationConnection;
+#[cfg(feature = "sqlite")]
+use diesel::connection::SimpleConnection;
+use super::DbConnection;
+use super::errors::Error;
+
+// The migrations of the enabled backend are compiled into the binary, so
+// the schema it expects always travels along with it
+#[allow(dead_code)]
+mod embedded_migrations {
+    #[derive(EmbedMigrations)]
+    #[cfg_attr(feature = "mysql", embed_migrations_options(migrations_path = "migrations/mysql"))]
+    #[cfg_attr(feature = "postgres", embed_migrations_options(migrations_path = "migrations/postgres"))]
+    #[cfg_attr(feature = "sqlite", embed_migrations_options(migrations_path = "migrations/sqlite"))]
+    struct _Dummy;
+
+    pub fn versions() -> Vec<String> {
+        ALL_MIGRATIONS.iter().map(|migration| migration.version().to_string()).collect()
+    }
+}
+
+fn describe(versions: &[String]) -> String {
+    versions.join(", ")
+}
+
+// Checks the schema version of the database against the embedded migrations,
+// applying any pending ones if `apply` is set. A database which has seen
+// migrations this binary doesn't know about was upgraded by a newer rublic,
+// and is never touched
+pub fn prepare(conn: &DbConnection, apply: bool) -> Result<(), Error> {
+    diesel_migrations::setup_database(conn)?;
+
+    let embedded = embedded_migrations::versions();
+    let applied: HashSet<String> = conn.previously_run_migration_versions()?;
+
+    let mut unknown: Vec<String> = applied.iter()
+        .filter(|version| !embedded.contains(version))
+        .cloned()
+        .collect();
+
+    if !unknown.is_empty() {
+        unknown.sort();
+        return Err(Error::UnknownSchema(format!("the database has migrations {} which this version of rublic doesn't know", describe(&unknown))));
+    }
+
+    let pending: Vec<String> = embedded.into_iter()
+        .filter(|version| !applied.contains(version))
+        .collect();
+
+    if pending.is_empty() {
+        info!("database schema is up to date");
+        return Ok(());
+    }
+
+    if !apply {
+        return Err(Error::PendingMigrations(format!("{} have to be applied, either by setting RUBLIC_AUTO_MIGRATE or running with --migrate-only", describe(&pending))));
+    }
+
+    info!("applying migrations: {}", describe(&pending));
+
+    // SQLite can't drop columns, so its migrations rebuild tables instead, which would take every
+    // row referring to them along while foreign keys are enforced. That can only be switched off
+    // outside of a transaction, so it's done around all of the migrations rather than in them
+    #[cfg(feature = "sqlite")]
+    conn.batch_execute("PRAGMA foreign_keys = OFF;")?;
+
+    let mut output = Vec::new();
+    let applied = embedded_migrations::run_with_output(conn, &mut output);
+
+    #[cfg(feature = "sqlite")]
+    conn.batch_execute("PRAGMA foreign_keys = ON;")?;
+
+    applied?;
+
+    for line in String::from_utf8_lossy(&output).lines() {
+        info!("{}", line);
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use std::path::Path;
+    use diesel::prelude::*;
+    use diesel::connection::SimpleConnection;
+    use crate::schema::user_group_mappings;
+    use crate::database::{DbConnection, testing};
+    use super::prepare;
+
+    fn memberships(conn: &DbConnection) -> i64 {
+        user_group_mappings::table.count().get_result(conn).unwrap()
+    }
+
+    #[test]
+    fn rebuilt_tables_keep_the_rows_referring_to_them() {
+        let pool = testing::pool();
+        let conn = pool.get().unwrap();
+
+        conn.batch_execute("
+            INSERT INTO users (id, friendly_name, disabled) VALUES ('user', 'user', 0);
+            INSERT INTO groups (id, friendly_name, permission) VALUES ('group', 'group', 'public');
+            INSERT INTO user_group_mappings (user_id, group_id) VALUES ('user', 'group');
+        ").unwrap();
+
+        // Back to before API keys, which rebuilds the users table on the way down and up again
+        conn.batch_execute("PRAGMA foreign_keys = OFF;").unwrap();
+        for _ in 0..3 {
+            diesel_migrations::revert_latest_migration_in_directory(&*conn, Path::new("migrations/sqlite")).unwrap();
+        }
+        conn.batch_execute("PRAGMA foreign_keys = ON;").unwrap();
+        assert_eq!(memberships(&conn), 1);
+
+        prepare(&conn, true).unwrap();
+        assert_eq!(memberships(&conn), 1);
+    }
+}
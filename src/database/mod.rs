@@ -1,16 +1,39 @@
 pub mod models;
 pub mod messages;
 pub mod errors;
+pub mod migrations;
+pub mod audit;
 mod handlers;
 
+#[cfg(all(test, feature = "sqlite"))]
+pub mod testing;
+
 // models.rs
 use actix::{Actor, SyncContext};
-use diesel::mysql::MysqlConnection;
 use diesel::r2d2::{ConnectionManager, Pool};
 use self::errors::Error;
 
+#[cfg(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")))]
+compile_error!("one of the mysql, postgres or sqlite features has to be enabled");
+
+#[cfg(any(
+    all(feature = "mysql", feature = "postgres"),
+    all(feature = "mysql", feature = "sqlite"),
+    all(feature = "postgres", feature = "sqlite")
+))]
+compile_error!("only one of the mysql, postgres or sqlite features may be enabled, try --no-default-features");
+
+#[cfg(feature = "mysql")]
+pub type DbConnection = diesel::mysql::MysqlConnection;
+
+#[cfg(feature = "postgres")]
+pub type DbConnection = diesel::pg::PgConnection;
+
+#[cfg(feature = "sqlite")]
+pub type DbConnection = diesel::sqlite::SqliteConnection;
+
 /// This is db executor actor. can be run in parallel
-pub struct DbExecutor(pub Pool<ConnectionManager<MysqlConnection>>);
+pub struct DbExecutor(pub Pool<ConnectionManager<DbConnection>>);
 
 impl Actor for DbExecutor {
     type Context = SyncContext<Self>;
@@ -18,7 +41,7 @@ impl Actor for DbExecutor {
 
 impl DbExecutor {
     fn with_connection<T, F>(&mut self, func: F) -> Result<T, Error>
-        where F: FnOnce(&MysqlConnection) -> Result<T, Error> {
+        where F: FnOnce(&DbConnection) -> Result<T, Error> {
         match self.0.get() {
             Ok(conn) => match func(&conn) {
                 Ok(result) => Ok(result),
@@ -27,4 +50,31 @@ impl DbExecutor {
             Err(_) => Err(Error::Unknown)
         }
     }
-}
\ No newline at end of file
+}
+
+pub fn create_pool(url: &str) -> Pool<ConnectionManager<DbConnection>> {
+    let manager = ConnectionManager::<DbConnection>::new(url);
+    let builder = Pool::builder();
+
+    #[cfg(feature = "sqlite")]
+    let builder = builder.connection_customizer(Box::new(SqliteCustomizer));
+
+    builder.build(manager)
+        .expect("Failed to create pool.")
+}
+
+// SQLite leaves foreign keys unenforced unless asked to, which the cascading deletes
+// rely on, and fails straight away rather than waiting when another connection writes
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+struct SqliteCustomizer;
+
+#[cfg(feature = "sqlite")]
+impl diesel::r2d2::CustomizeConnection<DbConnection, diesel::r2d2::Error> for SqliteCustomizer {
+    fn on_acquire(&self, conn: &mut DbConnection) -> Result<(), diesel::r2d2::Error> {
+        use diesel::connection::SimpleConnection;
+
+        conn.batch_execute("PRAGMA foreign_keys = ON; PRAGMA busy_timeout = 5000;")
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
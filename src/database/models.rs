@@ -1,18 +1,105 @@
 use crate::schema::*;
 use crate::chrono::{NaiveDateTime};
+use crate::cryptoutil::CryptoUtil;
 
 #[derive(Identifiable, Queryable, Insertable, Associations)]
 pub struct User {
     pub id: String,
     pub friendly_name: String,
-    pub hashed_key: String
+    pub disabled: bool
+}
+
+// Every user gets a key along with their account, named so it can be told apart from any added later
+pub const DEFAULT_KEY_LABEL: &str = "default";
+
+#[derive(Identifiable, Queryable, Insertable, Associations)]
+#[table_name = "api_keys"]
+#[belongs_to(User)]
+pub struct ApiKey {
+    pub id: String,
+    pub user_id: String,
+    pub label: String,
+    pub hashed_key: String,
+    pub previous_hashed_key: Option<String>,
+    pub previous_key_expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub scope_domains: Option<String>,
+    pub scope_permissions: Option<String>
+}
+
+// Credentials name the key they belong to, so only that one has to be checked: they are the
+// id of the key and its secret, separated by a dot. Those handed out before they did are all
+// default keys, since users had no others then
+pub fn split_credential(credential: &str) -> (Option<&str>, &str) {
+    let mut parts = credential.splitn(2, '.');
+
+    match (parts.next(), parts.next()) {
+        (Some(id), Some(secret)) => (Some(id), secret),
+        _ => (None, credential)
+    }
+}
+
+impl ApiKey {
+    // What the holder of the key presents, given its secret
+    pub fn credential(&self, secret: &str) -> String {
+        format!("{}.{}", self.id, secret)
+    }
+
+    // The current key, or the one it replaced while its grace period lasts,
+    // as long as the key itself hasn't expired
+    pub fn accepts_key(&self, key: &str, now: NaiveDateTime) -> bool {
+        if self.expires_at.map_or(false, |expires_at| expires_at <= now) {
+            return false;
+        }
+
+        if CryptoUtil::check_key(key, &self.hashed_key) {
+            return true;
+        }
+
+        match (&self.previous_hashed_key, self.previous_key_expires_at) {
+            (Some(previous), Some(expires_at)) => expires_at > now && CryptoUtil::check_key(key, previous),
+            _ => false
+        }
+    }
+
+    // The domains the key is restricted to, if it is. A scope which can't
+    // be read restricts the key to nothing rather than lifting the restriction
+    pub fn domains(&self) -> Option<Vec<String>> {
+        self.scope_domains.as_ref().map(|scope| serde_json::from_str(scope).unwrap_or_default())
+    }
+
+    pub fn permissions(&self) -> Option<Vec<String>> {
+        self.scope_permissions.as_ref().map(|scope| serde_json::from_str(scope).unwrap_or_default())
+    }
 }
 
 #[derive(Identifiable, Queryable, Insertable, Associations)]
 pub struct Domain {
     pub id: String,
     pub fqdn: String,
-    pub hashed_fqdn: String
+    pub hashed_fqdn: String,
+    pub archived: bool
+}
+
+#[derive(Identifiable, Queryable, Insertable, Associations)]
+#[table_name = "domain_aliases"]
+#[primary_key(hashed_fqdn)]
+#[belongs_to(Domain)]
+pub struct DomainAlias {
+    pub fqdn: String,
+    pub hashed_fqdn: String,
+    pub domain_id: String
+}
+
+#[derive(Identifiable, Queryable, Insertable)]
+#[primary_key(hashed_fqdn, friendly_name)]
+pub struct LiveVersion {
+    pub fqdn: String,
+    pub hashed_fqdn: String,
+    pub friendly_name: String,
+    pub version: i32
 }
 
 #[derive(Identifiable, Queryable, Insertable, Associations)]
@@ -22,6 +109,18 @@ pub struct Group {
     pub permission: String,
 }
 
+#[derive(Identifiable, Queryable, Insertable)]
+#[table_name = "refresh_tokens"]
+#[primary_key(jti)]
+pub struct RefreshTokenEntry {
+    pub jti: String,
+    pub subject: String,
+    pub issued_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub key_id: Option<String>
+}
+
 #[derive(Identifiable, Queryable, Insertable, Associations)]
 #[primary_key(domain_id, group_id)]
 #[belongs_to(Domain)]
@@ -40,6 +139,22 @@ pub struct UserGroupMapping {
     pub group_id: String
 }
 
+#[derive(Identifiable, Queryable, Insertable, Associations)]
+#[primary_key(user_id, role)]
+#[belongs_to(User)]
+pub struct UserRole {
+    pub user_id: String,
+    pub role: String
+}
+
+#[derive(Identifiable, Queryable, Insertable, Associations)]
+#[primary_key(group_id, role)]
+#[belongs_to(Group)]
+pub struct GroupRole {
+    pub group_id: String,
+    pub role: String
+}
+
 #[derive(Identifiable, Queryable, Insertable, Associations, Debug)]
 #[primary_key(domain_id, id, friendly_name)]
 pub struct Certificate {
@@ -49,7 +164,176 @@ pub struct Certificate {
     pub path: String,
     pub is_private: bool,
     pub not_before: Option<NaiveDateTime>,
-    pub not_after: Option<NaiveDateTime>
+    pub not_after: Option<NaiveDateTime>,
+    pub modified_at: Option<NaiveDateTime>,
+    pub hash: Option<String>
+}
+
+#[derive(Identifiable, Queryable, Insertable)]
+#[table_name = "quarantine"]
+#[primary_key(hashed_path)]
+pub struct QuarantinedFile {
+    pub path: String,
+    pub hashed_path: String,
+    pub fqdn: String,
+    pub kind: String,
+    pub message: String,
+    pub hash: Option<String>,
+    pub occurred_at: NaiveDateTime
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "audit_log"]
+pub struct AuditEntry {
+    pub id: i64,
+    pub occurred_at: NaiveDateTime,
+    pub subject: Option<String>,
+    pub client_ip: Option<String>,
+    pub action: String,
+    pub fqdn: Option<String>,
+    pub version: Option<i32>,
+    pub friendly_name: Option<String>,
+    pub is_private: Option<bool>,
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub result: String,
+    pub previous_hash: Option<String>,
+    pub hash: Option<String>
+}
+
+// The hash and the link to the previous entry are filled in when the entry is recorded
+#[derive(Insertable)]
+#[table_name = "audit_log"]
+pub struct NewAuditEntry {
+    pub occurred_at: NaiveDateTime,
+    pub subject: Option<String>,
+    pub client_ip: Option<String>,
+    pub action: String,
+    pub fqdn: Option<String>,
+    pub version: Option<i32>,
+    pub friendly_name: Option<String>,
+    pub is_private: Option<bool>,
+    pub target: Option<String>,
+    pub details: Option<String>,
+    pub result: String,
+    pub previous_hash: Option<String>,
+    pub hash: Option<String>
+}
+
+impl NewAuditEntry {
+    // Covers everything but the id, which isn't known until the entry has been inserted
+    pub fn compute_hash(&self) -> String {
+        let contents = (
+            &self.occurred_at,
+            &self.subject,
+            &self.client_ip,
+            &self.action,
+            &self.fqdn,
+            &self.version,
+            &self.friendly_name,
+            &self.is_private,
+            &self.target,
+            &self.details,
+            &self.result,
+            &self.previous_hash
+        );
+
+        CryptoUtil::hash_string(&serde_json::to_string(&contents).unwrap())
+    }
+}
+
+impl<'a> From<&'a AuditEntry> for NewAuditEntry {
+    fn from(entry: &'a AuditEntry) -> Self {
+        NewAuditEntry {
+            occurred_at: entry.occurred_at,
+            subject: entry.subject.clone(),
+            client_ip: entry.client_ip.clone(),
+            action: entry.action.clone(),
+            fqdn: entry.fqdn.clone(),
+            version: entry.version,
+            friendly_name: entry.friendly_name.clone(),
+            is_private: entry.is_private,
+            target: entry.target.clone(),
+            details: entry.details.clone(),
+            result: entry.result.clone(),
+            previous_hash: entry.previous_hash.clone(),
+            hash: entry.hash.clone()
+        }
+    }
+}
+
+#[derive(Identifiable, Queryable)]
+#[table_name = "audit_checkpoints"]
+pub struct AuditCheckpoint {
+    pub id: i64,
+    pub created_at: NaiveDateTime,
+    pub entry_id: i64,
+    pub entry_hash: String,
+    pub public_key: String,
+    pub signature: String
+}
+
+#[derive(Insertable)]
+#[table_name = "audit_checkpoints"]
+pub struct NewAuditCheckpoint {
+    pub created_at: NaiveDateTime,
+    pub entry_id: i64,
+    pub entry_hash: String,
+    pub public_key: String,
+    pub signature: String
+}
+
+impl NewAuditCheckpoint {
+    // What the signature is made over
+    pub fn message(&self) -> String {
+        checkpoint_message(&self.created_at, self.entry_id, &self.entry_hash)
+    }
+}
+
+impl AuditCheckpoint {
+    pub fn message(&self) -> String {
+        checkpoint_message(&self.created_at, self.entry_id, &self.entry_hash)
+    }
+}
+
+fn checkpoint_message(created_at: &NaiveDateTime, entry_id: i64, entry_hash: &str) -> String {
+    format!("rublic audit checkpoint {} {} {}", created_at.format("%Y-%m-%dT%H:%M:%S"), entry_id, entry_hash)
+}
+
+pub struct ChainBreak {
+    pub id: i64,
+    pub reason: String
+}
+
+pub struct ChainVerification {
+    pub entries: i64,
+    // Entries recorded before the log was chained
+    pub unchained: i64,
+    pub checkpoints: i64,
+    pub head: Option<String>,
+    pub first_break: Option<ChainBreak>
+}
+
+// One page of a list ordered by name, and by id among equal names. A page picks up
+// after the name and id of the last item of the previous one, rather than at an
+// offset, so items added or removed in the meantime don't shift it
+#[derive(Clone)]
+pub struct ListQuery {
+    pub q: Option<String>,
+    pub descending: bool,
+    pub after: Option<(String, String)>,
+    pub limit: i64
+}
+
+// Every field which is set has to match, the rest are ignored
+#[derive(Default)]
+pub struct AuditFilter {
+    pub subject: Option<String>,
+    pub fqdn: Option<String>,
+    pub action: Option<String>,
+    pub result: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>
 }
 
 #[derive(Queryable)]
@@ -0,0 +1,36 @@
+// A throwaway in-memory database for tests, migrated up to the current schema
+
+use actix::{Addr, Handler, Message, SyncArbiter, SystemRunner, System};
+use futures::Future;
+use diesel::r2d2::{ConnectionManager, Pool};
+use super::{DbExecutor, DbConnection, SqliteCustomizer, migrations};
+use super::errors::Error;
+
+// Every connection to :memory: is a database of its own, so the pool is kept to
+// the single connection the migrations were applied to
+pub fn pool() -> Pool<ConnectionManager<DbConnection>> {
+    let pool = Pool::builder()
+        .max_size(1)
+        .connection_customizer(Box::new(SqliteCustomizer))
+        .build(ConnectionManager::<DbConnection>::new(":memory:"))
+        .expect("Failed to create pool.");
+
+    migrations::prepare(&pool.get().unwrap(), true).expect("Failed to migrate database.");
+    pool
+}
+
+// A system to run futures on, along with a database executor started within it
+pub fn executor() -> (SystemRunner, Addr<DbExecutor>) {
+    let sys = System::new("test");
+    let pool = pool();
+    let db = SyncArbiter::start(1, move || DbExecutor(pool.clone()));
+    (sys, db)
+}
+
+// Sends a message to the executor and waits for its reply
+pub fn send<M, T>(sys: &mut SystemRunner, db: &Addr<DbExecutor>, msg: M) -> Result<T, Error>
+    where M: Message<Result = Result<T, Error>> + Send + 'static,
+          DbExecutor: Handler<M>,
+          T: Send + 'static {
+    sys.block_on(db.send(msg).flatten())
+}
@@ -17,6 +17,9 @@ pub enum ServiceError {
     #[fail(display = "Conflict: {}", _0)]
     Conflict(String),
 
+    #[fail(display = "Unknown {}: {:?}", _0, _1)]
+    UnknownReferences(String, Vec<String>),
+
     #[fail(display = "Unauthorized")]
     Unauthorized
 }
@@ -53,10 +56,19 @@ impl From<crate::certificates::errors::Error> for ServiceError {
     }
 }
 
+// Missing data is a 404, and clashing data such as a duplicate name, or a member added
+// by another request in the meantime, a 409. Anything else is an internal error
 impl From<crate::database::errors::Error> for ServiceError {
     fn from(e: crate::database::errors::Error) -> Self {
-        error!("uncaught error: {:?}", e);
-        ServiceError::InternalServerError
+        match e {
+            crate::database::errors::Error::DataNotFound(message) => ServiceError::NotFound(message),
+            crate::database::errors::Error::DataConflict(message) => ServiceError::Conflict(message),
+            crate::database::errors::Error::UnknownReferences(kind, ids) => ServiceError::UnknownReferences(kind, ids),
+            e => {
+                error!("uncaught error: {:?}", e);
+                ServiceError::InternalServerError
+            }
+        }
     }
 }
 
@@ -67,6 +79,32 @@ impl From<crate::authorization::errors::Error> for ServiceError {
     }
 }
 
+impl From<crate::watcher::errors::Error> for ServiceError {
+    fn from(e: crate::watcher::errors::Error) -> Self {
+        error!("uncaught error: {:?}", e);
+        ServiceError::InternalServerError
+    }
+}
+
+impl From<crate::reconciler::errors::Error> for ServiceError {
+    fn from(e: crate::reconciler::errors::Error) -> Self {
+        error!("uncaught error: {:?}", e);
+        ServiceError::InternalServerError
+    }
+}
+
+impl From<crate::auditor::errors::Error> for ServiceError {
+    fn from(e: crate::auditor::errors::Error) -> Self {
+        match e {
+            crate::auditor::errors::Error::NoSigningKey => ServiceError::Conflict("checkpoints take RUBLIC_AUDIT_SIGNING_KEY to be set".into()),
+            e => {
+                error!("uncaught error: {:?}", e);
+                ServiceError::InternalServerError
+            }
+        }
+    }
+}
+
 impl From<std::io::Error> for ServiceError {
     fn from(e: std::io::Error) -> Self {
         error!("uncaught error: {:?}", e);
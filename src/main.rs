@@ -18,6 +18,7 @@ extern crate openssl;
 extern crate jsonwebtoken as jwt;
 #[macro_use] extern crate log;
 #[macro_use] extern crate diesel;
+#[macro_use] extern crate diesel_migrations;
 #[macro_use] extern crate failure;
 #[macro_use] extern crate lazy_static;
 
@@ -30,34 +31,55 @@ mod errors;
 mod cryptoutil;
 mod database;
 mod watcher;
+mod reconciler;
+mod auditor;
 mod certificates;
 mod api;
 
 use actix::prelude::*;
 use actix_web::server;
-use diesel::{r2d2::ConnectionManager, MysqlConnection};
 use dotenv::dotenv;
 use crate::authorization::AuthorizationManager;
 use crate::certificates::CertificateManager;
 use crate::database::DbExecutor;
-use crate::watcher::ArchiveWatcher;
-use crate::config::{DATABASE_URL, LETSENCRYPT_ARCHIVE};
+use crate::watcher::{ArchiveWatcher, LiveWatcher};
+use crate::watcher::models::Backend;
+use crate::reconciler::Reconciler;
+use crate::auditor::Auditor;
+use crate::config::{DATABASE_URL, AUTO_MIGRATE, AUDIT_EXPORT_DIR, AUDIT_SIGNING_KEY, LETSENCRYPT_ARCHIVE, LETSENCRYPT_LIVE};
 
 
 fn main() {
     dotenv().ok();
     env_logger::init();
 
+    // create db connection pool
+    let pool = crate::database::create_pool(&DATABASE_URL);
+
+    // Deploy pipelines can migrate ahead of time, without the rest of the configuration
+    let migrate_only = std::env::args().skip(1).any(|arg| arg == "--migrate-only");
+
+    let prepared = pool.get()
+        .map_err(|e| crate::database::errors::Error::MigrationFailed(e.to_string()))
+        .and_then(|conn| crate::database::migrations::prepare(&conn, *AUTO_MIGRATE || migrate_only));
+
+    if let Err(e) = prepared {
+        error!("unable to prepare the database: {}", e);
+        std::process::exit(1);
+    }
+
+    if migrate_only {
+        return;
+    }
+
+    if std::env::args().skip(1).any(|arg| arg == "--verify-audit") {
+        std::process::exit(verify_audit(&pool.get().expect("Failed to connect to the database.")));
+    }
+
     crate::config::initialize();
 
     let sys = actix::System::new("Rublic");
 
-    // create db connection pool
-    let manager = ConnectionManager::<MysqlConnection>::new(DATABASE_URL.to_string());
-    let pool = r2d2::Pool::builder()
-        .build(manager)
-        .expect("Failed to create pool.");
-
     let database = SyncArbiter::start(4, move || DbExecutor(pool.clone()));
 
     let dbref = database.clone();
@@ -70,16 +92,64 @@ fn main() {
         AuthorizationManager { db: dbref.clone() }
     });
 
+    let certmanref = certman.clone();
+    let live = Arbiter::start(move |_| {
+        LiveWatcher::new(certmanref.clone(), LETSENCRYPT_LIVE.to_path_buf(), Backend::for_root(&LETSENCRYPT_LIVE))
+    });
+
+    // A single arbiter serves the archive and all of its domains
+    let certmanref = certman.clone();
+    let dbref = database.clone();
+    let watcher = Arbiter::start(move |_| {
+        ArchiveWatcher::new(dbref.clone(), certmanref.clone(), LETSENCRYPT_ARCHIVE.to_path_buf(),
+            Backend::for_root(&LETSENCRYPT_ARCHIVE), Some(live.recipient()))
+    });
+
+    // Reconciliation blocks while it walks the archive, so it gets an arbiter of its own
     let certmanref = certman.clone();
     let dbref = database.clone();
-    Arbiter::start(move |_| {
-        ArchiveWatcher::new(dbref.clone(), certmanref.clone(), LETSENCRYPT_ARCHIVE.to_path_buf())
+    let reconciler = Arbiter::start(move |_| {
+        Reconciler::new(dbref.clone(), certmanref.clone(), LETSENCRYPT_ARCHIVE.to_path_buf(), LETSENCRYPT_LIVE.to_path_buf())
     });
 
-    server::new(move || app::create_app(database.clone(), certman.clone(), authman.clone()))
+    let dbref = database.clone();
+    let auditor = Arbiter::start(move |_| {
+        Auditor::new(dbref.clone(), AUDIT_EXPORT_DIR.clone(), AUDIT_SIGNING_KEY.clone())
+    });
+
+    server::new(move || app::create_app(database.clone(), certman.clone(), authman.clone(), watcher.clone(), reconciler.clone(), auditor.clone()))
         .bind("127.0.0.1:3000")
         .expect("Can not bind to '127.0.0.1:3000'")
         .start();
 
     sys.run();
+}
+
+// Walks the audit chain and reports the first break, if any, as the exit code
+fn verify_audit(conn: &crate::database::DbConnection) -> i32 {
+    match crate::database::audit::verify(conn, &crate::config::audit_trusted_keys()) {
+        Ok(verification) => {
+            println!("{} chained entries, {} unchained entries, {} checkpoints",
+                verification.entries, verification.unchained, verification.checkpoints);
+
+            if let Some(head) = verification.head {
+                println!("head: {}", head);
+            }
+
+            match verification.first_break {
+                Some(broken) => {
+                    println!("the chain is broken at entry {}: {}", broken.id, broken.reason);
+                    1
+                },
+                None => {
+                    println!("the chain is intact");
+                    0
+                }
+            }
+        },
+        Err(e) => {
+            error!("unable to verify the audit log: {}", e);
+            2
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,20 @@
+#[derive(Fail, Debug)]
+pub enum Error {
+    #[fail(display = "Database Error: {}", _0)]
+    DatabaseError(crate::database::errors::Error),
+
+    #[fail(display = "Unknown Error")]
+    Unknown
+}
+
+impl From<actix::MailboxError> for Error {
+    fn from(_: actix::MailboxError) -> Self {
+        Error::Unknown
+    }
+}
+
+impl From<crate::database::errors::Error> for Error {
+    fn from(e: crate::database::errors::Error) -> Self {
+        Error::DatabaseError(e)
+    }
+}
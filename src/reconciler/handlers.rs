@@ -0,0 +1,21 @@
+use actix::Handler;
+use super::Reconciler;
+use super::messages::*;
+use super::models::*;
+use super::errors::Error;
+
+impl Handler<Reconcile> for Reconciler {
+    type Result = Result<ReconcileReport, Error>;
+
+    fn handle(&mut self, _: Reconcile, _: &mut Self::Context) -> Self::Result {
+        self.reconcile()
+    }
+}
+
+impl Handler<GetReconcileReport> for Reconciler {
+    type Result = Result<Option<ReconcileReport>, Error>;
+
+    fn handle(&mut self, _: GetReconcileReport, _: &mut Self::Context) -> Self::Result {
+        Ok(self.last_report.clone())
+    }
+}
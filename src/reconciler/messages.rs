@@ -0,0 +1,5 @@
+use super::errors::Error;
+use super::models::*;
+
+actor_command_new! (Reconcile() -> Result<ReconcileReport, Error>);
+actor_command_new! (GetReconcileReport() -> Result<Option<ReconcileReport>, Error>);
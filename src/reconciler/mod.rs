@@ -0,0 +1,280 @@
+pub mod errors;
+pub mod models;
+pub mod messages;
+mod handlers;
+
+use std::io::ErrorKind;
+use std::fs::read_dir;
+use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use chrono::Utc;
+use futures::Future;
+use actix::{Actor, Context, Addr, AsyncContext};
+use crate::database::DbExecutor;
+use crate::database::models::{Certificate, Domain};
+use crate::database::messages::{CreateDomain, GetDomains, GetCertificates, GetQuarantine, DeleteCertificateByPath};
+use crate::certificates::{CertificateManager, parse_filename, fingerprint, version_paths};
+use crate::certificates::messages::{VersionDiscovered, VersionIncomplete, LiveLinkChanged};
+use crate::watcher::models::admit_directory;
+use crate::config::{RECONCILE_INTERVAL, MANUAL_WATCH_MODE};
+use self::errors::Error;
+use self::models::ReconcileReport;
+
+// Periodically compares the archive with the certificates table, to catch
+// anything the watchers missed, including changes made while rublic was down
+pub struct Reconciler {
+    pub db: Addr<DbExecutor>,
+    pub certman: Addr<CertificateManager>,
+    pub dir: PathBuf,
+    pub live: PathBuf,
+    pub last_report: Option<ReconcileReport>,
+}
+
+impl Reconciler {
+    pub fn new(db: Addr<DbExecutor>, certman: Addr<CertificateManager>, dir: PathBuf, live: PathBuf) -> Self {
+        Reconciler {
+            db,
+            certman,
+            dir,
+            live,
+            last_report: None
+        }
+    }
+
+    pub fn reconcile(&mut self) -> Result<ReconcileReport, Error> {
+        info!("reconciling archive: {}", self.dir.to_string_lossy());
+
+        let mut report = ReconcileReport {
+            started_at: Utc::now().naive_utc(),
+            finished_at: Utc::now().naive_utc(),
+            inserted: 0,
+            reparsed: 0,
+            removed: 0,
+            failed: 0,
+            errors: Vec::new()
+        };
+
+        let mut domains: HashMap<String, Domain> = self.db.send(GetDomains {}).flatten().wait()?
+            .into_iter()
+            .map(|domain| (domain.fqdn.clone(), domain))
+            .collect();
+
+        let known: HashMap<String, Certificate> = self.db.send(GetCertificates {}).flatten().wait()?
+            .into_iter()
+            .map(|cert| (cert.path.clone(), cert))
+            .collect();
+
+        // Files which failed to be ingested are only retried once they've changed
+        let quarantined: HashMap<String, Option<String>> = self.db.send(GetQuarantine { fqdn: None }).flatten().wait()?
+            .into_iter()
+            .map(|file| (file.path, file.hash))
+            .collect();
+
+        let mut seen = HashSet::new();
+
+        // Certificates of domains which couldn't be read are left alone, rather than
+        // assuming their files are all gone, and so are those of archived domains,
+        // which are kept as history
+        let mut untouchable: HashSet<String> = domains.values()
+            .filter(|domain| domain.archived)
+            .map(|domain| domain.id.clone())
+            .collect();
+
+        // The archive itself is walked rather than the domains already known, so directories
+        // the archive watcher never got to see are picked up too. If it can't be read at all,
+        // it's more likely to be unmounted than empty, and nothing is removed
+        let dirs: Vec<PathBuf> = match read_dir(&self.dir) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect(),
+            Err(e) => {
+                report.errors.push(format!("unable to read {}: {}", self.dir.to_string_lossy(), e));
+                untouchable.extend(domains.values().map(|domain| domain.id.clone()));
+                Vec::new()
+            }
+        };
+
+        for dir in dirs {
+            let fqdn: String = dir.file_name().unwrap().to_string_lossy().into();
+
+            let domain = match domains.remove(&fqdn) {
+                Some(ref domain) if domain.archived => continue,
+                Some(domain) => domain,
+
+                // Registering domains is up to an administrator in manual mode
+                None if *MANUAL_WATCH_MODE || admit_directory(&fqdn).is_err() => continue,
+                None => match self.db.send(CreateDomain { fqdn: fqdn.clone() }).flatten().wait() {
+                    Ok(domain) => domain,
+                    Err(e) => {
+                        report.errors.push(format!("unable to create domain {}: {}", fqdn, e));
+                        continue;
+                    }
+                }
+            };
+
+            let entries = match read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    report.errors.push(format!("unable to read {}: {}", dir.to_string_lossy(), e));
+                    untouchable.insert(domain.id);
+                    continue;
+                }
+            };
+
+            // Files which are new and files which have changed, by version
+            let mut stale: BTreeMap<i32, (usize, usize)> = BTreeMap::new();
+
+            for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+                let version = match parse_filename(&path.file_name().unwrap().to_string_lossy()) {
+                    Ok((_, version)) if path.is_file() => version,
+                    _ => continue
+                };
+
+                let path_str: String = path.to_string_lossy().into();
+
+                if let Some(Some(failed)) = quarantined.get(&path_str) {
+                    if fingerprint(&path).ok().map_or(false, |(_, hash)| &hash == failed) {
+                        seen.insert(path_str);
+                        continue;
+                    }
+                }
+
+                match known.get(&path_str) {
+                    None => stale.entry(version).or_insert((0, 0)).0 += 1,
+                    Some(cert) => match fingerprint(&path) {
+                        Ok((modified_at, hash)) => {
+                            if cert.modified_at != Some(modified_at) || cert.hash.as_ref() != Some(&hash) {
+                                stale.entry(version).or_insert((0, 0)).1 += 1;
+                            }
+                        },
+                        Err(e) => report.errors.push(format!("unable to read {}: {}", path_str, e))
+                    }
+                }
+
+                seen.insert(path_str);
+            }
+
+            // Versions are always ingested as a whole, so one changed file means all of them are.
+            // Files only count as inserted or reparsed once their version made it in
+            for (version, (new, changed)) in stale {
+                let (paths, missing) = match version_paths(&dir, version) {
+                    Ok(files) => files,
+                    Err(e) => {
+                        report.failed += new + changed;
+                        report.errors.push(format!("unable to read version {} of {}: {}", version, domain.fqdn, e));
+                        continue;
+                    }
+                };
+
+                if !missing.is_empty() {
+                    report.failed += new + changed;
+                    report.errors.push(format!("version {} of {} is missing {}", version, domain.fqdn, missing.join(", ")));
+
+                    self.certman.send(VersionIncomplete {
+                        fqdn: domain.fqdn.clone(),
+                        version,
+                        paths,
+                        missing
+                    }).flatten().wait().ok();
+                    continue;
+                }
+
+                let ingested = self.certman.send(VersionDiscovered {
+                    fqdn: domain.fqdn.clone(),
+                    version,
+                    paths
+                }).flatten().wait();
+
+                match ingested {
+                    Ok(_) => {
+                        report.inserted += new;
+                        report.reparsed += changed;
+                    },
+                    Err(e) => {
+                        report.failed += new + changed;
+                        report.errors.push(format!("unable to ingest version {} of {}: {}", version, domain.fqdn, e));
+                    }
+                }
+            }
+        }
+
+        self.reconcile_links(&mut report);
+
+        for cert in known.values() {
+            if seen.contains(&cert.path) || untouchable.contains(&cert.domain_id) {
+                continue;
+            }
+
+            match self.db.send(DeleteCertificateByPath { path: cert.path.clone() }).flatten().wait() {
+                Ok(()) => report.removed += 1,
+                Err(e) => report.errors.push(format!("unable to remove {}: {}", cert.path, e))
+            }
+        }
+
+        report.finished_at = Utc::now().naive_utc();
+
+        info!("reconciled archive: {} inserted, {} reparsed, {} removed, {} failed, {} errors",
+            report.inserted, report.reparsed, report.removed, report.failed, report.errors.len());
+
+        for e in &report.errors {
+            error!("reconciliation error: {}", e);
+        }
+
+        self.last_report = Some(report.clone());
+        Ok(report)
+    }
+
+    // Re-reads every link in the live tree, in case the live watcher missed a change
+    fn reconcile_links(&mut self, report: &mut ReconcileReport) {
+        let dirs = match read_dir(&self.live) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return,
+            Err(e) => {
+                report.errors.push(format!("unable to read {}: {}", self.live.to_string_lossy(), e));
+                return;
+            }
+        };
+
+        for dir in dirs.filter(|dir| dir.is_dir()) {
+            let fqdn: String = dir.file_name().unwrap().to_string_lossy().into();
+
+            let links = match read_dir(&dir) {
+                Ok(entries) => entries.filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().map(|file_type| file_type.is_symlink()).unwrap_or(false))
+                    .map(|entry| entry.path()),
+                Err(e) => {
+                    report.errors.push(format!("unable to read {}: {}", dir.to_string_lossy(), e));
+                    continue;
+                }
+            };
+
+            for link in links {
+                let changed = self.certman.send(LiveLinkChanged {
+                    fqdn: fqdn.clone(),
+                    link: link.clone()
+                }).flatten().wait();
+
+                if let Err(e) = changed {
+                    report.errors.push(format!("unable to read live link {}: {}", link.to_string_lossy(), e));
+                }
+            }
+        }
+    }
+}
+
+impl Actor for Reconciler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // Files may have been added or removed while rublic wasn't running,
+        // so catch up straight away rather than waiting for the first interval
+        ctx.run_later(std::time::Duration::from_secs(0), |act, _| {
+            act.reconcile().map_err(|e| error!("unable to reconcile archive: {}", e)).ok();
+        });
+
+        ctx.run_interval(*RECONCILE_INTERVAL, |act, _| {
+            act.reconcile().map_err(|e| error!("unable to reconcile archive: {}", e)).ok();
+        });
+    }
+}
@@ -0,0 +1,21 @@
+use chrono::NaiveDateTime;
+
+#[derive(Clone)]
+pub struct ReconcileReport {
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+
+    // Files which were on disk, but not in the database
+    pub inserted: usize,
+
+    // Files whose contents changed after they were ingested
+    pub reparsed: usize,
+
+    // Certificates in the database whose files no longer exist
+    pub removed: usize,
+
+    // Files which were new or had changed, but whose version couldn't be ingested
+    pub failed: usize,
+
+    pub errors: Vec<String>
+}
@@ -1,3 +1,49 @@
+table! {
+    api_keys (id) {
+        id -> Char,
+        user_id -> Char,
+        label -> Varchar,
+        hashed_key -> Varchar,
+        previous_hashed_key -> Nullable<Varchar>,
+        previous_key_expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        scope_domains -> Nullable<Text>,
+        scope_permissions -> Nullable<Text>,
+    }
+}
+
+table! {
+    audit_log (id) {
+        id -> Bigint,
+        occurred_at -> Timestamp,
+        subject -> Nullable<Varchar>,
+        client_ip -> Nullable<Varchar>,
+        action -> Varchar,
+        fqdn -> Nullable<Varchar>,
+        version -> Nullable<Integer>,
+        friendly_name -> Nullable<Varchar>,
+        is_private -> Nullable<Bool>,
+        target -> Nullable<Varchar>,
+        details -> Nullable<Text>,
+        result -> Varchar,
+        previous_hash -> Nullable<Char>,
+        hash -> Nullable<Char>,
+    }
+}
+
+table! {
+    audit_checkpoints (id) {
+        id -> Bigint,
+        created_at -> Timestamp,
+        entry_id -> Bigint,
+        entry_hash -> Char,
+        public_key -> Char,
+        signature -> Char,
+    }
+}
+
 table! {
     certificates (domain_id, id, friendly_name) {
         id -> Integer,
@@ -5,8 +51,10 @@ table! {
         friendly_name -> Varchar,
         path -> Mediumtext,
         is_private -> Bool,
-        not_before -> Nullable<Datetime>,
-        not_after -> Nullable<Datetime>,
+        not_before -> Nullable<Timestamp>,
+        not_after -> Nullable<Timestamp>,
+        modified_at -> Nullable<Timestamp>,
+        hash -> Nullable<Char>,
     }
 }
 
@@ -15,6 +63,15 @@ table! {
         id -> Char,
         fqdn -> Varchar,
         hashed_fqdn -> Char,
+        archived -> Bool,
+    }
+}
+
+table! {
+    domain_aliases (hashed_fqdn) {
+        fqdn -> Varchar,
+        hashed_fqdn -> Char,
+        domain_id -> Char,
     }
 }
 
@@ -33,11 +90,57 @@ table! {
     }
 }
 
+table! {
+    group_roles (group_id, role) {
+        group_id -> Char,
+        role -> Varchar,
+    }
+}
+
 table! {
     users (id) {
         id -> Char,
         friendly_name -> Varchar,
-        hashed_key -> Varchar,
+        disabled -> Bool,
+    }
+}
+
+table! {
+    live_versions (hashed_fqdn, friendly_name) {
+        fqdn -> Varchar,
+        hashed_fqdn -> Char,
+        friendly_name -> Varchar,
+        version -> Integer,
+    }
+}
+
+table! {
+    quarantine (hashed_path) {
+        path -> Mediumtext,
+        hashed_path -> Char,
+        fqdn -> Varchar,
+        kind -> Varchar,
+        message -> Text,
+        hash -> Nullable<Char>,
+        occurred_at -> Timestamp,
+    }
+}
+
+table! {
+    refresh_tokens (jti) {
+        jti -> Char,
+        subject -> Varchar,
+        issued_at -> Timestamp,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        key_id -> Nullable<Char>,
+    }
+}
+
+table! {
+    user_roles (user_id, role) {
+        user_id -> Char,
+        role -> Varchar,
     }
 }
 
@@ -48,17 +151,30 @@ table! {
     }
 }
 
+joinable!(api_keys -> users (user_id));
 joinable!(certificates -> domains (domain_id));
+joinable!(domain_aliases -> domains (domain_id));
 joinable!(domain_group_mappings -> domains (domain_id));
 joinable!(domain_group_mappings -> groups (group_id));
 joinable!(user_group_mappings -> groups (group_id));
 joinable!(user_group_mappings -> users (user_id));
+joinable!(group_roles -> groups (group_id));
+joinable!(user_roles -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    api_keys,
+    audit_checkpoints,
+    audit_log,
     certificates,
     domains,
+    domain_aliases,
     domain_group_mappings,
     groups,
+    group_roles,
+    live_versions,
+    quarantine,
+    refresh_tokens,
     users,
     user_group_mappings,
+    user_roles,
 );
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use actix::{Handler, ActorContext, ActorFuture, WrapFuture, ResponseActFuture, ResponseFuture, fut};
+use chrono::Utc;
+use futures::Future;
+use futures::future::{self, join_all};
+use inotify::WatchMask;
+use crate::certificates::parse_filename;
+use crate::certificates::messages::{CertificateDisappeared, FileUnrecognized};
+use super::{InotifyWatcher, PollingWatcher, PolledDirectory, ArchiveWatcher, DomainWatcher, LiveWatcher, Subscription};
+use super::messages::*;
+use super::models::*;
+use super::errors::Error;
+
+impl Handler<Watch> for InotifyWatcher {
+    type Result = Result<WatchId, Error>;
+
+    fn handle(&mut self, msg: Watch, _: &mut Self::Context) -> Self::Result {
+        let wd = self.notifier.add_watch(&msg.path, WatchMask::ALL_EVENTS)?;
+
+        for event in scan_directory(&msg.path)? {
+            msg.recipient.do_send(event).ok();
+        }
+
+        // Watching a directory which already is, such as one that was renamed, gives back the
+        // descriptor it already had, so the earlier subscription is taken over
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+
+        self.watches.insert(wd, Subscription {
+            id,
+            path: msg.path,
+            recipient: msg.recipient
+        });
+
+        Ok(id)
+    }
+}
+
+impl Handler<Unwatch> for InotifyWatcher {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: Unwatch, _: &mut Self::Context) -> Self::Result {
+        let wd = self.watches.iter()
+            .find(|(_, subscription)| subscription.id == msg.id)
+            .map(|(wd, _)| wd.clone())
+            .ok_or_else(|| Error::NotWatched(format!("watch {}", msg.id.0)))?;
+
+        self.watches.remove(&wd);
+
+        // The watch is already gone if the directory itself was deleted
+        self.notifier.rm_watch(wd).ok();
+        Ok(())
+    }
+}
+
+impl Handler<GetDirectoryStatus> for InotifyWatcher {
+    type Result = Result<DirectoryStatus, Error>;
+
+    fn handle(&mut self, _: GetDirectoryStatus, _: &mut Self::Context) -> Self::Result {
+        Ok(DirectoryStatus {
+            watches: self.watches.len(),
+            recoveries: self.recoveries
+        })
+    }
+}
+
+impl Handler<Watch> for PollingWatcher {
+    type Result = Result<WatchId, Error>;
+
+    fn handle(&mut self, msg: Watch, _: &mut Self::Context) -> Self::Result {
+        let entries = snapshot_directory(&msg.path)?;
+
+        // Everything already present is reported straight away, like the inotify backend does
+        for event in diff_snapshots(&HashMap::new(), &entries) {
+            msg.recipient.do_send(event).ok();
+        }
+
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+
+        self.watches.insert(msg.path, PolledDirectory {
+            id,
+            recipient: msg.recipient,
+            entries
+        });
+
+        Ok(id)
+    }
+}
+
+impl Handler<Unwatch> for PollingWatcher {
+    type Result = Result<(), Error>;
+
+    fn handle(&mut self, msg: Unwatch, _: &mut Self::Context) -> Self::Result {
+        let path = self.watches.iter()
+            .find(|(_, directory)| directory.id == msg.id)
+            .map(|(path, _)| path.clone())
+            .ok_or_else(|| Error::NotWatched(format!("watch {}", msg.id.0)))?;
+
+        self.watches.remove(&path);
+        Ok(())
+    }
+}
+
+impl Handler<GetDirectoryStatus> for PollingWatcher {
+    type Result = Result<DirectoryStatus, Error>;
+
+    fn handle(&mut self, _: GetDirectoryStatus, _: &mut Self::Context) -> Self::Result {
+        // Polling never loses events, so there is nothing to recover from
+        Ok(DirectoryStatus {
+            watches: self.watches.len(),
+            recoveries: 0
+        })
+    }
+}
+
+impl Handler<Event> for ArchiveWatcher {
+    type Result = ();
+
+    fn handle(&mut self, event: Event, ctx: &mut Self::Context) -> Self::Result {
+        if event.event_type == EventType::Rescan {
+            self.rescan(ctx);
+            return;
+        } else if event.event_type == EventType::SelfDeleted {
+            self.lose_archive(ctx);
+            return;
+        }
+
+        if event.file_type != FileType::Directory {
+            return;
+        }
+
+        if event.event_type == EventType::Updated {
+            info!("discovered domain: {}", event.path.to_string_lossy());
+            self.watch(event.path, ctx);
+        } else if event.event_type == EventType::Deleted
+               && self.rejected.remove(&event.path).is_some() {
+            return;
+        } else if event.event_type == EventType::Deleted
+               && self.children.contains_key(&event.path) {
+            info!("domain removed: {}", event.path.to_string_lossy());
+            self.unwatch(event.path);
+        } else if event.event_type == EventType::Renamed {
+            if let Some(from) = event.from {
+                self.rename(from, event.path, ctx);
+            }
+        }
+    }
+}
+
+impl Handler<DomainRegistered> for ArchiveWatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: DomainRegistered, ctx: &mut Self::Context) -> Self::Result {
+        // Give a directory which was turned down for not being registered another chance
+        let path = self.dir.join(&msg.fqdn);
+
+        if self.rejected.remove(&path).is_some() {
+            self.watch(path, ctx);
+        }
+    }
+}
+
+impl Handler<DomainWatcherStopped> for ArchiveWatcher {
+    type Result = ();
+
+    fn handle(&mut self, msg: DomainWatcherStopped, _: &mut Self::Context) -> Self::Result {
+        // Watchers stopped by the archive itself have already been removed, so this is one
+        // which stopped on its own, because its directory went away or couldn't be watched
+        let stopped = match self.children.get(&msg.path) {
+            Some(Some(child)) => !child.connected() || !msg.path.is_dir(),
+            _ => false
+        };
+
+        if !stopped {
+            return;
+        }
+
+        if msg.path.is_dir() {
+            self.children.remove(&msg.path);
+        } else {
+            info!("domain removed: {}", msg.path.to_string_lossy());
+            self.unwatch(msg.path);
+        }
+    }
+}
+
+impl Handler<GetArchiveStatus> for ArchiveWatcher {
+    type Result = ResponseActFuture<Self, ArchiveStatus, Error>;
+
+    fn handle(&mut self, _: GetArchiveStatus, _: &mut Self::Context) -> Self::Result {
+        // A domain watcher which stops while being asked is simply left out
+        let domains = join_all(self.children.values()
+            .filter_map(|child| child.as_ref())
+            .map(|child| child.send(GetDomainStatus {}).flatten().then(|status| Ok(status.ok())))
+            .collect::<Vec<_>>());
+
+        // The live tree has an inotify instance of its own, whose watches count against the same limit
+        let live: Box<dyn Future<Item = Option<DirectoryStatus>, Error = Error>> = match &self.live {
+            Some(live) => Box::new(live.send(GetDirectoryStatus {}).flatten().then(|status| Ok(status.ok()))),
+            None => Box::new(future::ok(None))
+        };
+
+        Box::new(self.watcher.status.send(GetDirectoryStatus {}).flatten()
+            .join3(live, domains)
+            .into_actor(self)
+            .and_then(|(status, live, domains), act, _| {
+                let mut domains: Vec<_> = domains.into_iter().filter_map(|domain| domain).collect();
+                domains.sort_by(|a, b| a.dir.cmp(&b.dir));
+
+                let mut rejected: Vec<_> = act.rejected.iter()
+                    .map(|(path, reason)| (path.clone(), reason.clone()))
+                    .collect();
+                rejected.sort();
+
+                fut::ok(ArchiveStatus {
+                    dir: act.dir.clone(),
+                    backend: act.backend,
+                    watches: status.watches + live.as_ref().map_or(0, |live| live.watches),
+                    max_watches: match act.backend {
+                        Backend::Inotify => max_user_watches(),
+                        Backend::Polling(_) => None
+                    },
+                    recoveries: status.recoveries + live.as_ref().map_or(0, |live| live.recoveries),
+                    domains,
+                    rejected
+                })
+            })
+        )
+    }
+}
+
+impl Handler<GetDirectoryStatus> for LiveWatcher {
+    type Result = ResponseFuture<DirectoryStatus, Error>;
+
+    fn handle(&mut self, msg: GetDirectoryStatus, _: &mut Self::Context) -> Self::Result {
+        Box::new(self.watcher.status.send(msg).flatten())
+    }
+}
+
+impl Handler<Event> for LiveWatcher {
+    type Result = ();
+
+    fn handle(&mut self, event: Event, ctx: &mut Self::Context) -> Self::Result {
+        if event.event_type == EventType::Rescan {
+            self.rescan(event.path, ctx);
+            return;
+        } else if event.event_type == EventType::SelfDeleted {
+            if event.path == self.dir {
+                error!("live directory disappeared: {}", self.dir.to_string_lossy());
+            }
+            self.unwatch(event.path);
+            return;
+        }
+
+        // Directories in the root of the tree are domains, anything below them is a link
+        if event.path.parent() == Some(&self.dir) {
+            if event.file_type != FileType::Directory {
+                return;
+            }
+
+            match event.event_type {
+                EventType::Updated => self.watch(event.path, ctx),
+                EventType::Deleted => self.unwatch(event.path),
+                EventType::Renamed => {
+                    if let Some(from) = event.from {
+                        self.unwatch(from);
+                    }
+                    self.watch(event.path, ctx);
+                },
+                _ => {}
+            }
+        } else if event.file_type == FileType::File {
+            if let Some(from) = event.from {
+                self.update_link(from);
+            }
+            self.update_link(event.path);
+        }
+    }
+}
+
+impl Handler<Event> for DomainWatcher {
+    type Result = ();
+
+    fn handle(&mut self, event: Event, ctx: &mut Self::Context) -> Self::Result {
+        self.last_event = Some(Utc::now().naive_utc());
+
+        if event.event_type == EventType::Rescan {
+            self.rescan(ctx);
+            return;
+        } else if event.event_type == EventType::SelfDeleted {
+            // Whether it was deleted or renamed, the archive
+            // watcher takes care of the domain from here on
+            ctx.stop();
+            return;
+        }
+
+        if event.file_type != FileType::File {
+            return;
+        }
+
+        if event.event_type == EventType::Renamed {
+            if let Some(from) = event.from {
+                info!("lost certificate: {}", from.to_string_lossy());
+                self.certman.do_send(CertificateDisappeared { path: from });
+            }
+        }
+
+        if event.event_type == EventType::Updated || event.event_type == EventType::Renamed {
+            let filename = event.path.file_name().unwrap().to_string_lossy();
+
+            match parse_filename(&filename) {
+                Ok((_, version)) => self.touch_version(version, ctx),
+                Err(_) => self.certman.do_send(FileUnrecognized {
+                    fqdn: self.dir.file_name().unwrap().to_string_lossy().into(),
+                    path: event.path
+                })
+            }
+        } else if event.event_type == EventType::Deleted {
+            info!("lost certificate: {}", event.path.to_string_lossy());
+            self.certman.do_send(CertificateDisappeared { path: event.path });
+        }
+    }
+}
+
+impl Handler<GetDomainStatus> for DomainWatcher {
+    type Result = Result<DomainStatus, Error>;
+
+    fn handle(&mut self, _: GetDomainStatus, _: &mut Self::Context) -> Self::Result {
+        Ok(DomainStatus {
+            dir: self.dir.clone(),
+            state: self.state(),
+            last_event: self.last_event,
+            ingested: self.ingested,
+            errors: self.errors.clone()
+        })
+    }
+}
+
+impl Handler<StopWatching> for DomainWatcher {
+    type Result = ();
+
+    fn handle(&mut self, _: StopWatching, ctx: &mut Self::Context) -> Self::Result {
+        ctx.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use actix::{Actor, AsyncContext, Context, Handler, System};
+    use futures::Future;
+    use super::super::{InotifyWatcher, PollingWatcher};
+    use super::super::messages::*;
+    use super::super::models::*;
+
+    struct Sink;
+
+    impl Actor for Sink {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<Event> for Sink {
+        type Result = ();
+
+        fn handle(&mut self, _: Event, _: &mut Self::Context) {}
+    }
+
+    // Passes on the first update it hears of, and stops the system once it has
+    struct Listener(mpsc::Sender<Event>);
+
+    impl Actor for Listener {
+        type Context = Context<Self>;
+
+        fn started(&mut self, ctx: &mut Self::Context) {
+            ctx.run_later(Duration::from_secs(5), |_, _| System::current().stop());
+        }
+    }
+
+    impl Handler<Event> for Listener {
+        type Result = ();
+
+        fn handle(&mut self, event: Event, _: &mut Self::Context) {
+            if event.event_type == EventType::Updated {
+                self.0.send(event).ok();
+                System::current().stop();
+            }
+        }
+    }
+
+    #[test]
+    fn watches_are_removed_by_id() {
+        let mut sys = System::new("test");
+        let dir = std::env::temp_dir().join(format!("rublic-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+
+        let watcher = PollingWatcher::new(Duration::from_secs(60)).start();
+        let sink = Sink.start();
+
+        let first = sys.block_on(watcher.send(Watch { path: dir.join("a"), recipient: sink.clone().recipient() }).flatten()).unwrap();
+        let second = sys.block_on(watcher.send(Watch { path: dir.join("b"), recipient: sink.recipient() }).flatten()).unwrap();
+        assert_ne!(first, second);
+
+        sys.block_on(watcher.send(Unwatch { id: first }).flatten()).unwrap();
+        assert!(sys.block_on(watcher.send(Unwatch { id: first }).flatten()).is_err());
+
+        let status = sys.block_on(watcher.send(GetDirectoryStatus {}).flatten()).unwrap();
+        assert_eq!(status.watches, 1);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn inotify_events_are_delivered_once_they_arrive() {
+        let mut sys = System::new("test");
+        let dir = std::env::temp_dir().join(format!("rublic-inotify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let watcher = InotifyWatcher::new().unwrap().start();
+        let (tx, rx) = mpsc::channel();
+        let listener = Listener(tx).start();
+
+        sys.block_on(watcher.send(Watch { path: dir.clone(), recipient: listener.recipient() }).flatten()).unwrap();
+        std::fs::write(dir.join("cert.pem"), "").unwrap();
+        sys.run();
+
+        let event = rx.try_recv().expect("no event was delivered");
+        assert_eq!(event.path, dir.join("cert.pem"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
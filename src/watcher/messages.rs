@@ -0,0 +1,13 @@
+use std::path::PathBuf;
+use actix::Recipient;
+use super::errors::Error;
+use super::models::*;
+
+actor_command_new! (Watch(path: PathBuf, recipient: Recipient<Event>) -> Result<WatchId, Error>);
+actor_command_new! (Unwatch(id: WatchId) -> Result<(), Error>);
+actor_command_new! (StopWatching() -> ());
+actor_command_new! (DomainWatcherStopped(path: PathBuf) -> ());
+actor_command_new! (DomainRegistered(fqdn: String) -> ());
+actor_command_new! (GetArchiveStatus() -> Result<ArchiveStatus, Error>);
+actor_command_new! (GetDirectoryStatus() -> Result<DirectoryStatus, Error>);
+actor_command_new! (GetDomainStatus() -> Result<DomainStatus, Error>);
@@ -1,124 +1,946 @@
 pub mod errors;
 pub mod models;
+pub mod messages;
+mod handlers;
 
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::PathBuf;
-use std::collections::HashMap;
-use futures::Future;
-use actix::{Actor, Context, Addr, Arbiter};
+use std::time::Duration as StdDuration;
+use std::collections::{HashMap, HashSet};
+use chrono::{NaiveDateTime, Utc};
+use futures::{Future, Stream, Poll, Async, try_ready};
+use actix::prelude::*;
+use inotify::{Inotify, EventMask, EventOwned, WatchDescriptor};
+use mio::{Ready, PollOpt, Token, event::Evented, unix::EventedFd};
+use tokio_reactor::PollEvented;
 use crate::database::DbExecutor;
-use crate::database::messages::{CreateDomain};
-use crate::certificates::messages::{CertificateDiscovered, CertificateDisappeared};
-use crate::certificates::CertificateManager;
-use self::models::{FileType, EventType, DirectoryWatcher};
+use crate::database::messages::{CreateDomain, GetDomainByFqdn, RenameDomain, SetDomainArchived};
+use crate::certificates::{CertificateManager, parse_filename, version_paths};
+use crate::certificates::messages::{VersionDiscovered, VersionIncomplete, ReconcileDomain, LiveLinkChanged, LiveDomainRemoved};
+use crate::config::{SETTLE_TIMEOUT, MANUAL_WATCH_MODE};
+use self::errors::Error;
+use self::messages::*;
+use self::models::{Backend, Event, WatchId, EventType, EntryState, FileType, DomainState, IngestionError, map_event, map_file_type,
+    admit_directory, scan_directory, snapshot_directory, diff_snapshots};
+
+// A backend which watches directories and reports whatever changes in them as Events
+pub trait DirectoryWatcher: Actor<Context = Context<Self>>
+    + Handler<Watch>
+    + Handler<Unwatch>
+    + Handler<GetDirectoryStatus> {}
+
+impl DirectoryWatcher for InotifyWatcher {}
+impl DirectoryWatcher for PollingWatcher {}
+
+// Lets the archive and domain watchers talk to a backend without knowing which one it is
+#[derive(Clone)]
+pub struct WatcherHandle {
+    pub watch: Recipient<Watch>,
+    pub unwatch: Recipient<Unwatch>,
+    pub status: Recipient<GetDirectoryStatus>
+}
+
+impl WatcherHandle {
+    pub fn new<W: DirectoryWatcher>(watcher: Addr<W>) -> Self {
+        WatcherHandle {
+            watch: watcher.clone().recipient(),
+            unwatch: watcher.clone().recipient(),
+            status: watcher.recipient()
+        }
+    }
+
+    pub fn start(backend: Backend) -> Result<Self, Error> {
+        Ok(match backend {
+            Backend::Inotify => WatcherHandle::new(InotifyWatcher::new()?.start()),
+            Backend::Polling(interval) => WatcherHandle::new(PollingWatcher::new(interval).start())
+        })
+    }
+}
+
+// Owns the single inotify instance shared by every watcher, and
+// forwards events to whoever subscribed to the directory they came from
+pub struct InotifyWatcher {
+    notifier: Inotify,
+    watches: HashMap<WatchDescriptor, Subscription>,
+    next_id: usize,
+
+    // Number of times events were lost and directories had to be rescanned
+    recoveries: usize,
+
+    // Entries which were moved away, by cookie, until their MOVED_TO turns up
+    moves: HashMap<u32, PendingMove>,
+
+    // Where the kernel writes events to before they're copied out and handled
+    buffer: Vec<u8>
+}
+
+struct Subscription {
+    id: WatchId,
+    path: PathBuf,
+    recipient: Recipient<Event>
+}
+
+struct PendingMove {
+    wd: WatchDescriptor,
+    path: PathBuf,
+    file_type: FileType,
+    handle: SpawnHandle
+}
+
+// How long the first half of a rename waits for the second, before
+// the entry is taken to have been moved out of the watched directories
+const MOVE_TIMEOUT_MS: u64 = 100;
+
+// How long to wait before waiting on the inotify instance again after it failed
+const LISTEN_RETRY_SECS: u64 = 1;
+
+// How often to try watching the archive again after it disappeared
+const ARCHIVE_RETRY_SECS: u64 = 10;
 
 pub struct ArchiveWatcher {
     pub db: Addr<DbExecutor>,
     pub certman: Addr<CertificateManager>,
-    pub children: HashMap<PathBuf, Addr<DomainWatcher>>,
+    pub watcher: WatcherHandle,
+    pub backend: Backend,
+
+    // A directory is entered without a watcher while its domain is being registered,
+    // so events arriving in the meantime don't register it all over again
+    pub children: HashMap<PathBuf, Option<Addr<DomainWatcher>>>,
+    pub archive_watch: Option<WatchId>,
+
+    // The watcher of the live tree, whose watches are reported along with the archive's
+    pub live: Option<Recipient<GetDirectoryStatus>>,
+
+    // Directories which were turned down, and why, so they are only logged once
+    pub rejected: HashMap<PathBuf, String>,
     pub dir: PathBuf,
 }
 
 pub struct DomainWatcher {
-    pub db: Addr<DbExecutor>,
+    pub archive: Addr<ArchiveWatcher>,
     pub certman: Addr<CertificateManager>,
+    pub watcher: WatcherHandle,
     pub dir: PathBuf,
+
+    // Versions which have seen changes recently, and the timer
+    // which ingests them once they have settled
+    pub pending: HashMap<i32, SpawnHandle>,
+
+    // Diagnostics reported through the watcher status
+    pub watch: Option<WatchId>,
+    pub last_event: Option<NaiveDateTime>,
+    pub ingested: usize,
+    pub errors: HashMap<PathBuf, IngestionError>
+}
+
+impl InotifyWatcher {
+    pub fn new() -> Result<Self, Error> {
+        Ok(InotifyWatcher {
+            notifier: Inotify::init()?,
+            watches: HashMap::new(),
+            next_id: 0,
+            recoveries: 0,
+            moves: HashMap::new(),
+            buffer: vec![0u8; 4096]
+        })
+    }
+
+    fn notify(&self, wd: &WatchDescriptor, event: Event) {
+        if let Some(subscription) = self.watches.get(wd) {
+            subscription.recipient.do_send(event).ok();
+        }
+    }
+
+    // Holds on to a MOVED_FROM, so it can be paired with the MOVED_TO carrying the same cookie
+    fn expect_move(&mut self, cookie: u32, wd: WatchDescriptor, path: PathBuf, file_type: FileType, ctx: &mut Context<Self>) {
+        let handle = ctx.run_later(StdDuration::from_millis(MOVE_TIMEOUT_MS), move |act, _| {
+            if let Some(pending) = act.moves.remove(&cookie) {
+                act.notify(&pending.wd, Event {
+                    path: pending.path,
+                    event_type: EventType::Deleted,
+                    file_type: pending.file_type,
+                    from: None
+                });
+            }
+        });
+
+        self.moves.insert(cookie, PendingMove { wd, path, file_type, handle });
+    }
+
+    // Asks the subscriber of the given watch, or all of them if none is given,
+    // to rescan their directory because some of its events have been lost
+    fn rescan(&mut self, wd: Option<&WatchDescriptor>) {
+        self.recoveries += 1;
+
+        for (_, subscription) in self.watches.iter().filter(|(key, _)| wd.map_or(true, |wd| wd == *key)) {
+            warn!("rescanning directory: {}", subscription.path.to_string_lossy());
+
+            subscription.recipient.do_send(Event {
+                path: subscription.path.clone(),
+                event_type: EventType::Rescan,
+                file_type: FileType::Directory,
+                from: None
+            }).ok();
+        }
+    }
+}
+
+// The file descriptor of the inotify instance, as far as the reactor is concerned
+struct InotifyFd(RawFd);
+
+impl Evented for InotifyFd {
+    fn register(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.0).register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        EventedFd(&self.0).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0).deregister(poll)
+    }
+}
+
+// Yields whenever the inotify instance has events to be read. The events themselves are
+// read by the watcher, as a stream of them would have to borrow its buffer for good
+struct InotifyReadiness(PollEvented<InotifyFd>);
+
+impl Stream for InotifyReadiness {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<()>, io::Error> {
+        try_ready!(self.0.poll_read_ready(Ready::readable()));
+
+        // Cleared before the events are read, so any arriving while
+        // they're being handled wake the watcher up once more
+        self.0.clear_read_ready(Ready::readable())?;
+        Ok(Async::Ready(Some(())))
+    }
+}
+
+impl Actor for InotifyWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.listen(ctx);
+    }
+}
+
+impl StreamHandler<(), io::Error> for InotifyWatcher {
+    fn handle(&mut self, _: (), ctx: &mut Self::Context) {
+        self.read_events(ctx);
+    }
+
+    fn error(&mut self, e: io::Error, _: &mut Self::Context) -> Running {
+        error!("failed to wait for inotify events: {}", e);
+        Running::Stop
+    }
+
+    // The instance is registered with the reactor again a little later, rather than
+    // stopping the watcher, and whatever happened in the meantime is found by rescanning
+    fn finished(&mut self, ctx: &mut Self::Context) {
+        ctx.run_later(StdDuration::from_secs(LISTEN_RETRY_SECS), |act, ctx| {
+            act.listen(ctx);
+            act.rescan(None);
+        });
+    }
+}
+
+impl InotifyWatcher {
+    fn listen(&mut self, ctx: &mut Context<Self>) {
+        Self::add_stream(InotifyReadiness(PollEvented::new(InotifyFd(self.notifier.as_raw_fd()))), ctx);
+    }
+
+    fn read_events(&mut self, ctx: &mut Context<Self>) {
+        loop {
+            // The events borrow the buffer, so they are copied out before being handled
+            let events: Vec<EventOwned> = match self.notifier.read_events(&mut self.buffer) {
+                Ok(events) => events.map(|event| EventOwned {
+                    wd: event.wd,
+                    mask: event.mask,
+                    cookie: event.cookie,
+                    name: event.name.map(|name| name.to_os_string())
+                }).collect(),
+                Err(e) => {
+                    error!("failed to read inotify events: {}", e);
+
+                    // Whatever was in the buffer is gone
+                    self.rescan(None);
+                    return;
+                }
+            };
+
+            if events.is_empty() {
+                return;
+            }
+
+            for event in events {
+                self.handle_event(event, ctx);
+            }
+        }
+    }
+
+    fn handle_event(&mut self, event: EventOwned, ctx: &mut Context<Self>) {
+        // The kernel ran out of room for events, and there's no telling
+        // which directories they belonged to
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            warn!("inotify event queue overflowed");
+            self.rescan(None);
+            return;
+        }
+
+        // The kernel has dropped the watch, so nothing more will arrive for it
+        if event.mask.contains(EventMask::IGNORED) {
+            self.watches.remove(&event.wd);
+            return;
+        }
+
+        if event.mask.contains(EventMask::UNMOUNT) {
+            warn!("filesystem backing a watched directory was unmounted");
+            self.rescan(Some(&event.wd));
+            return;
+        }
+
+        let event_type = map_event(event.mask);
+
+        if event_type == EventType::Irrelevant {
+            return;
+        }
+
+        let path = match self.watches.get(&event.wd) {
+            Some(subscription) => match (event.name, event_type) {
+                (Some(name), _) => subscription.path.join(name),
+
+                // Only events concerning the watched directory itself come without a name
+                (None, EventType::SelfDeleted) => subscription.path.clone(),
+                (None, _) => {
+                    warn!("received an event without a name for {}", subscription.path.to_string_lossy());
+                    self.rescan(Some(&event.wd));
+                    return;
+                }
+            },
+            None => return
+        };
+
+        let file_type = map_file_type(event.mask);
+
+        if event.mask.contains(EventMask::MOVED_FROM) {
+            self.expect_move(event.cookie, event.wd, path, file_type, ctx);
+            return;
+        }
+
+        if event.mask.contains(EventMask::MOVED_TO) {
+            if let Some(pending) = self.moves.remove(&event.cookie) {
+                ctx.cancel_future(pending.handle);
+
+                if pending.wd == event.wd {
+                    self.notify(&event.wd, Event {
+                        path,
+                        event_type: EventType::Renamed,
+                        from: Some(pending.path),
+                        file_type
+                    });
+                    return;
+                }
+
+                // Moved between two watched directories, each of which only sees its own half
+                self.notify(&pending.wd, Event {
+                    path: pending.path,
+                    event_type: EventType::Deleted,
+                    file_type: pending.file_type,
+                    from: None
+                });
+            }
+        }
+
+        self.notify(&event.wd, Event {
+            path,
+            event_type,
+            file_type,
+            from: None
+        });
+    }
+}
+
+// Scans every watched directory at a fixed interval, for filesystems
+// on which inotify never fires
+pub struct PollingWatcher {
+    interval: StdDuration,
+    watches: HashMap<PathBuf, PolledDirectory>,
+    next_id: usize
+}
+
+struct PolledDirectory {
+    id: WatchId,
+    recipient: Recipient<Event>,
+    entries: HashMap<PathBuf, EntryState>
+}
+
+impl PollingWatcher {
+    pub fn new(interval: StdDuration) -> Self {
+        PollingWatcher {
+            interval,
+            watches: HashMap::new(),
+            next_id: 0
+        }
+    }
+
+    fn poll(&mut self) {
+        let mut lost = Vec::new();
+
+        for (path, directory) in self.watches.iter_mut() {
+            let entries = match snapshot_directory(path) {
+                Ok(entries) => entries,
+                Err(Error::IoError(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                    directory.recipient.do_send(Event {
+                        path: path.clone(),
+                        event_type: EventType::SelfDeleted,
+                        file_type: FileType::Directory,
+                        from: None
+                    }).ok();
+
+                    // Just like inotify, the watch is dropped along with the directory
+                    lost.push(path.clone());
+                    continue;
+                },
+                Err(e) => {
+                    error!("unable to poll directory {}: {}", path.to_string_lossy(), e);
+                    continue;
+                }
+            };
+
+            for event in diff_snapshots(&directory.entries, &entries) {
+                directory.recipient.do_send(event).ok();
+            }
+
+            directory.entries = entries;
+        }
+
+        for path in lost {
+            self.watches.remove(&path);
+        }
+    }
+}
+
+impl Actor for PollingWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(self.interval, |act, _| act.poll());
+    }
 }
 
 impl ArchiveWatcher {
-    pub fn new(db: Addr<DbExecutor>, certman: Addr<CertificateManager>, dir: PathBuf) -> Self {
+    pub fn new(db: Addr<DbExecutor>, certman: Addr<CertificateManager>, dir: PathBuf, backend: Backend,
+               live: Option<Recipient<GetDirectoryStatus>>) -> Self {
+        let watcher = WatcherHandle::start(backend)
+            .expect("unable to launch directory watcher");
+
         ArchiveWatcher {
             children: HashMap::new(),
+            archive_watch: None,
+            live,
+            rejected: HashMap::new(),
+            watcher,
+            backend,
             dir,
             db: db.clone(),
             certman: certman.clone()
         }
     }
 
-    pub fn watch(&mut self, path: PathBuf) {
-        // Spin up a new DomainWatcher for the directory
-        let watcher = DomainWatcher {
-            db: self.db.clone(),
-            certman: self.certman.clone(),
-            dir: path.clone()
+    pub fn watch(&mut self, path: PathBuf, ctx: &mut Context<Self>) {
+        if self.children.contains_key(&path) || self.rejected.contains_key(&path) {
+            return;
+        }
+
+        let fqdn: String = path.file_name().unwrap().to_string_lossy().into();
+        let db = self.db.clone();
+
+        if let Err(reason) = admit_directory(&fqdn) {
+            self.reject(path, reason);
+            return;
+        }
+
+        self.children.insert(path.clone(), None);
+
+        // Create domain in DB, but ignore if it already exists. In manual mode it has
+        // to exist already instead. The domain has to exist before its watcher can start
+        // adding certificates
+        let registered: Box<dyn Future<Item = bool, Error = Error>> = if *MANUAL_WATCH_MODE {
+            Box::new(self.db.send(GetDomainByFqdn { fqdn: fqdn.clone() }).flatten()
+                .then(|domain| Ok(domain.is_ok())))
+        } else {
+            Box::new(self.db.send(CreateDomain { fqdn: fqdn.clone() }).flatten()
+                .then(|_| Ok(true)))
+        };
+
+        // It might also have been archived if its directory disappeared earlier on, so revive it
+        let domain = registered.and_then(move |registered| {
+            db.send(SetDomainArchived { fqdn, archived: false }).flatten()
+                .then(move |_| Ok(registered))
+        });
+
+        ctx.spawn(domain.into_actor(self).then(move |registered, act, ctx| {
+            // The directory went away again while its domain was being registered
+            match act.children.get(&path) {
+                Some(None) => (),
+                _ => return fut::ok(())
+            }
+
+            if let Ok(false) = registered {
+                act.children.remove(&path);
+                act.reject(path, "not registered".into());
+                return fut::ok(());
+            }
+
+            // Spin up a new DomainWatcher for the directory
+            let watcher = DomainWatcher::new(ctx.address(), act.certman.clone(), act.watcher.clone(), path.clone());
+            act.children.insert(path, Some(watcher.start()));
+            fut::ok(())
+        }));
+    }
+
+    // Brings the set of watched domains in line with what's actually in the archive
+    pub fn rescan(&mut self, ctx: &mut Context<Self>) {
+        let dirs: HashSet<PathBuf> = match scan_directory(&self.dir) {
+            Ok(events) => events.into_iter()
+                .filter(|event| event.file_type == FileType::Directory)
+                .map(|event| event.path)
+                .collect(),
+            Err(e) => {
+                error!("unable to rescan archive {}: {}", self.dir.to_string_lossy(), e);
+                return;
+            }
+        };
+
+        self.rejected.retain(|path, _| dirs.contains(path));
+
+        let lost: Vec<PathBuf> = self.children.keys()
+            .filter(|path| !dirs.contains(*path))
+            .cloned().collect();
+
+        for path in lost {
+            info!("domain removed: {}", path.to_string_lossy());
+            self.unwatch(path);
+        }
+
+        for path in dirs {
+            self.watch(path, ctx);
+        }
+    }
+
+    pub fn unwatch(&mut self, path: PathBuf) {
+        if let Some(Some(child)) = self.children.remove(&path) {
+            child.do_send(StopWatching {});
+        }
+
+        self.archive_domain(&path);
+    }
+
+    fn reject(&mut self, path: PathBuf, reason: String) {
+        info!("not watching directory {}: {}", path.to_string_lossy(), reason);
+        self.rejected.insert(path, reason);
+    }
+
+    // Moves the domain's history over to its new directory name, and watches it there
+    pub fn rename(&mut self, from: PathBuf, path: PathBuf, ctx: &mut Context<Self>) {
+        if self.rejected.remove(&from).is_some() {
+            self.watch(path, ctx);
+            return;
+        }
+
+        if let Some(Some(child)) = self.children.remove(&from) {
+            child.do_send(StopWatching {});
+        }
+
+        let fqdn: String = from.file_name().unwrap().to_string_lossy().into();
+        let new_fqdn: String = path.file_name().unwrap().to_string_lossy().into();
+
+        info!("domain renamed: {} to {}", fqdn, new_fqdn);
+        ctx.spawn(self.db.send(RenameDomain { fqdn, new_fqdn }).flatten().into_actor(self)
+            .then(move |result, act, ctx| {
+                // The new name may already belong to another domain, in which
+                // case the move is handled like a removal followed by a creation
+                if let Err(e) = result {
+                    warn!("unable to rename domain {}: {}", from.to_string_lossy(), e);
+                    act.archive_domain(&from);
+                }
+
+                act.watch(path, ctx);
+                fut::ok(())
+            })
+        );
+    }
+
+    // Keep the domain around as history rather than deleting it,
+    // so its groups survive the directory being recreated later on
+    fn archive_domain(&self, path: &PathBuf) {
+        Arbiter::spawn(self.db.send(SetDomainArchived {
+                fqdn: path.file_name().unwrap().to_string_lossy().into(),
+                archived: true
+            })
+            .map(|_| ())
+            .map_err(|_| ())
+        );
+    }
+
+    fn watch_archive(&mut self, ctx: &mut Context<Self>) {
+        let watch = Watch {
+            path: self.dir.clone(),
+            recipient: ctx.address().recipient()
         };
 
-        self.children.insert(
-            path.clone(), 
-            Arbiter::start(|_| watcher)
+        ctx.spawn(self.watcher.watch.send(watch).flatten().into_actor(self)
+            .then(|result, act, ctx| {
+                match result {
+                    Ok(id) => {
+                        info!("watching archive: {}", act.dir.to_string_lossy());
+                        act.archive_watch = Some(id);
+                    },
+                    Err(e) => {
+                        error!("unable to watch archive {}: {}", act.dir.to_string_lossy(), e);
+                        act.retry_archive(ctx);
+                    }
+                }
+                fut::ok(())
+            })
         );
+    }
+
+    // The archive itself is gone, which more likely means it was unmounted than that
+    // every certificate was revoked, so its domains are stopped without being archived
+    pub fn lose_archive(&mut self, ctx: &mut Context<Self>) {
+        error!("archive disappeared: {}", self.dir.to_string_lossy());
+
+        for (_, child) in self.children.drain() {
+            if let Some(child) = child {
+                child.do_send(StopWatching {});
+            }
+        }
 
-        // Create domain in DB, but ignore if it already exists
-        self.db.send(CreateDomain { 
-            fqdn: path.file_name().unwrap().to_string_lossy().into() 
-        }).flatten().wait().ok();
+        // A moved directory keeps its inotify watch, which would report stale paths
+        if let Some(id) = self.archive_watch.take() {
+            self.watcher.unwatch.do_send(Unwatch { id }).ok();
+        }
+        self.retry_archive(ctx);
+    }
+
+    fn retry_archive(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_later(StdDuration::from_secs(ARCHIVE_RETRY_SECS), |act, ctx| {
+            act.watch_archive(ctx);
+        });
     }
 }
 
 impl Actor for ArchiveWatcher {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
-        let mut watcher = DirectoryWatcher::new(self.dir.clone())
-            .expect("unable to launch archive watcher");
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.watch_archive(ctx);
+    }
+}
 
-        info!("watching archive: {}", self.dir.to_string_lossy());
+// Follows certbot's live/ tree, whose symlinks say which version of each file is the current one
+pub struct LiveWatcher {
+    pub certman: Addr<CertificateManager>,
+    pub watcher: WatcherHandle,
+    pub dir: PathBuf,
 
-        loop {
-            if let Ok(event) = watcher.get_event() {
-                if event.file_type != FileType::Directory {
-                    continue;
-                }
+    // Domain directories, along with their watch once the backend has confirmed it
+    pub domains: HashMap<PathBuf, Option<WatchId>>
+}
+
+impl LiveWatcher {
+    pub fn new(certman: Addr<CertificateManager>, dir: PathBuf, backend: Backend) -> Self {
+        let watcher = WatcherHandle::start(backend)
+            .expect("unable to launch directory watcher");
+
+        LiveWatcher {
+            domains: HashMap::new(),
+            certman,
+            watcher,
+            dir
+        }
+    }
+
+    // Links are reported as Updated when the watch is added, which is when they are first read
+    pub fn watch(&mut self, path: PathBuf, ctx: &mut Context<Self>) {
+        if self.domains.contains_key(&path) {
+            return;
+        }
+
+        self.domains.insert(path.clone(), None);
 
-                if event.event_type == EventType::Updated {
-                    info!("discovered domain: {}", event.path.to_string_lossy());
-                    self.watch(event.path);
-                } else if event.event_type == EventType::Deleted 
-                       && self.children.contains_key(&event.path) {
-                    info!("domain removed: {}", event.path.to_string_lossy());
-                    self.children.remove(&event.path);
+        let watch = Watch {
+            path: path.clone(),
+            recipient: ctx.address().recipient()
+        };
+
+        ctx.spawn(self.watcher.watch.send(watch).flatten().into_actor(self)
+            .then(move |result, act, _| {
+                match result {
+                    Ok(id) => match act.domains.get_mut(&path) {
+                        Some(entry) => *entry = Some(id),
+
+                        // The directory was let go of before the watch was confirmed
+                        None => { act.watcher.unwatch.do_send(Unwatch { id }).ok(); }
+                    },
+                    Err(e) => {
+                        error!("unable to watch live directory {}: {}", path.to_string_lossy(), e);
+                        act.domains.remove(&path);
+                    }
                 }
+                fut::ok(())
+            })
+        );
+    }
+
+    pub fn unwatch(&mut self, path: PathBuf) {
+        let id = match self.domains.remove(&path) {
+            Some(id) => id,
+            None => return
+        };
+
+        if let Some(id) = id {
+            self.watcher.unwatch.do_send(Unwatch { id }).ok();
+        }
+
+        self.certman.do_send(LiveDomainRemoved {
+            fqdn: path.file_name().unwrap().to_string_lossy().into()
+        });
+    }
+
+    pub fn update_link(&mut self, link: PathBuf) {
+        let fqdn: String = match link.parent().and_then(|dir| dir.file_name()) {
+            Some(fqdn) => fqdn.to_string_lossy().into(),
+            None => return
+        };
+
+        Arbiter::spawn(self.certman.send(LiveLinkChanged { fqdn, link: link.clone() }).flatten()
+            .map_err(move |e| info!("ignoring live file {}: {}", link.to_string_lossy(), e))
+        );
+    }
+
+    // Re-reads a directory of the live tree, or the tree itself
+    pub fn rescan(&mut self, path: PathBuf, ctx: &mut Context<Self>) {
+        let events = match scan_directory(&path) {
+            Ok(events) => events,
+            Err(e) => {
+                error!("unable to rescan live directory {}: {}", path.to_string_lossy(), e);
+                return;
             }
+        };
+
+        if path != self.dir {
+            for event in events {
+                self.update_link(event.path);
+            }
+            return;
+        }
+
+        let dirs: HashSet<PathBuf> = events.into_iter()
+            .filter(|event| event.file_type == FileType::Directory)
+            .map(|event| event.path)
+            .collect();
+
+        let lost: Vec<PathBuf> = self.domains.keys()
+            .filter(|path| !dirs.contains(*path))
+            .cloned().collect();
+
+        for path in lost {
+            self.unwatch(path);
+        }
+
+        for path in dirs {
+            self.watch(path, ctx);
         }
     }
 }
 
+impl Actor for LiveWatcher {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let watch = Watch {
+            path: self.dir.clone(),
+            recipient: ctx.address().recipient()
+        };
+
+        ctx.spawn(self.watcher.watch.send(watch).flatten().into_actor(self)
+            .then(|result, act, ctx| {
+                match result {
+                    Ok(_) => info!("watching live links: {}", act.dir.to_string_lossy()),
+                    Err(e) => {
+                        error!("unable to watch live links {}: {}", act.dir.to_string_lossy(), e);
+                        ctx.stop();
+                    }
+                }
+                fut::ok(())
+            })
+        );
+    }
+}
+
 impl DomainWatcher {
-    pub fn discovered_certificate(&mut self, fullpath: PathBuf) {
-        self.certman.send(CertificateDiscovered {
+    pub fn new(archive: Addr<ArchiveWatcher>, certman: Addr<CertificateManager>, watcher: WatcherHandle, dir: PathBuf) -> Self {
+        DomainWatcher {
+            archive,
+            certman,
+            watcher,
+            dir,
+            pending: HashMap::new(),
+            watch: None,
+            last_event: None,
+            ingested: 0,
+            errors: HashMap::new()
+        }
+    }
+
+    pub fn state(&self) -> DomainState {
+        if self.watch.is_none() {
+            DomainState::Starting
+        } else if !self.pending.is_empty() {
+            DomainState::Settling
+        } else {
+            DomainState::Watching
+        }
+    }
+
+    // (Re)starts the settle timer of a version, so it's only
+    // ingested once its files have stopped changing
+    pub fn touch_version(&mut self, version: i32, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.pending.remove(&version) {
+            ctx.cancel_future(handle);
+        }
+
+        let handle = ctx.run_later(*SETTLE_TIMEOUT, move |act, ctx| {
+            act.settle_version(version, ctx);
+        });
+
+        self.pending.insert(version, handle);
+    }
+
+    // Re-ingests every version in the directory, and drops whatever
+    // the database knows about that is no longer on disk
+    pub fn rescan(&mut self, ctx: &mut Context<Self>) {
+        let paths: Vec<PathBuf> = match scan_directory(&self.dir) {
+            Ok(events) => events.into_iter()
+                .filter(|event| event.file_type == FileType::File)
+                .map(|event| event.path)
+                .collect(),
+            Err(e) => {
+                error!("unable to rescan domain {}: {}", self.dir.to_string_lossy(), e);
+                return;
+            }
+        };
+
+        for path in &paths {
+            if let Ok((_, version)) = parse_filename(&path.file_name().unwrap().to_string_lossy()) {
+                self.touch_version(version, ctx);
+            }
+        }
+
+        self.certman.do_send(ReconcileDomain {
             fqdn: self.dir.file_name().unwrap().to_string_lossy().into(),
-            path: fullpath
-        }).wait().ok();
+            paths
+        });
     }
 
-    pub fn lost_certificate(&mut self, fullpath: PathBuf) {
-        self.certman.send(CertificateDisappeared { path: fullpath }).wait().ok();
+    fn settle_version(&mut self, version: i32, ctx: &mut Context<Self>) {
+        self.pending.remove(&version);
+
+        let fqdn: String = self.dir.file_name().unwrap().to_string_lossy().into();
+        let (paths, missing) = match version_paths(&self.dir, version) {
+            Ok(files) => files,
+            Err(e) => {
+                error!("unable to read version {} of {}: {}", version, fqdn, e);
+                return;
+            }
+        };
+
+        // The version has settled without the remaining files turning up, so what is there
+        // is quarantined until they do, which touches the version again
+        if !missing.is_empty() {
+            let error = IngestionError {
+                at: Utc::now().naive_utc(),
+                message: format!("version {} is missing {}", version, missing.join(", "))
+            };
+
+            warn!("version {} of {} is incomplete: {}", version, fqdn, error.message);
+            for path in &paths {
+                self.errors.insert(path.clone(), error.clone());
+            }
+
+            self.certman.do_send(VersionIncomplete { fqdn, version, paths, missing });
+            return;
+        }
+
+        info!("discovered version {} of {}", version, fqdn);
+        ctx.spawn(self.certman.send(VersionDiscovered { fqdn, version, paths: paths.clone() }).flatten().into_actor(self)
+            .then(move |result, act, _| {
+                match result {
+                    Ok(certs) => {
+                        act.ingested += certs.len();
+
+                        for path in &paths {
+                            act.errors.remove(path);
+                        }
+                    },
+                    Err(e) => {
+                        error!("unable to ingest version {} of {}: {}", version, act.dir.to_string_lossy(), e);
+
+                        // The version is ingested as a whole, so there's no telling which of its files was at fault
+                        let error = IngestionError {
+                            at: Utc::now().naive_utc(),
+                            message: e.to_string()
+                        };
+
+                        for path in paths {
+                            act.errors.insert(path, error.clone());
+                        }
+                    }
+                }
+                fut::ok(())
+            })
+        );
     }
 }
 
 impl Actor for DomainWatcher {
     type Context = Context<Self>;
 
-    fn started(&mut self, _ctx: &mut Self::Context) {
-
-        let mut watcher = DirectoryWatcher::new(self.dir.clone())
-            .expect("unable to launch domain watcher");
-
-        info!("watching domain: {}", self.dir.to_string_lossy());
+    fn started(&mut self, ctx: &mut Self::Context) {
+        let watch = Watch {
+            path: self.dir.clone(),
+            recipient: ctx.address().recipient()
+        };
 
-        loop {
-            if let Ok(event) = watcher.get_event() {
-                if event.file_type != FileType::File {
-                    continue;
+        ctx.spawn(self.watcher.watch.send(watch).flatten().into_actor(self)
+            .then(|result, act, ctx| {
+                match result {
+                    Ok(id) => {
+                        info!("watching domain: {}", act.dir.to_string_lossy());
+                        act.watch = Some(id);
+                    },
+                    Err(e) => {
+                        error!("unable to watch domain {}: {}", act.dir.to_string_lossy(), e);
+                        ctx.stop();
+                    }
                 }
+                fut::ok(())
+            })
+        );
+    }
 
-                if event.event_type == EventType::Updated {
-                    info!("discovered certificate: {}", event.path.to_string_lossy());
-                    self.discovered_certificate(event.path);
-                } else if event.event_type == EventType::Deleted {
-                    info!("lost certificate: {}", event.path.to_string_lossy());
-                    self.lost_certificate(event.path);
-                }
-            }
+    fn stopped(&mut self, _: &mut Self::Context) {
+        info!("stopped watching domain: {}", self.dir.to_string_lossy());
+        if let Some(id) = self.watch.take() {
+            self.watcher.unwatch.do_send(Unwatch { id }).ok();
         }
+        self.archive.do_send(DomainWatcherStopped { path: self.dir.clone() });
     }
-}
\ No newline at end of file
+}
@@ -1,36 +1,160 @@
 use std::fs::read_dir;
-use std::path::PathBuf;
-use std::collections::VecDeque;
-use inotify::{Inotify, EventMask, WatchMask};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use std::collections::HashMap;
+use chrono::NaiveDateTime;
+use inotify::EventMask;
+use crate::config::{POLLED_ARCHIVES, POLL_INTERVAL, INCLUDE_DOMAINS, EXCLUDE_DOMAINS};
 use super::errors::Error;
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum FileType {
     Directory,
     File
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy, Debug)]
 pub enum EventType {
     Updated,
     Deleted,
     SelfDeleted,
+
+    // Moved within the same directory, from the path in Event::from
+    Renamed,
+
+    // Events for the directory may have been lost, so it has to be scanned again
+    Rescan,
     Irrelevant
 }
 
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Backend {
+    Inotify,
+    Polling(Duration)
+}
+
+impl Backend {
+    pub fn for_root(root: &Path) -> Self {
+        if POLLED_ARCHIVES.iter().any(|polled| polled == root) {
+            Backend::Polling(*POLL_INTERVAL)
+        } else {
+            Backend::Inotify
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Inotify => "inotify",
+            Backend::Polling(_) => "polling"
+        }
+    }
+}
+
+// Handed out by a backend for every watch it adds, so the watch can be removed again
+// even after its directory was renamed, or its old path was taken by another one
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct WatchId(pub usize);
+
 pub struct Event {
     pub path: PathBuf,
     pub file_type: FileType,
-    pub event_type: EventType
+    pub event_type: EventType,
+    pub from: Option<PathBuf>
 }
 
-pub struct DirectoryWatcher {
-    pub path: PathBuf,
-    notifier: Inotify,
-    events: VecDeque<Event>
+impl actix::Message for Event {
+    type Result = ();
+}
+
+pub struct ArchiveStatus {
+    pub dir: PathBuf,
+    pub backend: Backend,
+    pub domains: Vec<DomainStatus>,
+
+    // Directories which aren't watched, and why
+    pub rejected: Vec<(PathBuf, String)>,
+
+    // Watches held for the archive and the live tree together
+    pub watches: usize,
+
+    // The limit on inotify watches for the user running rublic, if inotify is used at all
+    pub max_watches: Option<usize>,
+    pub recoveries: usize
+}
+
+pub struct DirectoryStatus {
+    pub watches: usize,
+    pub recoveries: usize
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum DomainState {
+    // Waiting for the directory watcher to confirm the watch
+    Starting,
+    Watching,
+
+    // Some versions have changed recently and are waiting to be ingested
+    Settling
+}
+
+impl DomainState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DomainState::Starting => "starting",
+            DomainState::Watching => "watching",
+            DomainState::Settling => "settling"
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IngestionError {
+    pub at: NaiveDateTime,
+    pub message: String
+}
+
+pub struct DomainStatus {
+    pub dir: PathBuf,
+    pub state: DomainState,
+    pub last_event: Option<NaiveDateTime>,
+    pub ingested: usize,
+
+    // The last error of every file which has not been ingested successfully since
+    pub errors: HashMap<PathBuf, IngestionError>
+}
+
+// What a directory entry looked like the last time it was polled
+#[derive(PartialEq, Clone, Copy)]
+pub struct EntryState {
+    pub file_type: FileType,
+    pub modified: Option<SystemTime>,
+    pub len: u64
+}
+
+// Whether a directory of the archive may become a domain, going by its name alone
+pub fn admit_directory(fqdn: &str) -> Result<(), String> {
+    if let Some(include) = &*INCLUDE_DOMAINS {
+        if !include.is_match(fqdn) {
+            return Err(format!("not included by {}", include.as_str()));
+        }
+    }
+
+    if let Some(exclude) = &*EXCLUDE_DOMAINS {
+        if exclude.is_match(fqdn) {
+            return Err(format!("excluded by {}", exclude.as_str()));
+        }
+    }
+
+    Ok(())
 }
 
-fn map_event(mask: EventMask) -> EventType {
+// The most inotify watches a user may hold, across all of their processes
+pub fn max_user_watches() -> Option<usize> {
+    std::fs::read_to_string("/proc/sys/fs/inotify/max_user_watches").ok()
+        .and_then(|limit| limit.trim().parse().ok())
+}
+
+pub fn map_event(mask: EventMask) -> EventType {
     if mask.intersects(EventMask::DELETE_SELF | EventMask::MOVE_SELF) {
         return EventType::SelfDeleted;
     }
@@ -44,87 +168,102 @@ fn map_event(mask: EventMask) -> EventType {
     EventType::Irrelevant
 }
 
-impl DirectoryWatcher {
-    pub fn new(path: PathBuf) -> Result<Self, Error> {
-        let mut notifier = Inotify::init()?;
+pub fn map_file_type(mask: EventMask) -> FileType {
+    if mask.contains(EventMask::ISDIR) {
+        FileType::Directory
+    } else {
+        FileType::File
+    }
+}
 
-        notifier.add_watch(&path, WatchMask::ALL_EVENTS)?;
+// Produces an Updated event for everything already present in the directory,
+// so whatever existed before the watch was added gets picked up as well
+pub fn scan_directory(path: &Path) -> Result<Vec<Event>, Error> {
+    let mut events = Vec::new();
 
-        let mut watcher = DirectoryWatcher {
-            notifier,
-            events: VecDeque::new(),
-            path   
-        };
+    for entry in read_dir(path)? {
+        if let Ok(entry) = entry {
+            if let Ok(file_type) = entry.file_type() {
 
-        watcher.generate_initial_events()?;
-        Ok(watcher)
-    }
+                let file_type = if file_type.is_dir() {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                };
 
-    fn generate_initial_events(&mut self) -> Result<(), Error> {
-        let entries = read_dir(&self.path)?;
-
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Ok(file_type) = entry.file_type() {
-                    
-                    let file_type = if file_type.is_dir() {
-                        FileType::Directory
-                    } else {
-                        FileType::File
-                    };
-
-                    self.events.push_back(Event {
-                        path: entry.path(),
-                        event_type: EventType::Updated,
-                        file_type
-                    });
-                }
+                events.push(Event {
+                    path: entry.path(),
+                    event_type: EventType::Updated,
+                    file_type,
+                    from: None
+                });
             }
         }
-        Ok(())
     }
 
-    fn read_events(&mut self) -> Result<usize, Error> {
-        let mut buffer = [0u8; 4096];
-        let events = self.notifier.read_events_blocking(&mut buffer)?;
+    Ok(events)
+}
+
+// Records the state of everything in the directory, so the next poll can tell what changed
+pub fn snapshot_directory(path: &Path) -> Result<HashMap<PathBuf, EntryState>, Error> {
+    let mut entries = HashMap::new();
 
-        for event in events {
-            let event_type = map_event(event.mask);
+    for entry in read_dir(path)? {
+        if let Ok(entry) = entry {
+            if let Ok(metadata) = entry.metadata() {
 
-            if event_type == EventType::Irrelevant {
-                continue;
+                let file_type = if metadata.is_dir() {
+                    FileType::Directory
+                } else {
+                    FileType::File
+                };
+
+                entries.insert(entry.path(), EntryState {
+                    modified: metadata.modified().ok(),
+                    len: metadata.len(),
+                    file_type
+                });
             }
+        }
+    }
+
+    Ok(entries)
+}
 
-            let name = match event.name {
-                Some(name) => name,
-                None => return Err(Error::NameError)
-            };
+// Synthesises the events inotify would have delivered between two snapshots.
+// Directories only count as updated when they appear, as changes to their
+// contents are reported by whoever watches them
+pub fn diff_snapshots(previous: &HashMap<PathBuf, EntryState>, current: &HashMap<PathBuf, EntryState>) -> Vec<Event> {
+    let mut events = Vec::new();
 
-            let file_type = if event.mask.contains(EventMask::ISDIR) {
-                FileType::Directory
-            } else {
-                FileType::File
-            };
+    for (path, state) in previous {
+        match current.get(path) {
+            Some(current_state) if current_state.file_type == state.file_type => continue,
+            _ => events.push(Event {
+                path: path.clone(),
+                event_type: EventType::Deleted,
+                file_type: state.file_type,
+                from: None
+            })
+        }
+    }
 
-            let mut fullpath = self.path.clone();
-            fullpath.push(name);
+    for (path, state) in current {
+        let updated = match previous.get(path) {
+            Some(previous_state) if previous_state.file_type == state.file_type =>
+                state.file_type == FileType::File && previous_state != state,
+            _ => true
+        };
 
-            self.events.push_back(Event {
-                path: fullpath,
-                event_type,
-                file_type
+        if updated {
+            events.push(Event {
+                path: path.clone(),
+                event_type: EventType::Updated,
+                file_type: state.file_type,
+                from: None
             });
         }
-
-        Ok(self.events.len())
     }
 
-    pub fn get_event(&mut self) -> Result<Event, Error> {
-        if !self.events.is_empty() {
-            Ok(self.events.pop_front().unwrap())
-        } else {
-            while self.read_events()? == 0 { }
-            Ok(self.events.pop_front().unwrap())
-        }        
-    }
-}
\ No newline at end of file
+    events
+}